@@ -45,6 +45,7 @@ export function App() {
 
   // A map for converting pointerId from i32 to u32
   const pointerIdMap = useRef(new Map<number, number>())
+  const viewportRef = useRef<{ width: number; height: number }>()
 
   useEffect(() => {
     if (!roomId) return
@@ -145,18 +146,24 @@ export function App() {
     assert(patchedId !== undefined)
     e.info.pointerId = patchedId
 
-    if (e.info.eventType === 'up' || e.info.eventType === 'cancel') {
+    if (e.info.eventType === 'up' || e.info.eventType === 'cancel' || e.info.eventType === 'leave') {
       map.delete(id)
     }
   }
 
-  function onPointerEvent(eventType: 'up' | 'move' | 'down' | 'cancel', e: PointerEvent) {
+  function onPointerEvent(eventType: 'up' | 'move' | 'down' | 'cancel' | 'leave', e: PointerEvent) {
     const peer = peerRef.current
     if (!peer) return
     if (!videoRef.current) return
 
     e.preventDefault()
     const rect = videoRef.current.getBoundingClientRect()
+    // the host needs the size of the video element to find the letterboxed stream in it
+    const viewport = viewportRef.current
+    if (viewport?.width !== rect.width || viewport?.height !== rect.height) {
+      viewportRef.current = { width: rect.width, height: rect.height }
+      peer.sendObject('viewport', viewportRef.current)
+    }
     const event = MsgpackPointerEvent.fromEvent(eventType, e, rect)
     patchPointerId(event)
     peer.sendObject('pointer', event.serialize())
@@ -209,6 +216,12 @@ export function App() {
               video.addEventListener('pointermove', (e) => onPointerEvent('move', e), false)
               video.addEventListener('pointerup', (e) => onPointerEvent('up', e), false)
               video.addEventListener('pointercancel', (e) => onPointerEvent('cancel', e), false)
+              // a hovering pen going out of range, which is followed by no other event
+              video.addEventListener(
+                'pointerleave',
+                (e) => e.pointerType === 'pen' && e.buttons === 0 && onPointerEvent('leave', e),
+                false,
+              )
             }
           }}
           srcObject={videoStream}
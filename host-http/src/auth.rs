@@ -0,0 +1,152 @@
+// Pairing between the host and its clients, so that only those who know the code can see and control the desktop
+
+use std::{
+  collections::HashMap,
+  fmt,
+  net::{IpAddr, Ipv4Addr},
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+/// Failed attempts allowed from one address within `FAILURE_WINDOW`
+const MAX_FAILURES: u32 = 5;
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+  InvalidToken,
+  TooManyAttempts,
+}
+
+impl fmt::Display for AuthError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AuthError::InvalidToken => write!(f, "invalid token"),
+      AuthError::TooManyAttempts => write!(f, "too many attempts"),
+    }
+  }
+}
+
+struct Failures {
+  count: u32,
+  since: Instant,
+}
+
+pub struct Pairing {
+  token: String,
+  failures: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl Pairing {
+  /// Use the configured secret, or a random 6-digit pairing code
+  pub fn new(token: Option<String>) -> Self {
+    let token = token.unwrap_or_else(|| format!("{:06}", rand::thread_rng().gen_range(0..1_000_000)));
+    Self {
+      token,
+      failures: Mutex::default(),
+    }
+  }
+
+  pub fn token(&self) -> &str {
+    &self.token
+  }
+
+  pub fn verify(&self, addr: Option<IpAddr>, token: &str) -> Result<(), AuthError> {
+    self.verify_at(addr, token, Instant::now())
+  }
+
+  fn verify_at(&self, addr: Option<IpAddr>, token: &str, now: Instant) -> Result<(), AuthError> {
+    let addr = addr.unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+    let mut failures = self.failures.lock().unwrap();
+    failures.retain(|_, failures| now.saturating_duration_since(failures.since) < FAILURE_WINDOW);
+
+    // once limited, even the right token is refused until the window passes
+    if failures
+      .get(&addr)
+      .is_some_and(|failures| failures.count >= MAX_FAILURES)
+    {
+      return Err(AuthError::TooManyAttempts);
+    }
+    if constant_time_eq(token.as_bytes(), self.token.as_bytes()) {
+      failures.remove(&addr);
+      return Ok(());
+    }
+
+    failures
+      .entry(addr)
+      .or_insert_with(|| Failures { count: 0, since: now })
+      .count += 1;
+    Err(AuthError::InvalidToken)
+  }
+}
+
+/// Compare without revealing how many leading bytes match
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn addr(last: u8) -> Option<IpAddr> {
+    Some(Ipv4Addr::new(192, 168, 0, last).into())
+  }
+
+  #[test]
+  fn locks_out_after_too_many_failures() {
+    let pairing = Pairing::new(Some("123456".to_string()));
+    let start = Instant::now();
+    for _ in 0..MAX_FAILURES {
+      assert_eq!(
+        pairing.verify_at(addr(2), "000000", start),
+        Err(AuthError::InvalidToken)
+      );
+    }
+    // even the right token
+    assert_eq!(
+      pairing.verify_at(addr(2), "123456", start + Duration::from_secs(1)),
+      Err(AuthError::TooManyAttempts)
+    );
+    assert_eq!(
+      pairing.verify_at(addr(2), "123456", start + FAILURE_WINDOW - Duration::from_secs(1)),
+      Err(AuthError::TooManyAttempts)
+    );
+  }
+
+  #[test]
+  fn forgets_failures_after_the_window() {
+    let pairing = Pairing::new(Some("123456".to_string()));
+    let start = Instant::now();
+    for _ in 0..MAX_FAILURES {
+      let _ = pairing.verify_at(addr(2), "000000", start);
+    }
+    assert_eq!(pairing.verify_at(addr(2), "123456", start + FAILURE_WINDOW), Ok(()));
+
+    // failures spread over more than a window do not add up
+    for attempt in 0..MAX_FAILURES as u64 * 2 {
+      let now = start + FAILURE_WINDOW + Duration::from_secs(attempt * 20);
+      assert_eq!(pairing.verify_at(addr(2), "000000", now), Err(AuthError::InvalidToken));
+    }
+  }
+
+  #[test]
+  fn counts_failures_per_address() {
+    let pairing = Pairing::new(Some("123456".to_string()));
+    let start = Instant::now();
+    for _ in 0..MAX_FAILURES {
+      let _ = pairing.verify_at(addr(2), "000000", start);
+    }
+    assert_eq!(
+      pairing.verify_at(addr(3), "000000", start),
+      Err(AuthError::InvalidToken)
+    );
+    assert_eq!(pairing.verify_at(addr(3), "123456", start), Ok(()));
+    assert_eq!(
+      pairing.verify_at(addr(2), "123456", start),
+      Err(AuthError::TooManyAttempts)
+    );
+  }
+}
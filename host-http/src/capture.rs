@@ -0,0 +1,230 @@
+// Screen capture sources, expressed as ffmpeg input arguments
+//
+// https://ffmpeg.org/ffmpeg-devices.html
+// https://ffmpeg.org/ffmpeg-filters.html#ddagrab
+
+use std::{fmt, str::FromStr};
+
+use clap::ValueEnum;
+use input::Rect;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+  /// Windows GDI (any Windows version)
+  Gdigrab,
+  /// Windows Desktop Duplication API (Windows 8 and later)
+  Ddagrab,
+  /// X11 display
+  X11grab,
+  /// Linux DRM/KMS framebuffer (requires CAP_SYS_ADMIN)
+  Kmsgrab,
+  /// PipeWire screencast through xdg-desktop-portal (Wayland)
+  Pipewire,
+  /// Synthetic test pattern, which needs no display at all
+  Testsrc,
+}
+
+impl Source {
+  pub fn platform_default() -> Self {
+    if cfg!(windows) {
+      Source::Gdigrab
+    } else if cfg!(target_os = "linux") {
+      Source::X11grab
+    } else {
+      Source::Testsrc
+    }
+  }
+
+  /// Whether the captured region can be anywhere on the virtual desktop
+  pub fn captures_any_region(&self) -> bool {
+    // ddagrab offsets are relative to one output, and the portal picks what pipewiregrab captures
+    !matches!(self, Source::Ddagrab | Source::Pipewire)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+  pub width: u32,
+  pub height: u32,
+}
+
+impl FromStr for Size {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (width, height) = s
+      .split_once('x')
+      .ok_or_else(|| format!("expected WIDTHxHEIGHT: {}", s))?;
+    let width = width.parse().map_err(|_| format!("invalid width: {}", width))?;
+    let height = height.parse().map_err(|_| format!("invalid height: {}", height))?;
+    Ok(Size { width, height })
+  }
+}
+
+impl fmt::Display for Size {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}x{}", self.width, self.height)
+  }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Capture {
+  /// Screen capture source
+  #[arg(long = "capture", value_enum, default_value_t = Source::platform_default())]
+  pub source: Source,
+
+  /// Size of the captured region
+  #[arg(long, default_value = "1920x1080")]
+  pub video_size: Size,
+
+  /// Left edge of the captured region
+  #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+  pub offset_x: i32,
+
+  /// Top edge of the captured region
+  #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+  pub offset_y: i32,
+
+  #[arg(long, default_value_t = 30)]
+  pub framerate: u32,
+
+  /// X11 display to capture (x11grab)
+  #[arg(long, default_value = ":0.0")]
+  pub display: String,
+
+  /// DRM device to capture (kmsgrab)
+  #[arg(long, default_value = "/dev/dri/card0")]
+  pub drm_device: String,
+
+  /// Index of the output to capture (ddagrab)
+  #[arg(long, default_value_t = 0)]
+  pub output_idx: u32,
+
+  /// PipeWire node to capture, instead of asking through the portal (pipewire)
+  #[arg(long)]
+  pub pipewire_node: Option<u32>,
+}
+
+/// Arguments to pass to ffmpeg before the output options
+pub struct CaptureInput {
+  pub args: Vec<String>,
+  /// Filters that have to run first, e.g. to download hardware frames
+  pub filters: Vec<String>,
+}
+
+impl Capture {
+  /// Captured region of the virtual desktop
+  pub fn region(&self) -> Rect {
+    Rect {
+      x: self.offset_x,
+      y: self.offset_y,
+      width: self.video_size.width,
+      height: self.video_size.height,
+    }
+  }
+
+  /// The same capture of another region of the virtual desktop
+  pub fn with_region(&self, region: Rect) -> Capture {
+    Capture {
+      video_size: Size {
+        width: region.width,
+        height: region.height,
+      },
+      offset_x: region.x,
+      offset_y: region.y,
+      ..self.clone()
+    }
+  }
+
+  pub fn input(&self) -> CaptureInput {
+    let Capture {
+      video_size: size,
+      offset_x: x,
+      offset_y: y,
+      framerate: fps,
+      ..
+    } = self;
+
+    let (args, filters): (Vec<String>, Vec<String>) = match self.source {
+      Source::Gdigrab => (
+        vec![
+          "-f".into(),
+          "gdigrab".into(),
+          "-framerate".into(),
+          fps.to_string(),
+          "-offset_x".into(),
+          x.to_string(),
+          "-offset_y".into(),
+          y.to_string(),
+          "-video_size".into(),
+          size.to_string(),
+          "-i".into(),
+          "desktop".into(),
+        ],
+        vec![],
+      ),
+      Source::Ddagrab => (
+        vec![
+          "-f".into(),
+          "lavfi".into(),
+          "-i".into(),
+          format!(
+            "ddagrab=output_idx={}:framerate={}:video_size={}:offset_x={}:offset_y={}",
+            self.output_idx, fps, size, x, y
+          ),
+        ],
+        vec!["hwdownload".into(), "format=bgra".into()],
+      ),
+      Source::X11grab => (
+        vec![
+          "-f".into(),
+          "x11grab".into(),
+          "-framerate".into(),
+          fps.to_string(),
+          "-video_size".into(),
+          size.to_string(),
+          "-i".into(),
+          format!("{}+{},{}", self.display, x, y),
+        ],
+        vec![],
+      ),
+      Source::Kmsgrab => (
+        vec![
+          "-device".into(),
+          self.drm_device.clone(),
+          "-f".into(),
+          "kmsgrab".into(),
+          "-framerate".into(),
+          fps.to_string(),
+          "-i".into(),
+          "-".into(),
+        ],
+        vec![
+          "hwdownload".into(),
+          "format=bgr0".into(),
+          format!("crop={}:{}:{}:{}", size.width, size.height, x, y),
+        ],
+      ),
+      Source::Pipewire => {
+        let mut source = format!("pipewiregrab=framerate={}:enable_dmabuf=0", fps);
+        if let Some(node) = self.pipewire_node {
+          source += &format!(":pipewire_node={}", node);
+        }
+        (vec!["-f".into(), "lavfi".into(), "-i".into(), source], vec![])
+      }
+      Source::Testsrc => (
+        vec![
+          // lavfi sources run as fast as possible unless read at the native rate
+          "-re".into(),
+          "-f".into(),
+          "lavfi".into(),
+          "-i".into(),
+          format!("testsrc=size={}:rate={}", size, fps),
+        ],
+        vec![],
+      ),
+    };
+
+    CaptureInput { args, filters }
+  }
+}
@@ -0,0 +1,48 @@
+// Where the host keeps its files between runs
+
+use std::{
+  collections::BTreeMap,
+  env, fs,
+  io::{self, ErrorKind},
+  path::PathBuf,
+};
+
+use input::PressureCurve;
+
+const PRESSURE_PROFILES: &str = "pressure.json";
+
+/// `%APPDATA%\remote-stylus` on Windows, and `$XDG_CONFIG_HOME/remote-stylus` or `~/.config/remote-stylus` elsewhere
+pub fn config_dir() -> PathBuf {
+  let var = |name| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+  let base = if cfg!(windows) {
+    var("APPDATA")
+  } else {
+    var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+  };
+  base.unwrap_or_else(|| PathBuf::from(".")).join("remote-stylus")
+}
+
+/// Pressure curves by name from `pressure.json` in the config directory, or none if there is no such file
+pub fn pressure_profiles() -> io::Result<BTreeMap<String, PressureCurve>> {
+  let data = match fs::read(config_dir().join(PRESSURE_PROFILES)) {
+    Ok(data) => data,
+    Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+    Err(e) => return Err(e),
+  };
+  let profiles: BTreeMap<String, PressureCurve> =
+    serde_json::from_slice(&data).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+  for (name, curve) in &profiles {
+    curve
+      .validate()
+      .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("pressure profile {}: {}", name, e)))?;
+  }
+  Ok(profiles)
+}
+
+/// Write the pressure curves to `pressure.json` in the config directory
+pub fn save_pressure_profiles(profiles: &BTreeMap<String, PressureCurve>) -> io::Result<()> {
+  let dir = config_dir();
+  fs::create_dir_all(&dir)?;
+  let data = serde_json::to_vec_pretty(profiles).map_err(io::Error::other)?;
+  fs::write(dir.join(PRESSURE_PROFILES), data)
+}
@@ -0,0 +1,169 @@
+// Encoder profiles, expressed as ffmpeg output arguments
+//
+// https://www.webmproject.org/docs/encoder-parameters/
+// https://trac.ffmpeg.org/wiki/Encode/H.264
+// https://developer.mozilla.org/en-US/docs/Web/Media/Formats/codecs_parameter
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  capture::{Capture, Size, Source},
+  slicer::Container,
+};
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+  /// VP8 (libvpx)
+  Vp8,
+  /// VP9 (libvpx-vp9)
+  Vp9,
+  /// AV1 (libaom-av1)
+  Av1,
+  /// H.264 constrained baseline (libx264)
+  H264,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Encoder {
+  #[arg(long, value_enum, default_value_t = Codec::Vp8)]
+  pub codec: Codec,
+
+  /// Target bitrate in kbit/s
+  #[arg(long, default_value_t = 4000)]
+  pub bitrate: u32,
+
+  /// Keyframe interval in frames
+  #[arg(long, default_value_t = 60)]
+  pub gop: u32,
+
+  /// Output frame rate [default: the capture frame rate]
+  #[arg(long)]
+  pub fps: Option<u32>,
+
+  /// Scale the stream to this size, e.g. 1280x720 [default: the captured size]
+  #[arg(long)]
+  pub scale: Option<Size>,
+}
+
+/// What the client needs to know to decode the stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+  pub mime: String,
+  /// Size of the frames, unknown if the capture source picks it
+  pub size: Option<Size>,
+}
+
+impl Encoder {
+  pub fn container(&self) -> Container {
+    match self.codec {
+      // iPad Safari plays H.264 only from MP4
+      Codec::H264 => Container::Mp4,
+      _ => Container::Webm,
+    }
+  }
+
+  pub fn output_size(&self, capture: &Capture) -> Size {
+    let size = self.scale.unwrap_or(capture.video_size);
+    // 4:2:0 chroma subsampling needs even dimensions
+    Size {
+      width: size.width & !1,
+      height: size.height & !1,
+    }
+  }
+
+  pub fn stream_info(&self, capture: &Capture) -> StreamInfo {
+    // the portal picks what pipewiregrab captures, so only a scaled stream has a size known up front
+    let size = match (capture.source, self.scale) {
+      (Source::Pipewire, None) => None,
+      _ => Some(self.output_size(capture)),
+    };
+    // pick the codec level from the resolution: up to 720p, up to 1080p, or larger
+    let tier = match size.map(|size| size.width * size.height) {
+      Some(0..=921_600) => 0,
+      Some(921_601..=2_088_960) => 1,
+      _ => 2,
+    };
+
+    let mime = match self.codec {
+      Codec::Vp8 => r#"video/webm; codecs="vp8""#.to_string(),
+      Codec::Vp9 => format!(r#"video/webm; codecs="vp09.00.{}.08""#, ["31", "41", "51"][tier]),
+      Codec::Av1 => format!(r#"video/webm; codecs="av01.0.{}M.08""#, ["05", "08", "12"][tier]),
+      Codec::H264 => format!(r#"video/mp4; codecs="avc1.42E0{}""#, ["1F", "28", "32"][tier]),
+    };
+
+    StreamInfo { mime, size }
+  }
+
+  /// Full ffmpeg command line for capturing and encoding the stream to stdout
+  pub fn command_args(&self, capture: &Capture) -> Vec<String> {
+    let input = capture.input();
+    let size = self.output_size(capture);
+    let fps = self.fps.unwrap_or(capture.framerate);
+
+    let mut args = input.args;
+
+    let mut filters = input.filters;
+    // the size of pipewire captures is not known, so they are always scaled if asked to
+    let pipewire_scaled = capture.source == Source::Pipewire && self.scale.is_some();
+    if size != capture.video_size || pipewire_scaled {
+      filters.push(format!("scale={}:{}", size.width, size.height));
+    }
+    if !filters.is_empty() {
+      args.extend(["-vf".into(), filters.join(",")]);
+    }
+
+    let bitrate = format!("{}k", self.bitrate);
+    let codec_args: &[&str] = match self.codec {
+      Codec::Vp8 => &["-c:v", "libvpx", "-deadline", "realtime", "-cpu-used", "8"],
+      Codec::Vp9 => &[
+        "-c:v",
+        "libvpx-vp9",
+        "-deadline",
+        "realtime",
+        "-cpu-used",
+        "8",
+        "-row-mt",
+        "1",
+      ],
+      Codec::Av1 => &["-c:v", "libaom-av1", "-usage", "realtime", "-cpu-used", "8"],
+      Codec::H264 => &[
+        "-c:v",
+        "libx264",
+        "-preset",
+        "ultrafast",
+        "-tune",
+        "zerolatency",
+        "-profile:v",
+        "baseline",
+      ],
+    };
+    args.extend(codec_args.iter().map(|arg| arg.to_string()));
+
+    args.extend([
+      "-b:v".into(),
+      bitrate,
+      "-g".into(),
+      self.gop.to_string(),
+      "-r".into(),
+      fps.to_string(),
+      "-pix_fmt".into(),
+      "yuv420p".into(),
+    ]);
+
+    match self.container() {
+      Container::Webm => args.extend(["-f".into(), "webm".into()]),
+      // fragments start at keyframes, like the clusters of WebM
+      Container::Mp4 => args.extend([
+        "-f".into(),
+        "mp4".into(),
+        "-movflags".into(),
+        "empty_moov+default_base_moof+frag_keyframe".into(),
+      ]),
+    }
+
+    args.push("-".into());
+    args
+  }
+}
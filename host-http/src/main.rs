@@ -1,14 +1,23 @@
-use std::{net::IpAddr, process::Stdio};
+use std::{
+  collections::BTreeMap,
+  net::{IpAddr, SocketAddr},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  time::Instant,
+};
 
 use clap::Parser;
 use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use input::{
+  Acceleration, Area, Aspect, Barrel, Calibration, Chord, GestureKeys, InputDevices, Layout, Mapping, Monitor,
+  PalmSize, PointerEventType, PointerType, PressureCurve, Rect, Rotation, StrokeLevel,
+};
 use rust_embed::Embed;
-use serde::{Deserialize, Serialize};
 use tokio::{
-  io::AsyncReadExt,
-  process::Command,
   select,
-  sync::{mpsc, watch},
+  sync::{mpsc, watch, Mutex},
 };
 use warp::{
   filters::ws::{Message, WebSocket, Ws},
@@ -18,6 +27,27 @@ use warp::{
   Filter, Rejection, Reply,
 };
 
+use crate::{
+  auth::{AuthError, Pairing},
+  capture::Capture,
+  encoder::Encoder,
+  protocol::{
+    Capabilities, ClientMessage, Close, Feature, HostMessage, Preferences, TouchMode, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+  },
+  stream::{Streams, Subscription},
+  tls::Tls,
+};
+
+mod auth;
+mod capture;
+mod config;
+mod encoder;
+mod protocol;
+mod slicer;
+mod stream;
+mod tls;
+
 #[derive(Embed)]
 #[folder = "web/"]
 struct Asset;
@@ -30,6 +60,92 @@ struct Args {
 
   #[arg(long, default_value_t = 8080)]
   port: u16,
+
+  /// Secret that clients must send to connect [default: a random pairing code for each run]
+  #[arg(long)]
+  token: Option<String>,
+
+  /// Keyboard layout of this host (us, jis), used for keys without a physical key code
+  #[arg(long, default_value = "us")]
+  keyboard_layout: Layout,
+
+  /// What the barrel button of the pen does (button, right-click)
+  #[arg(long, default_value = "button")]
+  barrel: Barrel,
+
+  /// Pen pressure at which clicks start when pens are injected as the mouse
+  #[arg(long, default_value_t = 0.1)]
+  click_pressure: f64,
+
+  /// Gain of slow finger movements when touches are used as a trackpad
+  #[arg(long, default_value_t = 1.0)]
+  trackpad_sensitivity: f64,
+
+  /// Additional gain of the trackpad per desktop per second of finger speed
+  #[arg(long, default_value_t = 1.0)]
+  trackpad_acceleration: f64,
+
+  /// Width from which a touch is rejected as a palm, in client pixels
+  #[arg(long)]
+  palm_width: Option<f64>,
+
+  /// Height from which a touch is rejected as a palm, in client pixels
+  #[arg(long)]
+  palm_height: Option<f64>,
+
+  /// How the stream is fitted into the client view (stretch, letterbox, crop)
+  #[arg(long, default_value = "letterbox")]
+  aspect: Aspect,
+
+  /// Fixed region of the desktop that the client surface controls, like WIDTHxHEIGHT+X+Y [default: the stream]
+  #[arg(long, allow_hyphen_values = true)]
+  area: Option<Rect>,
+
+  /// Keep the proportions of the area by leaving a band of the client surface unused
+  #[arg(long)]
+  lock_aspect: bool,
+
+  /// Clockwise turn of the client relative to the desktop (0, 90, 180, 270), e.g. 180 for left-handed use
+  #[arg(long, default_value = "0")]
+  rotation: Rotation,
+
+  /// Keys that rotating two fingers clockwise presses for every 15 degrees in gesture mode, like ctrl+]
+  #[arg(long)]
+  rotate_clockwise: Option<Chord>,
+
+  /// Keys that rotating two fingers counterclockwise presses for every 15 degrees in gesture mode
+  #[arg(long)]
+  rotate_counterclockwise: Option<Chord>,
+
+  /// Keys that a three-finger tap presses in gesture mode
+  #[arg(long, default_value = "ctrl+z")]
+  three_finger_tap: Chord,
+
+  #[command(flatten)]
+  capture: Capture,
+
+  #[command(flatten)]
+  encoder: Encoder,
+
+  #[command(flatten)]
+  tls: Tls,
+}
+
+/// State shared by all the sessions
+struct Host {
+  args: Args,
+  /// Whether input can be injected, which every session does through input devices of its own, so that one
+  /// leaving or changing its preferences cannot release what another one holds
+  input: bool,
+  /// Bounding box of all monitors, which pointer positions are normalized to
+  desktop: Rect,
+  /// Monitors that sessions can switch to, empty if the capture source cannot follow
+  monitors: Vec<Monitor>,
+  /// Pressure curves by name, which calibrations add to, and which every session is told about
+  pressure_profiles: watch::Sender<BTreeMap<String, PressureCurve>>,
+  streams: Streams,
+  pairing: Pairing,
+  next_session_id: AtomicU64,
 }
 
 #[tokio::main]
@@ -39,14 +155,92 @@ async fn main() {
   let index_html = warp::path::end().and_then(|| async { serve_asset("index.html") });
   let assets = warp::path::tail().and_then(|path: Tail| async move { serve_asset(path.as_str()) });
 
+  // each session creates its own devices, so this only checks that it will be able to
+  let input = match input::probe_platform() {
+    Ok(()) => true,
+    Err(e) => {
+      eprintln!("Input is disabled: {}", e);
+      false
+    }
+  };
+
+  let monitors = input::monitors().unwrap_or_else(|e| {
+    eprintln!("Failed to enumerate monitors: {}", e);
+    vec![]
+  });
+  let desktop = input::virtual_desktop(&monitors).unwrap_or_else(|| {
+    eprintln!("Assuming that the desktop is the captured region");
+    args.capture.region()
+  });
+  let monitors = if args.capture.source.captures_any_region() {
+    monitors
+  } else {
+    eprintln!("Monitor selection is disabled for {:?}", args.capture.source);
+    vec![]
+  };
+
+  let pressure_profiles = config::pressure_profiles().unwrap_or_else(|e| {
+    eprintln!("Failed to load pressure profiles: {}", e);
+    BTreeMap::new()
+  });
+
+  let pairing = Pairing::new(args.token.clone());
+  let (host, port) = (args.host, args.port);
+
+  let identity = match args.tls.enabled().then(|| args.tls.identity(host)).transpose() {
+    Ok(identity) => identity,
+    Err(e) => {
+      eprintln!("Failed to set up TLS: {}", e);
+      return;
+    }
+  };
+  let scheme = if identity.is_some() { "https" } else { "http" };
+
+  println!("Listening on {}://{}:{}", scheme, host, port);
+  if let Some(identity) = &identity {
+    println!("Certificate fingerprint (SHA-256): {}", identity.fingerprint);
+  }
+  if args.token.is_none() {
+    println!(
+      "Pairing code: {} (or open {}://{}:{}/#token={})",
+      pairing.token(),
+      scheme,
+      host,
+      port,
+      pairing.token()
+    );
+  }
+
+  let state = Arc::new(Host {
+    args,
+    input,
+    desktop,
+    monitors,
+    pressure_profiles: watch::Sender::new(pressure_profiles),
+    streams: Streams::default(),
+    pairing,
+    next_session_id: AtomicU64::new(1),
+  });
+
   let websocket = warp::path("ws")
     .and(warp::ws())
-    .map(|ws: Ws| ws.on_upgrade(handle_websocket));
+    .and(warp::addr::remote())
+    .and(warp::any().map(move || state.clone()))
+    .map(|ws: Ws, addr: Option<SocketAddr>, state| ws.on_upgrade(move |ws| handle_websocket(ws, addr, state)));
 
   let routes = index_html.or(assets).or(websocket);
 
-  println!("Listening on http://{}:{}", args.host, args.port);
-  warp::serve(routes).run((args.host, args.port)).await;
+  match identity {
+    Some(identity) => {
+      warp::serve(routes)
+        .tls()
+        .cert_path(identity.cert)
+        .key_path(identity.key)
+        .run((host, port))
+        .await
+    }
+    None => warp::serve(routes).run((host, port)).await,
+  }
 }
 
 fn serve_asset(path: &str) -> Result<impl Reply, Rejection> {
@@ -60,70 +254,322 @@ fn serve_asset(path: &str) -> Result<impl Reply, Rejection> {
   Ok(res)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type", rename_all = "lowercase")]
-enum ClientMessage {
-  Hello,
-  Key { key: String },
+#[derive(Debug, Clone)]
+enum Stage {
+  Initial,
+  Greeted(Arc<Session>),
+  /// With the close frame to send, if the host is the one closing the connection
+  Closed(Option<Close>),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type", rename_all = "lowercase")]
-enum HostMessage {
-  Video {
-    #[serde(with = "serde_bytes")]
-    data: Vec<u8>,
-  },
+/// What was agreed on in the greeting
+#[derive(Debug)]
+struct Session {
+  id: u64,
+  version: u32,
+  features: Vec<Feature>,
+  max_touch_points: u32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Stage {
-  Initial,
-  Greeted,
-  Closed,
+impl Host {
+  /// Input that this host can inject
+  fn features(&self) -> Vec<Feature> {
+    match self.input {
+      true => vec![Feature::Pen, Feature::Touch, Feature::Mouse, Feature::Keyboard],
+      false => vec![],
+    }
+  }
+
+  /// Input devices for a new session, if input is enabled
+  fn devices(&self) -> Option<Arc<Mutex<InputDevices>>> {
+    if !self.input {
+      return None;
+    }
+    let backend = input::platform_backend()
+      .inspect_err(|e| eprintln!("Failed to create input devices: {}", e))
+      .ok()?;
+    let args = &self.args;
+    let devices = InputDevices::new(backend)
+      .with_layout(args.keyboard_layout)
+      .with_barrel(args.barrel)
+      .with_click_pressure(args.click_pressure)
+      .with_acceleration(Acceleration {
+        sensitivity: args.trackpad_sensitivity,
+        acceleration: args.trackpad_acceleration,
+      })
+      .with_gesture_keys(GestureKeys {
+        rotate_clockwise: args.rotate_clockwise.clone(),
+        rotate_counterclockwise: args.rotate_counterclockwise.clone(),
+        three_finger_tap: Some(args.three_finger_tap.clone()),
+      });
+    Some(Arc::new(Mutex::new(devices)))
+  }
+
+  /// Capture of one of the monitors, or of the region given on the command line
+  fn capture(&self, monitor: Option<usize>) -> Capture {
+    match monitor.and_then(|index| self.monitors.get(index)) {
+      Some(monitor) => self.args.capture.with_region(monitor.rect),
+      None => self.args.capture.clone(),
+    }
+  }
+
+  fn greet(&self, version: u32, capabilities: &Capabilities) -> Result<Session, Close> {
+    if version < MIN_PROTOCOL_VERSION {
+      return Err(Close::INCOMPATIBLE_VERSION);
+    }
+    if !capabilities.codecs.contains(&self.args.encoder.codec) {
+      return Err(Close::UNSUPPORTED_CODEC);
+    }
+
+    let features = self
+      .features()
+      .into_iter()
+      .filter(|feature| capabilities.input.contains(feature))
+      .collect();
+    Ok(Session {
+      id: self.next_session_id.fetch_add(1, Ordering::Relaxed),
+      version: version.min(PROTOCOL_VERSION),
+      features,
+      max_touch_points: capabilities.max_touch_points.min(input::MAX_CONTACTS as u32),
+    })
+  }
 }
 
-async fn handle_websocket(ws: WebSocket) {
+async fn handle_websocket(ws: WebSocket, addr: Option<SocketAddr>, state: Arc<Host>) {
   let (mut tx, mut rx) = ws.split();
   let (tx_stage, rx_stage) = watch::channel(Stage::Initial);
+  let (tx_monitor, mut rx_monitor) = watch::channel(None);
+  // replies to the client, which only the video task can send
+  let (tx_reply, mut rx_reply) = mpsc::unbounded_channel();
 
   // handle incoming messages
   tokio::spawn({
-    let rx_stage = rx_stage.clone();
+    let state = state.clone();
     async move {
-      while let Some(msg) = rx.next().await {
+      let mut devices: Option<Arc<Mutex<InputDevices>>> = None;
+      let mut session: Option<Arc<Session>> = None;
+      let mut preferences = Preferences::default();
+      let mut mapping = Mapping {
+        aspect: state.args.aspect,
+        area: Area {
+          rect: state.args.area,
+          lock_aspect: state.args.lock_aspect,
+          rotation: state.args.rotation,
+        },
+        ..Mapping::new(state.desktop, state.args.capture.region())
+      };
+      let mut calibration = Calibration::new();
+      let mut calibrating: Option<StrokeLevel> = None;
+      let palm_size = PalmSize {
+        width: state.args.palm_width,
+        height: state.args.palm_height,
+      };
+      // input that the devices hold back until a timeout, like a tap that may still turn into a drag
+      let mut deadline: Option<Instant> = None;
+      let expiry = tokio::time::sleep_until(Instant::now().into());
+      tokio::pin!(expiry);
+      let close = loop {
+        let msg = select! {
+          msg = rx.next() => msg,
+          () = &mut expiry, if deadline.is_some() => {
+            let Some(devices) = &devices else {
+              deadline = None;
+              continue;
+            };
+            let mut locked = devices.lock().await;
+            if let Err(e) = locked.expire() {
+              eprintln!("Failed to inject pointer event: {}", e);
+            }
+            deadline = locked.deadline();
+            if let Some(deadline) = deadline {
+              expiry.as_mut().reset(deadline.into());
+            }
+            continue;
+          }
+        };
+        let Some(msg) = msg else {
+          break None;
+        };
         let Ok(msg) = msg else {
           eprintln!("Failed to receive message");
-          break;
+          break None;
         };
+        if msg.is_close() {
+          break None;
+        }
         let Ok(msg) = rmp_serde::from_slice::<ClientMessage>(msg.as_bytes()) else {
           eprintln!("Failed to decode message");
-          break;
+          break Some(Close::INVALID_PAYLOAD);
         };
 
         // initial message must be greeting
-        if *rx_stage.borrow() == Stage::Initial {
-          if let ClientMessage::Hello = msg {
-            let _ = tx_stage.send(Stage::Greeted);
-            continue;
-          } else {
+        let Some(session) = &session else {
+          let ClientMessage::Hello {
+            token,
+            version,
+            capabilities,
+          } = msg
+          else {
             eprintln!("Unexpected message");
-            break;
+            break Some(Close::PROTOCOL_ERROR);
+          };
+          if let Err(e) = state.pairing.verify(addr.map(|addr| addr.ip()), &token) {
+            eprintln!("Rejected a client from {:?}: {}", addr, e);
+            break Some(match e {
+              AuthError::InvalidToken => Close::INVALID_TOKEN,
+              AuthError::TooManyAttempts => Close::TOO_MANY_ATTEMPTS,
+            });
           }
-        }
+          match state.greet(version, &capabilities) {
+            Ok(greeted) => {
+              println!(
+                "Session {} from {:?}: protocol {}, screen {:?}",
+                greeted.id, addr, greeted.version, capabilities.screen
+              );
+              let greeted = Arc::new(greeted);
+              devices = state.devices();
+              session = Some(greeted.clone());
+              let _ = tx_stage.send(Stage::Greeted(greeted));
+              continue;
+            }
+            Err(close) => {
+              eprintln!("Rejected a client from {:?}: {}", addr, close.reason);
+              break Some(close);
+            }
+          }
+        };
 
         // handle subsequent messages
         match msg {
-          ClientMessage::Hello => {
-            panic!("Unexpected message");
+          ClientMessage::Hello { .. } => {
+            eprintln!("Unexpected message");
+            break Some(Close::PROTOCOL_ERROR);
+          }
+          ClientMessage::Key(event) => {
+            let (Some(devices), true) = (&devices, session.features.contains(&Feature::Keyboard)) else {
+              continue;
+            };
+            if let Err(e) = devices.lock().await.key(&event) {
+              eprintln!("Failed to inject key event: {}", e);
+            }
+          }
+          ClientMessage::Pointer(event) => {
+            // strokes are only measured while calibrating, so that they do not draw on the host
+            if let (Some(level), PointerType::Pen) = (calibrating, event.pointer_type) {
+              let touching = matches!(event.event_type, PointerEventType::Down | PointerEventType::Move);
+              if touching && !event.buttons.is_empty() {
+                calibration.record(level, event.pressure);
+              }
+              continue;
+            }
+            let feature = match event.pointer_type {
+              PointerType::Pen => Feature::Pen,
+              PointerType::Touch => Feature::Touch,
+              PointerType::Mouse => Feature::Mouse,
+            };
+            let (Some(devices), true) = (&devices, session.features.contains(&feature)) else {
+              continue;
+            };
+            let event = mapping.apply(&palm_size.reject(event));
+            let mut locked = devices.lock().await;
+            let injected = match (event.pointer_type, preferences.touch_mode, preferences.emulate_mouse) {
+              (PointerType::Touch, TouchMode::Trackpad, _) => locked.trackpad(event),
+              (PointerType::Touch, TouchMode::Gestures, _) => locked.gestures(event),
+              (PointerType::Pen | PointerType::Touch, _, true) => locked.emulate_mouse(event),
+              _ => locked.inject(event),
+            };
+            if let Err(e) = injected {
+              eprintln!("Failed to inject pointer event: {}", e);
+            }
+            deadline = locked.deadline();
+            if let Some(deadline) = deadline {
+              expiry.as_mut().reset(deadline.into());
+            }
+          }
+          ClientMessage::Viewport { width, height } => {
+            mapping.view = Some((width, height));
+          }
+          ClientMessage::SelectMonitor { index } => {
+            let Some(monitor) = state.monitors.get(index) else {
+              eprintln!("No monitor {}", index);
+              continue;
+            };
+            // the stream follows in the other task, without dropping the connection
+            mapping.target = monitor.rect;
+            let _ = tx_monitor.send(Some(index));
+          }
+          ClientMessage::Preferences(changed) => {
+            // nothing may stay pressed on the devices that are no longer used
+            if let (true, Some(devices)) = (changed != preferences, &devices) {
+              if let Err(e) = devices.lock().await.reset() {
+                eprintln!("Failed to reset input devices: {}", e);
+              }
+            }
+            let pressure = match &changed.pressure_profile {
+              Some(name) => state.pressure_profiles.borrow().get(name).copied().unwrap_or_else(|| {
+                eprintln!("No pressure profile {}", name);
+                PressureCurve::default()
+              }),
+              None => PressureCurve::default(),
+            };
+            if let Some(devices) = &devices {
+              let mut locked = devices.lock().await;
+              locked.set_pressure_curve(pressure);
+              locked.set_palm_rejection(changed.palm_rejection);
+            }
+            preferences = changed;
+          }
+          ClientMessage::Calibrate { level } => {
+            if calibrating.is_none() && level.is_some() {
+              calibration = Calibration::new();
+              // nothing may stay pressed while the pen is not injected
+              if let Some(devices) = &devices {
+                if let Err(e) = devices.lock().await.reset() {
+                  eprintln!("Failed to reset input devices: {}", e);
+                }
+              }
+            }
+            calibrating = level;
           }
-          ClientMessage::Key { key } => {
-            println!("Received key: {}", key);
-            // TODO
+          ClientMessage::SaveCalibration { name } => {
+            calibrating = None;
+            // the strokes are kept, so that the client can try again with another name
+            let fitted = match name.trim().is_empty() {
+              true => Err("the profile has no name".to_string()),
+              false => calibration
+                .levels()
+                .and_then(|levels| Ok((levels, input::fit_curve(levels)?))),
+            };
+            let reply = match fitted {
+              Ok((levels, curve)) => {
+                let mut saved = Ok(());
+                state.pressure_profiles.send_modify(|profiles| {
+                  profiles.insert(name.clone(), curve);
+                  saved = config::save_pressure_profiles(profiles);
+                });
+                match saved {
+                  Ok(()) => HostMessage::Calibrated { name, curve, levels },
+                  Err(e) => HostMessage::CalibrationFailed {
+                    reason: format!("failed to save the profile: {}", e),
+                  },
+                }
+              }
+              Err(reason) => HostMessage::CalibrationFailed { reason },
+            };
+            if let HostMessage::CalibrationFailed { reason } = &reply {
+              eprintln!("Calibration failed: {}", reason);
+            }
+            let _ = tx_reply.send(reply);
           }
         }
+      };
+      // release everything so that no key stays stuck after the client is gone
+      if let Some(devices) = &devices {
+        if let Err(e) = devices.lock().await.reset() {
+          eprintln!("Failed to reset input devices: {}", e);
+        }
       }
-      let _ = tx_stage.send(Stage::Closed);
+      let _ = tx_stage.send(Stage::Closed(close));
     }
   });
 
@@ -131,99 +577,103 @@ async fn handle_websocket(ws: WebSocket) {
   tokio::spawn({
     let mut rx_stage = rx_stage.clone();
     async move {
-      if wait_for_greeting(&mut rx_stage).await.is_err() {
-        return;
-      }
-      let (tx_video, mut rx_video) = mpsc::channel(1);
-
-      // TODO: use desktop duplication api to capture screen and pass it to ffmpeg
-
-      // https://www.webmproject.org/docs/encoder-parameters/
-      let ffmpeg = Command::new("ffmpeg")
-        .args([
-          // "-filter_complex",
-          // "ddagrab=0,hwdownload,format=bgra",
-          //
-          "-f",
-          "gdigrab",
-          "-framerate",
-          "30",
-          "-video_size",
-          "1920x1080",
-          "-i",
-          "desktop",
-          //
-          "-c:v",
-          "libvpx",
-          "-deadline",
-          "realtime",
-          "-pix_fmt",
-          "yuv420p",
-          "-f",
-          "webm",
-          "-",
-        ])
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn();
-
-      let Ok(mut ffmpeg) = ffmpeg else {
-        eprintln!("Failed to start ffmpeg");
-        return;
-      };
-
-      let mut video = ffmpeg.stdout.take().unwrap();
-
-      tokio::spawn(async move {
-        let mut slicer = slicer::Slicer::new();
-        let mut buf = vec![0; 1024];
-
-        loop {
-          let n = video.read(&mut buf).await.unwrap();
-          if n == 0 {
-            break;
-          }
-          let chunks = slicer.append(&buf[..n]);
-          for chunk in chunks {
-            let _ = tx_video.send(chunk).await;
+      let session = match wait_for_greeting(&mut rx_stage).await {
+        Ok(session) => session,
+        Err(close) => {
+          if let Some(close) = close {
+            let _ = tx.send(Message::close_with(close.code, close.reason)).await;
           }
+          return;
         }
-      });
+      };
+      let capture = state.capture(None);
+      let region = capture.region();
+      let mut rx_profiles = state.pressure_profiles.subscribe();
+      let msg = HostMessage::Welcome {
+        version: session.version,
+        session_id: session.id,
+        desktop: state.desktop,
+        region,
+        aspect: state.args.aspect,
+        monitors: state.monitors.clone(),
+        monitor: state.monitors.iter().position(|monitor| monitor.rect == region),
+        features: session.features.clone(),
+        max_touch_points: session.max_touch_points,
+        pressure_profiles: rx_profiles.borrow_and_update().keys().cloned().collect(),
+      };
+      send_message(&mut tx, msg).await;
+
+      let mut video = start_stream(&mut tx, &state, &capture).await;
 
       loop {
         select! {
-          data = rx_video.recv() => {
-            let Some(data) = data else {
+          segment = video.recv() => {
+            let Some((segment, gap)) = segment else {
+              // the client would be left with frozen video otherwise
+              eprintln!("Video stream ended");
+              let close = Close::ENCODER_FAILED;
+              let _ = tx.send(Message::close_with(close.code, close.reason)).await;
               break;
             };
-            let msg = HostMessage::Video { data };
-            let _ = send_message(&mut tx, msg).await;
+            let msg = HostMessage::Video { data: segment.data().to_vec(), gap };
+            send_message(&mut tx, msg).await;
+          }
+          Some(msg) = rx_reply.recv() => {
+            send_message(&mut tx, msg).await;
+          }
+          Ok(()) = rx_profiles.changed() => {
+            let names = rx_profiles.borrow_and_update().keys().cloned().collect();
+            send_message(&mut tx, HostMessage::PressureProfiles { names }).await;
+          }
+          Ok(()) = rx_monitor.changed() => {
+            let Some(index) = *rx_monitor.borrow_and_update() else {
+              continue;
+            };
+            let capture = state.capture(Some(index));
+            let msg = HostMessage::Selected { monitor: index, region: capture.region() };
+            send_message(&mut tx, msg).await;
+            video = start_stream(&mut tx, &state, &capture).await;
           }
           _ = rx_stage.changed() => {
-            if *rx_stage.borrow_and_update() == Stage::Closed {
+            let stage = rx_stage.borrow_and_update().clone();
+            if let Stage::Closed(close) = stage {
+              if let Some(close) = close {
+                let _ = tx.send(Message::close_with(close.code, close.reason)).await;
+              }
               break;
             }
           }
         }
       }
-
-      let _ = ffmpeg.kill().await;
     }
   });
 }
 
-async fn wait_for_greeting(rx: &mut watch::Receiver<Stage>) -> Result<(), ()> {
+async fn wait_for_greeting(rx: &mut watch::Receiver<Stage>) -> Result<Arc<Session>, Option<Close>> {
   loop {
-    match *rx.borrow_and_update() {
-      Stage::Greeted => return Ok(()),
-      Stage::Closed => return Err(()),
-      _ => {}
+    match &*rx.borrow_and_update() {
+      Stage::Greeted(session) => return Ok(session.clone()),
+      Stage::Closed(close) => return Err(*close),
+      Stage::Initial => {}
     }
     rx.changed().await.unwrap();
   }
 }
 
+/// Announce the stream of a capture and join it
+async fn start_stream(tx: &mut SplitSink<WebSocket, Message>, state: &Host, capture: &Capture) -> Subscription {
+  let info = state.args.encoder.stream_info(capture);
+  let msg = HostMessage::Init {
+    mime: info.mime,
+    width: info.size.map(|size| size.width),
+    height: info.size.map(|size| size.height),
+  };
+  send_message(tx, msg).await;
+
+  // the encoder is shared with the other sessions, and keeps running after this one closes
+  state.streams.subscribe(capture, &state.args.encoder)
+}
+
 async fn send_message(tx: &mut SplitSink<WebSocket, Message>, msg: HostMessage) {
   let msg = rmp_serde::encode::to_vec_named(&msg).unwrap();
   let msg = Message::binary(msg);
@@ -231,119 +681,3 @@ async fn send_message(tx: &mut SplitSink<WebSocket, Message>, msg: HostMessage)
     eprintln!("Failed to send message: {}", e);
   }
 }
-
-// Split video data into 'initialization segment' and 'media segments', and send them separately
-mod slicer {
-  // https://qiita.com/tomoyukilabs/items/57ba8a982ab372611669
-  // https://qiita.com/ryiwamoto/items/0ff451da6ab76b4f4064
-  // https://www.matroska.org/files/matroska_file_format_alexander_noe.pdf
-  // https://inza.blog/2014/04/30/ebml-extensible-binary-meta-language/
-  // https://www.matroska.org/technical/basics.html
-
-  const TAG_EBML: [u8; 4] = [0x1a, 0x45, 0xdf, 0xa3];
-  const TAG_SEGMENT: [u8; 4] = [0x18, 0x53, 0x80, 0x67];
-  const TAG_CLUSTER: [u8; 4] = [0x1f, 0x43, 0xb6, 0x75];
-  const TAG_VOID: [u8; 1] = [0xec];
-
-  enum State {
-    Header,
-    Data,
-  }
-
-  pub struct Slicer {
-    state: State,
-    buffer: Vec<u8>,
-  }
-  impl Slicer {
-    pub fn new() -> Self {
-      Self {
-        state: State::Header,
-        buffer: Vec::new(),
-      }
-    }
-    pub fn append(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
-      let buf = &mut self.buffer;
-      buf.extend_from_slice(data);
-
-      match self.state {
-        State::Header => {
-          let mut offset = 0;
-
-          // find the EBML element
-          if !buf[offset..].starts_with(&TAG_EBML) {
-            assert!(buf.len() < offset + TAG_EBML.len(), "EBML not found");
-            return vec![];
-          }
-          offset += TAG_EBML.len();
-          offset += read_varint(buf, &mut offset);
-
-          // find the Segment element
-          if !buf[offset..].starts_with(&TAG_SEGMENT) {
-            assert!(buf.len() < offset + TAG_SEGMENT.len(), "Segment not found");
-            return vec![];
-          }
-          offset += TAG_SEGMENT.len();
-          assert!(read_varint(buf, &mut offset) == 0xff_ffff_ffff_ffff); // unknown size
-
-          // find the Cluster element
-          loop {
-            if buf.len() < offset + 1 {
-              return vec![];
-            }
-            if buf[offset..].starts_with(&TAG_CLUSTER) {
-              break;
-            }
-            if buf[offset..].starts_with(&TAG_VOID) {
-              // println!("Void");
-              offset += TAG_VOID.len();
-              offset += read_varint(buf, &mut offset);
-            } else {
-              // println!("Unknown {:x?}", &buf[offset..offset + 4]);
-              offset += 4;
-              offset += read_varint(buf, &mut offset);
-            }
-          }
-
-          self.state = State::Data;
-          // println!("Header found");
-          let data = buf.drain(..offset).collect();
-          vec![data]
-        }
-        State::Data => {
-          let mut offset = 0;
-
-          // find the Cluster element
-          if !buf[offset..].starts_with(&TAG_CLUSTER) {
-            assert!(buf.len() < offset + TAG_CLUSTER.len(), "Cluster not found");
-            return vec![];
-          }
-          offset += TAG_CLUSTER.len();
-          offset += read_varint(buf, &mut offset);
-
-          if buf.len() < offset {
-            return vec![];
-          }
-
-          let data: Vec<u8> = buf.drain(..offset).collect();
-          // println!("Cluster found: {}", data.len());
-          vec![data]
-        }
-      }
-    }
-  }
-
-  // unsigned integer with variable length
-  fn read_varint(data: &[u8], offset: &mut usize) -> usize {
-    let len = data[*offset].leading_zeros() as usize + 1;
-    let mut value = 0;
-    for i in 0..len {
-      value = (value << 8) | data[*offset + i] as u64;
-      if i == 0 {
-        value -= 1 << (8 - len);
-      }
-    }
-    // println!("Varint: {:?} -> {:x}", &data[*offset..*offset + len], value);
-    *offset += len;
-    value as usize
-  }
-}
@@ -0,0 +1,203 @@
+// Messages between the host and the web client, encoded as MessagePack
+//
+// The client greets with `Hello`, carrying its protocol version and capabilities, and the host answers with
+// `Welcome`, or closes the connection with one of the `Close` codes if it cannot serve the client.
+
+use input::{Aspect, KeyEvent, Monitor, PointerEvent, PressureCurve, Rect, StrokeLevel};
+use serde::{Deserialize, Serialize};
+
+use crate::encoder::Codec;
+
+/// Bumped on every incompatible change to the messages
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest client version that this host still understands
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClientMessage {
+  Hello {
+    token: String,
+    /// Missing for clients from before versioning
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    capabilities: Capabilities,
+  },
+  Key(KeyEvent),
+  Pointer(PointerEvent),
+  /// Size of the video element in CSS pixels, sent whenever it changes
+  Viewport {
+    width: f64,
+    height: f64,
+  },
+  /// Switch the stream and the pointer mapping to one of the monitors in `Welcome`
+  SelectMonitor {
+    index: usize,
+  },
+  /// Settings of this session, which the client may change at any time
+  Preferences(Preferences),
+  /// Record the pressure of pen strokes at a level instead of injecting them, or stop recording if unset
+  Calibrate {
+    level: Option<StrokeLevel>,
+  },
+  /// Fit a pressure curve to the recorded strokes and save it as a profile, which ends the calibration
+  SaveCalibration {
+    name: String,
+  },
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+  /// Codecs that the client can decode
+  pub codecs: Vec<Codec>,
+  /// Input that the client can send
+  pub input: Vec<Feature>,
+  pub max_touch_points: u32,
+  pub screen: Option<Screen>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Preferences {
+  /// Inject pens and touches as the mouse, for applications that ignore them
+  pub emulate_mouse: bool,
+  pub touch_mode: TouchMode,
+  /// Name of the pressure curve for the pen, which is linear if unset
+  pub pressure_profile: Option<String>,
+  /// Reject touches as palms while the pen is in use
+  pub palm_rejection: bool,
+}
+
+impl Default for Preferences {
+  fn default() -> Self {
+    Self {
+      emulate_mouse: false,
+      touch_mode: TouchMode::default(),
+      pressure_profile: None,
+      palm_rejection: true,
+    }
+  }
+}
+
+/// What touches do on the host
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TouchMode {
+  /// Touches on the host, or the mouse if it is emulated
+  #[default]
+  Raw,
+  /// The surface is a trackpad that moves the cursor relative to where it is
+  Trackpad,
+  /// Two fingers scroll, zoom and rotate, and three fingers tap
+  Gestures,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+  Pen,
+  Touch,
+  Mouse,
+  Keyboard,
+}
+
+/// Screen of the client in CSS pixels
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Screen {
+  pub width: f64,
+  pub height: f64,
+  pub device_pixel_ratio: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HostMessage {
+  /// Reply to `Hello`, with what was agreed on
+  #[serde(rename_all = "camelCase")]
+  Welcome {
+    version: u32,
+    session_id: u64,
+    /// Bounding box of all monitors, which may start at negative coordinates
+    desktop: Rect,
+    /// Region of the virtual desktop in the stream
+    region: Rect,
+    /// How the client should fit the stream into the video element, which pointer positions are mapped with
+    aspect: Aspect,
+    /// Monitors that the client can switch to, with the primary one first
+    monitors: Vec<Monitor>,
+    /// Index of the monitor in the stream, if it is exactly one of them
+    monitor: Option<usize>,
+    /// Input that the host accepts from this client
+    features: Vec<Feature>,
+    max_touch_points: u32,
+    /// Names of the pressure curves that the client can pick in `Preferences`
+    pressure_profiles: Vec<String>,
+  },
+  /// Reply to `SelectMonitor`, followed by a new `Init`
+  Selected { monitor: usize, region: Rect },
+  /// Reply to `SaveCalibration` with the saved profile, and the median pressure of the light, medium and firm
+  /// strokes, which it maps to 1/4, 1/2 and 3/4
+  Calibrated {
+    name: String,
+    curve: PressureCurve,
+    levels: [f64; 3],
+  },
+  /// Reply to `SaveCalibration` if no profile could be saved
+  CalibrationFailed { reason: String },
+  /// Names of the pressure curves, whenever a calibration of any session saved one
+  PressureProfiles { names: Vec<String> },
+  /// Sent before the first video data of each stream, with the size of the video if the host knows it up front
+  Init {
+    mime: String,
+    width: Option<u32>,
+    height: Option<u32>,
+  },
+  Video {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    /// Whether segments were dropped before this one, which is a keyframe then
+    gap: bool,
+  },
+}
+
+/// https://www.rfc-editor.org/rfc/rfc6455#section-7.4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Close {
+  pub code: u16,
+  pub reason: &'static str,
+}
+
+impl Close {
+  pub const PROTOCOL_ERROR: Close = Close {
+    code: 1002,
+    reason: "protocol error",
+  };
+  pub const INVALID_PAYLOAD: Close = Close {
+    code: 1007,
+    reason: "invalid message",
+  };
+  // application-specific codes are 4000-4999, so these mirror the HTTP status codes
+  pub const INVALID_TOKEN: Close = Close {
+    code: 4401,
+    reason: "invalid token",
+  };
+  pub const UNSUPPORTED_CODEC: Close = Close {
+    code: 4415,
+    reason: "unsupported codec",
+  };
+  pub const INCOMPATIBLE_VERSION: Close = Close {
+    code: 4426,
+    reason: "incompatible protocol version",
+  };
+  pub const TOO_MANY_ATTEMPTS: Close = Close {
+    code: 4429,
+    reason: "too many attempts",
+  };
+  pub const ENCODER_FAILED: Close = Close {
+    code: 4500,
+    reason: "video encoder failed",
+  };
+}
@@ -0,0 +1,90 @@
+// Split video data into 'initialization segment' and 'media segments', and send them separately
+
+use std::fmt;
+
+pub use self::{mp4::Mp4Slicer, webm::WebmSlicer};
+
+mod mp4;
+mod webm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+  /// WebM / Matroska, sliced into the EBML header + Tracks and Clusters
+  Webm,
+  /// Fragmented MP4, sliced into `ftyp` + `moov` and `moof` + `mdat`
+  Mp4,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+  /// Initialization segment, which every client needs first
+  Init(Vec<u8>),
+  /// Media segment, which can be decoded on its own if it starts with a keyframe
+  Media { data: Vec<u8>, keyframe: bool },
+}
+
+impl Segment {
+  pub fn data(&self) -> &[u8] {
+    match self {
+      Segment::Init(data) | Segment::Media { data, .. } => data,
+    }
+  }
+
+  /// Whether a client can start decoding from this segment, after the initialization segment
+  pub fn is_keyframe(&self) -> bool {
+    match self {
+      Segment::Init(_) => true,
+      Segment::Media { keyframe, .. } => *keyframe,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SliceError {
+  /// An EBML element ID or size that is not a valid variable length integer
+  InvalidVarint,
+  /// An element that may not appear at this position, e.g. a Cluster before the Segment
+  UnexpectedElement(u32),
+  /// An element of unknown size, which is only supported for Segments and Clusters
+  UnknownSize(u32),
+  /// An element or box that is too large to buffer
+  TooLarge(u64),
+  /// An element that does not fit in its parent
+  Overflow(u32),
+  /// Data after the end of a Segment of known size
+  TrailingData,
+  /// An MP4 box with an invalid size
+  InvalidBox([u8; 4]),
+}
+
+impl fmt::Display for SliceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SliceError::InvalidVarint => write!(f, "invalid EBML variable length integer"),
+      SliceError::UnexpectedElement(id) => write!(f, "unexpected EBML element {:x}", id),
+      SliceError::UnknownSize(id) => write!(f, "EBML element {:x} has unknown size", id),
+      SliceError::TooLarge(size) => write!(f, "element of {} bytes is too large", size),
+      SliceError::Overflow(id) => write!(f, "EBML element {:x} overflows its parent", id),
+      SliceError::TrailingData => write!(f, "data after the end of the Segment"),
+      SliceError::InvalidBox(name) => write!(f, "invalid MP4 box {:?}", String::from_utf8_lossy(name)),
+    }
+  }
+}
+
+impl std::error::Error for SliceError {}
+
+/// Elements and boxes are buffered whole, so this bounds the memory used for one segment
+const MAX_ELEMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+pub trait Slicer: Send {
+  /// Feed data from the muxer and take the segments completed so far.
+  /// After an error, the slicer stays broken and returns the same error.
+  fn append(&mut self, data: &[u8]) -> Result<Vec<Segment>, SliceError>;
+}
+
+pub fn new(container: Container) -> Box<dyn Slicer> {
+  match container {
+    Container::Webm => Box::new(WebmSlicer::new()),
+    Container::Mp4 => Box::new(Mp4Slicer::new()),
+  }
+}
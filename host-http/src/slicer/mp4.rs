@@ -0,0 +1,343 @@
+// https://developer.mozilla.org/en-US/docs/Web/API/Media_Source_Extensions_API/Transcoding_assets_for_MSE
+// https://w3c.github.io/mse-byte-stream-format-isobmff/
+// ISO/IEC 14496-12 (ISO base media file format), 4.2 Object Structure, 8.8 Movie Fragments
+
+use super::{Segment, SliceError, Slicer, MAX_ELEMENT_SIZE};
+
+const BOX_MOOV: [u8; 4] = *b"moov";
+const BOX_MVEX: [u8; 4] = *b"mvex";
+const BOX_TREX: [u8; 4] = *b"trex";
+const BOX_MOOF: [u8; 4] = *b"moof";
+const BOX_TRAF: [u8; 4] = *b"traf";
+const BOX_TFHD: [u8; 4] = *b"tfhd";
+const BOX_TRUN: [u8; 4] = *b"trun";
+const BOX_MDAT: [u8; 4] = *b"mdat";
+
+/// `tfhd` flag for the presence of each field before `default_sample_flags`, and that of the flags themselves
+const TFHD_FIELDS: [(u32, usize); 4] = [(0x01, 8), (0x02, 4), (0x08, 4), (0x10, 4)];
+const TFHD_DEFAULT_SAMPLE_FLAGS: u32 = 0x20;
+/// `trun` flags for the presence of `data_offset`, `first_sample_flags`, and the fields of each sample
+const TRUN_DATA_OFFSET: u32 = 0x01;
+const TRUN_FIRST_SAMPLE_FLAGS: u32 = 0x04;
+const TRUN_SAMPLE_DURATION: u32 = 0x100;
+const TRUN_SAMPLE_SIZE: u32 = 0x200;
+const TRUN_SAMPLE_FLAGS: u32 = 0x400;
+/// `sample_is_non_sync_sample` in the sample flags, which is clear for keyframes
+const SAMPLE_IS_NON_SYNC: u32 = 0x0001_0000;
+
+enum State {
+  /// Waiting for `moov`, which ends the initialization segment
+  Header,
+  /// Waiting for `mdat`, which ends each media segment
+  Data,
+  /// The stream could not be parsed, and everything is discarded
+  Broken(SliceError),
+}
+
+pub struct Mp4Slicer {
+  state: State,
+  buffer: Vec<u8>,
+  /// Length of the complete boxes at the start of the buffer that belong to the current segment
+  pending: usize,
+  /// Sample flags from the `trex` of the initialization segment, for fragments that give none
+  default_sample_flags: Option<u32>,
+}
+
+impl Mp4Slicer {
+  pub fn new() -> Self {
+    Self {
+      state: State::Header,
+      buffer: Vec::new(),
+      pending: 0,
+      default_sample_flags: None,
+    }
+  }
+}
+
+impl Slicer for Mp4Slicer {
+  fn append(&mut self, data: &[u8]) -> Result<Vec<Segment>, SliceError> {
+    if let State::Broken(err) = &self.state {
+      return Err(err.clone());
+    }
+    self.buffer.extend_from_slice(data);
+
+    let mut segments = vec![];
+    while let Some((size, name)) = read_box_header(&self.buffer[self.pending..]) {
+      let size = match size {
+        Some(size) if size > MAX_ELEMENT_SIZE => Err(SliceError::TooLarge(size)),
+        Some(size) => Ok(size as usize),
+        None => Err(SliceError::InvalidBox(name)),
+      };
+      let size = match size {
+        Ok(size) => size,
+        Err(err) => {
+          self.state = State::Broken(err.clone());
+          self.buffer.clear();
+          self.pending = 0;
+          return Err(err);
+        }
+      };
+      if self.buffer.len() < self.pending + size {
+        break;
+      }
+      self.pending += size;
+
+      let last = match self.state {
+        State::Header => name == BOX_MOOV,
+        State::Data => name == BOX_MDAT,
+        State::Broken(_) => false,
+      };
+      if last {
+        let data: Vec<u8> = self.buffer.drain(..self.pending).collect();
+        segments.push(match self.state {
+          State::Header => {
+            self.default_sample_flags = trex_sample_flags(&data);
+            Segment::Init(data)
+          }
+          _ => {
+            let keyframe = first_sample_flags(&data)
+              .or(self.default_sample_flags)
+              .is_some_and(|flags| flags & SAMPLE_IS_NON_SYNC == 0);
+            Segment::Media { data, keyframe }
+          }
+        });
+        self.pending = 0;
+        self.state = State::Data;
+      }
+    }
+    Ok(segments)
+  }
+}
+
+/// Read the size and type of the box at the start of the data, if the header is complete.
+/// The size is `None` if it is invalid, or extends to the end of the file which never comes for a live stream.
+fn read_box_header(data: &[u8]) -> Option<(Option<u64>, [u8; 4])> {
+  if data.len() < 8 {
+    return None;
+  }
+  let size = u32::from_be_bytes(data[0..4].try_into().unwrap()) as u64;
+  let name: [u8; 4] = data[4..8].try_into().unwrap();
+
+  let (size, header) = match size {
+    1 => {
+      if data.len() < 16 {
+        return None;
+      }
+      (u64::from_be_bytes(data[8..16].try_into().unwrap()), 16)
+    }
+    size => (size, 8),
+  };
+
+  if size < header {
+    return Some((None, name));
+  }
+  Some((Some(size), name))
+}
+
+/// The complete boxes in the data, as their type and body, up to the first one that is cut off or invalid
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+  std::iter::from_fn(move || {
+    let (Some(size), name) = read_box_header(data)? else {
+      return None;
+    };
+    // a size of 1 is followed by the 64-bit size
+    let header = match data[..4] == [0, 0, 0, 1] {
+      true => 16,
+      false => 8,
+    };
+    let size = usize::try_from(size).ok().filter(|&size| size <= data.len())?;
+    let body = &data[header..size];
+    data = &data[size..];
+    Some((name, body))
+  })
+}
+
+/// The body of the first box of the given type among the boxes in the data
+fn child(data: &[u8], name: [u8; 4]) -> Option<&[u8]> {
+  boxes(data).find(|(child, _)| *child == name).map(|(_, body)| body)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+  let bytes = data.get(offset..offset + 4)?;
+  Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// `default_sample_flags` of the first track, from `moov` / `mvex` / `trex`
+fn trex_sample_flags(init: &[u8]) -> Option<u32> {
+  let trex = child(child(child(init, BOX_MOOV)?, BOX_MVEX)?, BOX_TREX)?;
+  // version and flags, track_ID, default_sample_description_index, default_sample_duration, default_sample_size
+  read_u32(trex, 20)
+}
+
+/// Flags of the first sample of the first track in a media segment, from `moof` / `traf`, if it gives them
+fn first_sample_flags(segment: &[u8]) -> Option<u32> {
+  let traf = child(child(segment, BOX_MOOF)?, BOX_TRAF)?;
+
+  let trun = child(traf, BOX_TRUN)?;
+  let flags = read_u32(trun, 0)? & 0x00ff_ffff;
+  // after version and flags, and sample_count
+  let mut offset = 8;
+  if flags & TRUN_DATA_OFFSET != 0 {
+    offset += 4;
+  }
+  if flags & TRUN_FIRST_SAMPLE_FLAGS != 0 {
+    return read_u32(trun, offset);
+  }
+  if flags & TRUN_SAMPLE_FLAGS != 0 {
+    for field in [TRUN_SAMPLE_DURATION, TRUN_SAMPLE_SIZE] {
+      if flags & field != 0 {
+        offset += 4;
+      }
+    }
+    return read_u32(trun, offset);
+  }
+
+  let tfhd = child(traf, BOX_TFHD)?;
+  let flags = read_u32(tfhd, 0)? & 0x00ff_ffff;
+  if flags & TFHD_DEFAULT_SAMPLE_FLAGS == 0 {
+    return None;
+  }
+  // after version and flags, and track_ID
+  let offset = 8
+    + TFHD_FIELDS
+      .iter()
+      .filter(|(field, _)| flags & field != 0)
+      .map(|(_, size)| size)
+      .sum::<usize>();
+  read_u32(tfhd, offset)
+}
+
+#[cfg(test)]
+mod tests {
+  use proptest::prelude::*;
+
+  use super::*;
+
+  const SYNC: u32 = 0x0200_0000;
+  const NON_SYNC: u32 = 0x0101_0000;
+
+  fn mp4_box(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    [&(body.len() as u32 + 8).to_be_bytes()[..], name, body].concat()
+  }
+
+  fn full_box(name: &[u8; 4], flags: u32, fields: &[u32]) -> Vec<u8> {
+    let body: Vec<u8> = [flags]
+      .iter()
+      .chain(fields)
+      .flat_map(|field| field.to_be_bytes())
+      .collect();
+    mp4_box(name, &body)
+  }
+
+  fn init(default_sample_flags: u32) -> Vec<u8> {
+    let trex = full_box(b"trex", 0, &[1, 1, 0, 0, default_sample_flags]);
+    [
+      mp4_box(b"ftyp", b"isom\0\0\x02\0"),
+      mp4_box(b"moov", &[mp4_box(b"mvhd", &[0; 20]), mp4_box(b"mvex", &trex)].concat()),
+    ]
+    .concat()
+  }
+
+  /// A fragment with the given `tfhd` and `trun`, and some data
+  fn fragment(tfhd: Vec<u8>, trun: Vec<u8>) -> Vec<u8> {
+    let traf = mp4_box(b"traf", &[tfhd, full_box(b"tfdt", 0, &[0]), trun].concat());
+    let moof = mp4_box(b"moof", &[full_box(b"mfhd", 0, &[1]), traf].concat());
+    [moof, mp4_box(b"mdat", &[0x55; 40])].concat()
+  }
+
+  fn keyframes(data: &[u8]) -> Vec<bool> {
+    Mp4Slicer::new()
+      .append(data)
+      .unwrap()
+      .iter()
+      .map(Segment::is_keyframe)
+      .collect()
+  }
+
+  #[test]
+  fn reads_first_sample_flags() {
+    let tfhd = full_box(b"tfhd", 0x02_0000, &[1]);
+    // data_offset and first_sample_flags, then the size of each of the two samples
+    let trun = |flags: u32| full_box(b"trun", 0x205, &[2, 100, flags, 20, 20]);
+    let data = [
+      init(NON_SYNC),
+      fragment(tfhd.clone(), trun(SYNC)),
+      fragment(tfhd, trun(NON_SYNC)),
+    ]
+    .concat();
+    assert_eq!(keyframes(&data), [true, true, false]);
+  }
+
+  #[test]
+  fn reads_sample_flags_of_the_first_sample() {
+    let tfhd = full_box(b"tfhd", 0, &[1]);
+    // duration, size and flags of each of the two samples
+    let trun = |flags: u32| full_box(b"trun", 0x700, &[2, 33, 20, flags, 33, 20, SYNC]);
+    let data = [
+      init(SYNC),
+      fragment(tfhd.clone(), trun(SYNC)),
+      fragment(tfhd, trun(NON_SYNC)),
+    ]
+    .concat();
+    assert_eq!(keyframes(&data), [true, true, false]);
+  }
+
+  #[test]
+  fn falls_back_to_default_sample_flags() {
+    let trun = full_box(b"trun", 0x001, &[1, 100]);
+    // base_data_offset and default_sample_duration come before default_sample_flags
+    let tfhd = |flags: u32| full_box(b"tfhd", 0x29, &[1, 0, 0, 33, flags]);
+    let data = [
+      init(NON_SYNC),
+      fragment(tfhd(SYNC), trun.clone()),
+      fragment(tfhd(NON_SYNC), trun.clone()),
+      fragment(full_box(b"tfhd", 0, &[1]), trun.clone()),
+    ]
+    .concat();
+    assert_eq!(keyframes(&data), [true, true, false, false]);
+    let data = [init(SYNC), fragment(full_box(b"tfhd", 0, &[1]), trun)].concat();
+    assert_eq!(keyframes(&data), [true, true]);
+  }
+
+  #[test]
+  fn fragments_without_flags_are_not_keyframes() {
+    let data = [
+      mp4_box(b"moov", &[]),
+      mp4_box(b"moof", &[]),
+      mp4_box(b"mdat", &[0x55; 4]),
+    ]
+    .concat();
+    assert_eq!(keyframes(&data), [true, false]);
+    // boxes that are too short for the flags they announce
+    let trun = mp4_box(b"trun", &[0x00, 0x00]);
+    let data = [mp4_box(b"moov", &[]), fragment(full_box(b"tfhd", 0x20, &[1]), trun)].concat();
+    assert_eq!(keyframes(&data), [true, false]);
+  }
+
+  proptest! {
+    #[test]
+    fn slices_in_pieces(keyframes in prop::collection::vec(any::<bool>(), 0..6), pieces in prop::collection::vec(1usize..64, 1..16)) {
+      let tfhd = full_box(b"tfhd", 0x02_0000, &[1]);
+      let flags = |keyframe: bool| if keyframe { SYNC } else { NON_SYNC };
+      let mut expected = vec![(init(NON_SYNC), true)];
+      for &keyframe in &keyframes {
+        expected.push((fragment(tfhd.clone(), full_box(b"trun", 0x005, &[1, 100, flags(keyframe)])), keyframe));
+      }
+      let data: Vec<u8> = expected.iter().flat_map(|(data, _)| data.clone()).collect();
+
+      let mut slicer = Mp4Slicer::new();
+      let mut segments = vec![];
+      let mut rest = &data[..];
+      for &piece in pieces.iter().cycle() {
+        if rest.is_empty() {
+          break;
+        }
+        let (head, tail) = rest.split_at(piece.min(rest.len()));
+        segments.extend(slicer.append(head).unwrap());
+        rest = tail;
+      }
+      let segments: Vec<_> = segments.iter().map(|segment| (segment.data().to_vec(), segment.is_keyframe())).collect();
+      prop_assert_eq!(segments, expected);
+    }
+  }
+}
@@ -0,0 +1,580 @@
+// https://qiita.com/tomoyukilabs/items/57ba8a982ab372611669
+// https://qiita.com/ryiwamoto/items/0ff451da6ab76b4f4064
+// https://www.matroska.org/files/matroska_file_format_alexander_noe.pdf
+// https://inza.blog/2014/04/30/ebml-extensible-binary-meta-language/
+// https://www.matroska.org/technical/basics.html
+// https://www.rfc-editor.org/rfc/rfc8794 (EBML)
+
+use super::{Segment, SliceError, Slicer, MAX_ELEMENT_SIZE};
+
+const ID_EBML: u32 = 0x1a45dfa3;
+const ID_SEGMENT: u32 = 0x18538067;
+const ID_CLUSTER: u32 = 0x1f43b675;
+const ID_SIMPLE_BLOCK: u32 = 0xa3;
+const ID_BLOCK_GROUP: u32 = 0xa0;
+const ID_REFERENCE_BLOCK: u32 = 0xfb;
+const ID_VOID: u32 = 0xec;
+const ID_CRC32: u32 = 0xbf;
+
+/// Children of the Segment other than Cluster, which also end a Cluster of unknown size
+const SEGMENT_CHILDREN: [u32; 7] = [
+  0x114d9b74, // SeekHead
+  0x1549a966, // Info
+  0x1654ae6b, // Tracks
+  0x1c53bb6b, // Cues
+  0x1941a469, // Attachments
+  0x1043a770, // Chapters
+  0x1254c367, // Tags
+];
+
+enum State {
+  /// Waiting for the EBML header
+  Ebml,
+  /// Waiting for the Segment
+  Segment,
+  /// Inside the Segment, before the first Cluster
+  Header,
+  /// Between Clusters
+  Clusters,
+  /// Inside a Cluster of unknown size, which ends at the next element of the Segment
+  Cluster,
+  /// After the end of a Segment of known size
+  Ended,
+  /// The stream could not be parsed, and everything is discarded
+  Broken(SliceError),
+}
+
+pub struct WebmSlicer {
+  state: State,
+  buffer: Vec<u8>,
+  /// Length of the complete elements at the start of the buffer that belong to the current segment
+  pending: usize,
+  /// Stream position of the start of the buffer
+  position: u64,
+  /// Stream position of the end of the Segment, if its size is known
+  segment_end: Option<u64>,
+  /// Offset of the body of the current Cluster in the buffer
+  cluster: usize,
+}
+
+struct Header {
+  id: u32,
+  /// `None` for unknown size
+  size: Option<u64>,
+  /// Length of the ID and the size
+  len: usize,
+}
+
+impl WebmSlicer {
+  pub fn new() -> Self {
+    Self {
+      state: State::Ebml,
+      buffer: Vec::new(),
+      pending: 0,
+      position: 0,
+      segment_end: None,
+      cluster: 0,
+    }
+  }
+
+  fn slice(&mut self, segments: &mut Vec<Segment>) -> Result<(), SliceError> {
+    loop {
+      if self.segment_end == Some(self.offset()) {
+        if let State::Cluster = self.state {
+          self.flush_cluster(segments);
+        }
+        self.state = State::Ended;
+      }
+      if let State::Ended = self.state {
+        if self.pending < self.buffer.len() {
+          return Err(SliceError::TrailingData);
+        }
+        return Ok(());
+      }
+
+      let Some(header) = read_header(&self.buffer[self.pending..])? else {
+        return Ok(());
+      };
+
+      match self.state {
+        State::Ebml => {
+          if header.id != ID_EBML {
+            return Err(SliceError::UnexpectedElement(header.id));
+          }
+          if !self.skip(&header)? {
+            return Ok(());
+          }
+          self.state = State::Segment;
+        }
+        State::Segment => match header.id {
+          ID_VOID | ID_CRC32 => {
+            if !self.skip(&header)? {
+              return Ok(());
+            }
+          }
+          ID_SEGMENT => {
+            // enter the Segment, whose children are sliced on their own
+            self.pending += header.len;
+            self.segment_end = header.size.map(|size| self.offset().saturating_add(size));
+            self.state = State::Header;
+          }
+          id => return Err(SliceError::UnexpectedElement(id)),
+        },
+        State::Header => match header.id {
+          ID_CLUSTER => {
+            // everything up to the first Cluster is the initialization segment
+            let data = self.take();
+            segments.push(Segment::Init(data));
+            self.state = State::Clusters;
+          }
+          ID_EBML | ID_SEGMENT => return Err(SliceError::UnexpectedElement(header.id)),
+          _ => {
+            if !self.skip(&header)? {
+              return Ok(());
+            }
+          }
+        },
+        State::Clusters => match header.id {
+          ID_CLUSTER if header.size.is_none() => {
+            self.pending += header.len;
+            self.cluster = self.pending;
+            self.state = State::Cluster;
+          }
+          ID_CLUSTER => {
+            self.cluster = self.pending + header.len;
+            if !self.skip(&header)? {
+              return Ok(());
+            }
+            self.flush_cluster(segments);
+          }
+          ID_EBML | ID_SEGMENT => return Err(SliceError::UnexpectedElement(header.id)),
+          // e.g. Cues, which are sent along with the next Cluster
+          _ => {
+            if !self.skip(&header)? {
+              return Ok(());
+            }
+          }
+        },
+        State::Cluster => {
+          if header.id == ID_CLUSTER
+            || header.id == ID_EBML
+            || header.id == ID_SEGMENT
+            || SEGMENT_CHILDREN.contains(&header.id)
+          {
+            self.flush_cluster(segments);
+            self.state = State::Clusters;
+          } else if !self.skip(&header)? {
+            return Ok(());
+          }
+        }
+        State::Ended | State::Broken(_) => unreachable!(),
+      }
+    }
+  }
+
+  /// Stream position of the next element
+  fn offset(&self) -> u64 {
+    self.position + self.pending as u64
+  }
+
+  /// Add the whole element to the current segment, or return false if it is not complete yet
+  fn skip(&mut self, header: &Header) -> Result<bool, SliceError> {
+    let Some(size) = header.size else {
+      return Err(SliceError::UnknownSize(header.id));
+    };
+    let total = (header.len as u64).saturating_add(size);
+    let buffered = (self.pending as u64).saturating_add(total);
+    if buffered > MAX_ELEMENT_SIZE {
+      return Err(SliceError::TooLarge(buffered));
+    }
+    if let Some(end) = self.segment_end {
+      if self.offset() + total > end {
+        return Err(SliceError::Overflow(header.id));
+      }
+    }
+
+    let total = total as usize;
+    if self.buffer.len() < self.pending + total {
+      return Ok(false);
+    }
+    self.pending += total;
+    Ok(true)
+  }
+
+  /// Take the current segment out of the buffer
+  fn take(&mut self) -> Vec<u8> {
+    self.position += self.pending as u64;
+    let pending = std::mem::take(&mut self.pending);
+    self.buffer.drain(..pending).collect()
+  }
+
+  fn flush_cluster(&mut self, segments: &mut Vec<Segment>) {
+    let keyframe = starts_with_keyframe(&self.buffer[self.cluster..self.pending]);
+    let data = self.take();
+    segments.push(Segment::Media { data, keyframe });
+  }
+}
+
+impl Slicer for WebmSlicer {
+  fn append(&mut self, data: &[u8]) -> Result<Vec<Segment>, SliceError> {
+    if let State::Broken(err) = &self.state {
+      return Err(err.clone());
+    }
+    self.buffer.extend_from_slice(data);
+
+    let mut segments = vec![];
+    match self.slice(&mut segments) {
+      Ok(()) => Ok(segments),
+      Err(err) => {
+        self.state = State::Broken(err.clone());
+        self.buffer.clear();
+        self.pending = 0;
+        Err(err)
+      }
+    }
+  }
+}
+
+/// Read the ID and size of the element at the start of the data, if they are complete
+fn read_header(data: &[u8]) -> Result<Option<Header>, SliceError> {
+  let Some((id, id_len)) = read_varint(data)? else {
+    return Ok(None);
+  };
+  if id_len > 4 {
+    return Err(SliceError::InvalidVarint);
+  }
+  let Some((size, size_len)) = read_varint(&data[id_len..])? else {
+    return Ok(None);
+  };
+
+  // IDs keep their length marker, sizes do not
+  let mask = (1 << (7 * size_len)) - 1;
+  let size = size & mask;
+  Ok(Some(Header {
+    id: id as u32,
+    // all ones means unknown size
+    size: if size == mask { None } else { Some(size) },
+    len: id_len + size_len,
+  }))
+}
+
+/// Whether the first block in the body of a Cluster is a keyframe
+fn starts_with_keyframe(mut body: &[u8]) -> bool {
+  while let Ok(Some(header)) = read_header(body) {
+    let Some(size) = header.size else {
+      return false;
+    };
+    let Some(data) = usize::try_from(size)
+      .ok()
+      .and_then(|size| body.get(header.len..header.len + size))
+    else {
+      return false;
+    };
+    let end = header.len + data.len();
+    match header.id {
+      // track number, timestamp (2 bytes), and flags
+      ID_SIMPLE_BLOCK => {
+        return match read_varint(data) {
+          Ok(Some((_, len))) => data.get(len + 2).is_some_and(|flags| flags & 0x80 != 0),
+          _ => false,
+        };
+      }
+      // a Block without ReferenceBlocks does not depend on other frames
+      ID_BLOCK_GROUP => {
+        let mut children = data;
+        while let Ok(Some(child)) = read_header(children) {
+          if child.id == ID_REFERENCE_BLOCK {
+            return false;
+          }
+          let Some(size) = child.size.and_then(|size| usize::try_from(size).ok()) else {
+            return false;
+          };
+          let Some(rest) = children.get(child.len + size..) else {
+            return false;
+          };
+          children = rest;
+        }
+        return true;
+      }
+      _ => body = &body[end..],
+    }
+  }
+  false
+}
+
+/// Unsigned integer with variable length, including the length marker.
+/// Returns `None` if the data ends before the integer does.
+fn read_varint(data: &[u8]) -> Result<Option<(u64, usize)>, SliceError> {
+  let Some(&first) = data.first() else {
+    return Ok(None);
+  };
+  if first == 0 {
+    return Err(SliceError::InvalidVarint);
+  }
+  let len = first.leading_zeros() as usize + 1;
+  let Some(bytes) = data.get(..len) else {
+    return Ok(None);
+  };
+  let value = bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64);
+  Ok(Some((value, len)))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{process::Command, sync::OnceLock};
+
+  use proptest::prelude::*;
+
+  use super::*;
+
+  const ID_INFO: u32 = 0x1549a966;
+  const ID_TRACKS: u32 = 0x1654ae6b;
+  const ID_CUES: u32 = 0x1c53bb6b;
+  const ID_TIMESTAMP: u32 = 0xe7;
+
+  fn id(id: u32) -> Vec<u8> {
+    id.to_be_bytes().into_iter().skip_while(|&byte| byte == 0).collect()
+  }
+
+  /// Size with at least the given length of 1 to 8 bytes, or unknown size
+  fn size(size: Option<usize>, mut len: usize) -> Vec<u8> {
+    if let Some(size) = size {
+      while size as u64 >= (1 << (7 * len)) - 1 {
+        len += 1;
+      }
+    }
+    let value = size.map_or((1 << (7 * len)) - 1, |size| size as u64);
+    let value = value | 1 << (7 * len);
+    value.to_be_bytes()[8 - len..].to_vec()
+  }
+
+  fn element(tag: u32, body: &[u8], size_len: usize) -> Vec<u8> {
+    [id(tag), size(Some(body.len()), size_len), body.to_vec()].concat()
+  }
+
+  fn unknown(tag: u32, body: &[u8], size_len: usize) -> Vec<u8> {
+    [id(tag), size(None, size_len), body.to_vec()].concat()
+  }
+
+  #[derive(Debug, Clone)]
+  struct Cluster {
+    known: bool,
+    cues: bool,
+    blocks: Vec<Vec<u8>>,
+  }
+
+  #[derive(Debug, Clone)]
+  struct Stream {
+    known: bool,
+    size_len: usize,
+    clusters: Vec<Cluster>,
+  }
+
+  impl Stream {
+    /// The whole stream, and the segments it should be sliced into
+    fn build(&self) -> (Vec<u8>, Vec<Vec<u8>>) {
+      let len = self.size_len;
+      let ebml = element(ID_EBML, &element(0x4282, b"webm", len), len);
+      let header = [
+        element(ID_INFO, &element(0x2ad7b1, &[0x0f, 0x42, 0x40], len), len),
+        element(ID_TRACKS, &[0x55; 20], len),
+        element(ID_VOID, &[0; 3], len),
+      ]
+      .concat();
+
+      let mut segments = vec![header];
+      for (i, cluster) in self.clusters.iter().enumerate() {
+        let mut body = element(ID_TIMESTAMP, &[i as u8], len);
+        for block in &cluster.blocks {
+          body.extend(element(ID_SIMPLE_BLOCK, block, len));
+        }
+        let mut segment = vec![];
+        // Cues before the first Cluster belong to the initialization segment
+        if cluster.cues && i > 0 {
+          segment.extend(element(ID_CUES, &[0x33; 5], len));
+        }
+        match cluster.known {
+          true => segment.extend(element(ID_CLUSTER, &body, len)),
+          false => segment.extend(unknown(ID_CLUSTER, &body, len)),
+        }
+        segments.push(segment);
+      }
+
+      let body = segments.concat();
+      let segment = match self.known {
+        true => element(ID_SEGMENT, &body, 8),
+        false => unknown(ID_SEGMENT, &body, len),
+      };
+      let data = [ebml, segment].concat();
+
+      let header = data.len() - body.len() + segments[0].len();
+      segments[0] = data[..header].to_vec();
+      // the initialization segment ends at the first Cluster
+      if self.clusters.is_empty() {
+        segments.clear();
+      }
+      // nothing ends the last Cluster of unknown size in a Segment of unknown size
+      if !self.known && self.clusters.last().is_some_and(|cluster| !cluster.known) {
+        segments.pop();
+      }
+      (data, segments)
+    }
+  }
+
+  fn stream() -> impl Strategy<Value = Stream> {
+    let cluster = (
+      any::<bool>(),
+      any::<bool>(),
+      prop::collection::vec(prop::collection::vec(any::<u8>(), 0..200), 0..4),
+    )
+      .prop_map(|(known, cues, blocks)| Cluster { known, cues, blocks });
+    (any::<bool>(), 1usize..=8, prop::collection::vec(cluster, 0..6)).prop_map(|(known, size_len, clusters)| Stream {
+      known,
+      size_len,
+      clusters,
+    })
+  }
+
+  /// Feed the data in pieces of the given sizes, repeated until the end
+  fn slice_in_pieces(slicer: &mut WebmSlicer, data: &[u8], pieces: &[usize]) -> Result<Vec<Segment>, SliceError> {
+    let mut segments = vec![];
+    let mut rest = data;
+    for &piece in pieces.iter().cycle() {
+      if rest.is_empty() {
+        break;
+      }
+      let (head, tail) = rest.split_at(piece.min(rest.len()));
+      segments.extend(slicer.append(head)?);
+      rest = tail;
+    }
+    Ok(segments)
+  }
+
+  proptest! {
+    #[test]
+    fn slices_synthetic_stream(stream in stream(), pieces in prop::collection::vec(1usize..64, 1..16)) {
+      let (data, expected) = stream.build();
+      let segments = slice_in_pieces(&mut WebmSlicer::new(), &data, &pieces).unwrap();
+      let segments: Vec<_> = segments.into_iter().map(|segment| segment.data().to_vec()).collect();
+      prop_assert_eq!(segments, expected);
+    }
+
+    #[test]
+    fn never_panics(data in prop::collection::vec(any::<u8>(), 0..1024), pieces in prop::collection::vec(1usize..64, 1..16)) {
+      let mut slicer = WebmSlicer::new();
+      if let Err(err) = slice_in_pieces(&mut slicer, &data, &pieces) {
+        prop_assert_eq!(slicer.append(&[0x1a]), Err(err));
+      }
+    }
+
+    #[test]
+    fn never_panics_on_corrupted_stream(stream in stream(), index in any::<prop::sample::Index>(), byte in any::<u8>()) {
+      let (mut data, _) = stream.build();
+      let index = index.index(data.len());
+      data[index] = byte;
+      let _ = WebmSlicer::new().append(&data);
+    }
+  }
+
+  #[test]
+  fn detects_keyframes() {
+    let timestamp = element(ID_TIMESTAMP, &[0], 1);
+    let block = |flags: u8| element(ID_SIMPLE_BLOCK, &[0x81, 0x00, 0x00, flags, 0x55], 1);
+    let group = |reference: bool| {
+      let mut body = element(0xa1, &[0x81, 0x00, 0x00, 0x00, 0x55], 1);
+      if reference {
+        body.extend(element(ID_REFERENCE_BLOCK, &[0xff], 1));
+      }
+      element(ID_BLOCK_GROUP, &body, 1)
+    };
+
+    assert!(starts_with_keyframe(
+      &[timestamp.clone(), block(0x80), block(0x00)].concat()
+    ));
+    assert!(!starts_with_keyframe(
+      &[timestamp.clone(), block(0x00), block(0x80)].concat()
+    ));
+    assert!(starts_with_keyframe(&[timestamp.clone(), group(false)].concat()));
+    assert!(!starts_with_keyframe(&[timestamp.clone(), group(true)].concat()));
+    assert!(!starts_with_keyframe(&timestamp));
+  }
+
+  #[test]
+  fn rejects_invalid_streams() {
+    let cluster = element(ID_CLUSTER, &[], 1);
+    assert_eq!(
+      WebmSlicer::new().append(&cluster),
+      Err(SliceError::UnexpectedElement(ID_CLUSTER))
+    );
+    assert_eq!(WebmSlicer::new().append(&[0x00]), Err(SliceError::InvalidVarint));
+    assert_eq!(
+      WebmSlicer::new().append(&unknown(ID_EBML, &[], 1)),
+      Err(SliceError::UnknownSize(ID_EBML))
+    );
+
+    let stream = Stream {
+      known: true,
+      size_len: 1,
+      clusters: vec![],
+    };
+    let (data, _) = stream.build();
+    assert_eq!(
+      WebmSlicer::new().append(&[data, cluster].concat()),
+      Err(SliceError::TrailingData)
+    );
+  }
+
+  /// A few seconds of ffmpeg output, or `None` if ffmpeg is not installed
+  fn ffmpeg_output() -> Option<&'static [u8]> {
+    static OUTPUT: OnceLock<Option<Vec<u8>>> = OnceLock::new();
+    let output = OUTPUT.get_or_init(|| {
+      let output = Command::new("ffmpeg")
+        .args([
+          "-loglevel",
+          "error",
+          "-f",
+          "lavfi",
+          "-i",
+          "testsrc=size=320x240:rate=30",
+        ])
+        .args([
+          "-t",
+          "3",
+          "-c:v",
+          "libvpx",
+          "-deadline",
+          "realtime",
+          "-g",
+          "15",
+          "-f",
+          "webm",
+          "-",
+        ])
+        .output()
+        .ok()?;
+      output.status.success().then_some(output.stdout)
+    });
+    output.as_deref()
+  }
+
+  proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn slices_ffmpeg_output(pieces in prop::collection::vec(1usize..4096, 1..16)) {
+      let Some(data) = ffmpeg_output() else {
+        eprintln!("ffmpeg is not available, skipping");
+        return Ok(());
+      };
+      let segments = slice_in_pieces(&mut WebmSlicer::new(), data, &pieces).unwrap();
+      prop_assert!(segments.len() > 2);
+      prop_assert!(segments[1].is_keyframe());
+      let segments: Vec<_> = segments.into_iter().map(|segment| segment.data().to_vec()).collect();
+      prop_assert!(segments[0].starts_with(&id(ID_EBML)));
+      for segment in &segments[1..] {
+        prop_assert!(segment.windows(4).any(|window| window == id(ID_CLUSTER)));
+      }
+      prop_assert!(data.starts_with(&segments.concat()));
+    }
+  }
+}
@@ -0,0 +1,237 @@
+// Share one encoder per capture source between all the sessions watching it
+//
+// Each session gets the cached initialization segment and the media segments since the last keyframe first,
+// so that it can start decoding immediately, and then the live segments through its own bounded queue.
+// A session that falls behind skips segments until the next keyframe instead of slowing down the others, and
+// the keyframe is marked as following a gap.
+
+use std::{
+  collections::{HashMap, VecDeque},
+  io,
+  process::Stdio,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use tokio::{
+  io::AsyncReadExt,
+  process::Command,
+  sync::mpsc::{self, error::TrySendError},
+};
+
+use crate::{
+  capture::Capture,
+  encoder::Encoder,
+  slicer::{self, Container, Segment},
+};
+
+/// Segments queued for one session, beyond which it skips to the next keyframe
+const SESSION_QUEUE: usize = 4;
+/// Media segments cached for late joiners, in case keyframes stop coming
+const MAX_CACHED_SEGMENTS: usize = 64;
+/// How long an encoder keeps running without sessions, so that reconnecting clients start immediately
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Encoders by their ffmpeg command line
+#[derive(Default)]
+pub struct Streams {
+  streams: Mutex<HashMap<Vec<String>, Arc<Stream>>>,
+}
+
+impl Streams {
+  pub fn subscribe(&self, capture: &Capture, encoder: &Encoder) -> Subscription {
+    let args = encoder.command_args(capture);
+    let stream = self
+      .streams
+      .lock()
+      .unwrap()
+      .entry(args.clone())
+      .or_insert_with(|| Arc::new(Stream::new(args, encoder.container())))
+      .clone();
+    stream.subscribe()
+  }
+}
+
+pub struct Stream {
+  args: Vec<String>,
+  container: Container,
+  shared: Mutex<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+  running: bool,
+  init: Option<Arc<Segment>>,
+  /// Media segments since the last keyframe
+  cached: Vec<Arc<Segment>>,
+  sessions: Vec<Session>,
+  next_id: u64,
+  /// When the last session left
+  idle_since: Option<Instant>,
+}
+
+struct Session {
+  id: u64,
+  /// Segments, and whether segments were dropped before them
+  tx: mpsc::Sender<(Arc<Segment>, bool)>,
+  /// Waiting for a keyframe, which is the first segment of a session without cached ones
+  skipping: bool,
+  /// Whether segments were dropped since the last one that was sent
+  dropped: bool,
+}
+
+impl Stream {
+  fn new(args: Vec<String>, container: Container) -> Self {
+    Self {
+      args,
+      container,
+      shared: Mutex::default(),
+    }
+  }
+
+  fn subscribe(self: Arc<Self>) -> Subscription {
+    let mut shared = self.shared.lock().unwrap();
+    let (tx, rx) = mpsc::channel(SESSION_QUEUE);
+    let id = shared.next_id;
+    shared.next_id += 1;
+    // without cached media segments, the next one to decode from is the next keyframe
+    let skipping = shared.cached.is_empty();
+    shared.sessions.push(Session {
+      id,
+      tx,
+      skipping,
+      dropped: false,
+    });
+    shared.idle_since = None;
+
+    let backlog = shared
+      .init
+      .iter()
+      .chain(&shared.cached)
+      .map(|segment| (segment.clone(), false))
+      .collect();
+    if !shared.running {
+      shared.running = true;
+      tokio::spawn(self.clone().run());
+    }
+    drop(shared);
+
+    Subscription {
+      stream: self,
+      id,
+      backlog,
+      rx,
+    }
+  }
+
+  async fn run(self: Arc<Self>) {
+    if let Err(e) = self.encode().await {
+      eprintln!("Encoder stopped: {}", e);
+      self.shared.lock().unwrap().stop();
+    }
+  }
+
+  /// Run ffmpeg until it fails, or until nobody has been watching for a while
+  async fn encode(&self) -> io::Result<()> {
+    let mut ffmpeg = Command::new("ffmpeg")
+      .args(&self.args)
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::inherit())
+      .kill_on_drop(true)
+      .spawn()?;
+
+    let mut video = ffmpeg.stdout.take().unwrap();
+    let mut slicer = slicer::new(self.container);
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+      let n = video.read(&mut buf).await?;
+      if n == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "ffmpeg exited"));
+      }
+      let segments = slicer
+        .append(&buf[..n])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+      let mut shared = self.shared.lock().unwrap();
+      if shared.idle_since.is_some_and(|since| since.elapsed() > IDLE_TIMEOUT) {
+        shared.stop();
+        return Ok(());
+      }
+      for segment in segments {
+        shared.broadcast(Arc::new(segment));
+      }
+    }
+  }
+}
+
+impl Shared {
+  fn broadcast(&mut self, segment: Arc<Segment>) {
+    match &*segment {
+      Segment::Init(_) => {
+        self.init = Some(segment.clone());
+        self.cached.clear();
+      }
+      Segment::Media { keyframe: true, .. } => self.cached = vec![segment.clone()],
+      Segment::Media { .. } if self.cached.len() >= MAX_CACHED_SEGMENTS => self.cached.clear(),
+      Segment::Media { .. } if !self.cached.is_empty() => self.cached.push(segment.clone()),
+      Segment::Media { .. } => {}
+    }
+
+    self.sessions.retain_mut(|session| {
+      if session.skipping && !segment.is_keyframe() {
+        return true;
+      }
+      match session.tx.try_send((segment.clone(), session.dropped)) {
+        Ok(()) => {
+          session.skipping = false;
+          session.dropped = false;
+          true
+        }
+        Err(TrySendError::Full(_)) => {
+          session.skipping = true;
+          session.dropped = true;
+          true
+        }
+        Err(TrySendError::Closed(_)) => false,
+      }
+    });
+  }
+
+  /// Forget the encoder output, and end every subscription
+  fn stop(&mut self) {
+    self.running = false;
+    self.init = None;
+    self.cached.clear();
+    self.sessions.clear();
+  }
+}
+
+pub struct Subscription {
+  stream: Arc<Stream>,
+  id: u64,
+  /// Cached segments to send before the live ones
+  backlog: VecDeque<(Arc<Segment>, bool)>,
+  rx: mpsc::Receiver<(Arc<Segment>, bool)>,
+}
+
+impl Subscription {
+  /// Wait for the next segment and whether segments were dropped right before it, or `None` if the encoder stopped
+  pub async fn recv(&mut self) -> Option<(Arc<Segment>, bool)> {
+    match self.backlog.pop_front() {
+      Some(segment) => Some(segment),
+      None => self.rx.recv().await,
+    }
+  }
+}
+
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    let mut shared = self.stream.shared.lock().unwrap();
+    shared.sessions.retain(|session| session.id != self.id);
+    if shared.sessions.is_empty() {
+      shared.idle_since = Some(Instant::now());
+    }
+  }
+}
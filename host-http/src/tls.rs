@@ -0,0 +1,99 @@
+// Optional TLS, so that the page is a secure context on the iPad
+//
+// Without a certificate of its own, the host generates a self-signed one on the first run and keeps using it,
+// so that its fingerprint only has to be checked once.
+
+use std::{
+  fs,
+  io::{self, BufReader},
+  net::IpAddr,
+  path::{Path, PathBuf},
+};
+
+use rcgen::CertifiedKey;
+use sha2::{Digest, Sha256};
+
+use crate::config;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Tls {
+  /// Serve https and wss, with a self-signed certificate unless --cert and --key are given
+  #[arg(long)]
+  pub tls: bool,
+
+  /// PEM certificate chain to serve (implies --tls)
+  #[arg(long, requires = "key")]
+  pub cert: Option<PathBuf>,
+
+  /// PEM private key of the certificate (implies --tls)
+  #[arg(long, requires = "cert")]
+  pub key: Option<PathBuf>,
+}
+
+pub struct Identity {
+  pub cert: PathBuf,
+  pub key: PathBuf,
+  /// SHA-256 of the certificate, as shown by browsers
+  pub fingerprint: String,
+}
+
+impl Tls {
+  pub fn enabled(&self) -> bool {
+    self.tls || self.cert.is_some()
+  }
+
+  /// Find the certificate to serve, generating one if needed
+  pub fn identity(&self, host: IpAddr) -> io::Result<Identity> {
+    let (cert, key) = match (&self.cert, &self.key) {
+      (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+      _ => {
+        let dir = config::config_dir();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        if !cert.exists() || !key.exists() {
+          generate(&cert, &key, host)?;
+          println!("Generated a self-signed certificate in {}", dir.display());
+        }
+        (cert, key)
+      }
+    };
+    let fingerprint = fingerprint(&cert)?;
+    Ok(Identity { cert, key, fingerprint })
+  }
+}
+
+fn generate(cert_path: &Path, key_path: &Path, host: IpAddr) -> io::Result<()> {
+  let mut names = vec!["localhost".to_string()];
+  if !host.is_unspecified() {
+    names.push(host.to_string());
+  }
+  let CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(names).map_err(io::Error::other)?;
+
+  if let Some(dir) = cert_path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  write_private(key_path, key_pair.serialize_pem().as_bytes())?;
+  fs::write(cert_path, cert.pem())
+}
+
+/// Write a file that only the current user can read
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+  use std::io::Write;
+
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  options.open(path)?.write_all(data)
+}
+
+/// Fingerprint of the first certificate in the chain, e.g. `AB:CD:...`
+fn fingerprint(cert_path: &Path) -> io::Result<String> {
+  let mut reader = BufReader::new(fs::File::open(cert_path)?);
+  let cert = rustls_pemfile::certs(&mut reader)
+    .next()
+    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no certificate found"))??;
+
+  let hash = Sha256::digest(&cert);
+  let hex: Vec<String> = hash.iter().map(|byte| format!("{:02X}", byte)).collect();
+  Ok(hex.join(":"))
+}
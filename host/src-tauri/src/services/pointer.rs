@@ -1,292 +1,69 @@
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 
-use bitflags::bitflags;
-use serde::{Deserialize, Deserializer, Serialize};
+use input::{InputDevices, Mapping, PointerEvent, PressureCurve};
 use tauri::{
   async_runtime::Mutex,
   plugin::{Builder, TauriPlugin},
   Manager, Runtime, State,
 };
-use windows::Win32::{
-  Foundation::{HANDLE, HWND, POINT, RECT},
-  UI::{
-    Controls::{
-      CreateSyntheticPointerDevice, DestroySyntheticPointerDevice, HSYNTHETICPOINTERDEVICE, POINTER_FEEDBACK_NONE,
-      POINTER_TYPE_INFO, POINTER_TYPE_INFO_0,
-    },
-    Input::Pointer::{
-      InjectSyntheticPointerInput, POINTER_CHANGE_NONE, POINTER_FLAG_CANCELED, POINTER_FLAG_DOWN,
-      POINTER_FLAG_INCONTACT, POINTER_FLAG_INRANGE, POINTER_FLAG_PRIMARY, POINTER_FLAG_UP, POINTER_FLAG_UPDATE,
-      POINTER_INFO, POINTER_PEN_INFO, POINTER_TOUCH_INFO,
-    },
-    WindowsAndMessaging::{
-      PEN_FLAG_NONE, PEN_MASK_PRESSURE, PEN_MASK_ROTATION, PEN_MASK_TILT_X, PEN_MASK_TILT_Y, PT_MOUSE, PT_PEN,
-      PT_TOUCH, TOUCH_FLAG_NONE, TOUCH_MASK_CONTACTAREA, TOUCH_MASK_PRESSURE,
-    },
-  },
-};
-
-const MAX_CONTACTS: usize = 10;
-
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
-enum PointerEventType {
-  #[serde(rename = "down")]
-  Down,
-  #[serde(rename = "move")]
-  Move,
-  #[serde(rename = "up")]
-  Up,
-  #[serde(rename = "cancel")]
-  Cancel,
-}
-
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
-enum PointerType {
-  #[serde(rename = "mouse")]
-  Mouse,
-  #[serde(rename = "pen")]
-  Pen,
-  #[serde(rename = "touch")]
-  Touch,
-}
-
-bitflags! {
-  #[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
-  struct Button: u8 {
-      const NONE = 0b0000_0000;
-      const PRIMARY = 0b0000_0001;
-      const SECONDARY = 0b0000_0010;
-      const AUXILARY = 0b0000_0100;
-      const FOURTH = 0b0000_1000;
-      const FIFTH = 0b0001_0000;
-      const ERASER = 0b0010_0000;
-  }
-}
-
-fn button_from<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Button, D::Error> {
-  let bits: u8 = Deserialize::deserialize(deserializer)?;
-  Button::from_bits(bits).ok_or(serde::de::Error::custom("invalid button bits"))
-}
-
-#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
-struct PointerEvent {
-  #[serde(rename = "eventType")]
-  event_type: PointerEventType,
-  #[serde(rename = "pointerId")]
-  id: u32,
-  #[serde(rename = "pointerType")]
-  pointer_type: PointerType,
-  #[serde(rename = "isPrimary")]
-  is_primary: bool,
-  #[serde(rename = "normalizedX")]
-  x: f64,
-  #[serde(rename = "normalizedY")]
-  y: f64,
-  #[serde(deserialize_with = "button_from")]
-  button: Button,
-  #[serde(deserialize_with = "button_from")]
-  buttons: Button,
-  width: f64,
-  height: f64,
-  pressure: f64,
-  #[serde(rename = "tiltX")]
-  tilt_x: i32,
-  #[serde(rename = "tiltY")]
-  tilt_y: i32,
-  twist: u32,
-}
-
-impl From<PointerEvent> for Option<POINTER_TYPE_INFO> {
-  fn from(event: PointerEvent) -> Self {
-    let mut pointer_flags = match event.event_type {
-      PointerEventType::Down => POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT | POINTER_FLAG_DOWN,
-      PointerEventType::Move => POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT | POINTER_FLAG_UPDATE,
-      PointerEventType::Up => POINTER_FLAG_UP,
-      PointerEventType::Cancel => POINTER_FLAG_INRANGE | POINTER_FLAG_UPDATE | POINTER_FLAG_CANCELED,
-    };
-
-    let device_type = match event.pointer_type {
-      PointerType::Mouse => PT_MOUSE,
-      PointerType::Pen => PT_PEN,
-      PointerType::Touch => PT_TOUCH,
-    };
-
-    // TODO: Handle pointer buttons (e.g. eraser?).
-    //       The BUTTON_UP event should be handled as well
-    let button_change_type = POINTER_CHANGE_NONE;
-    // let button_change_type = match val.button {
-    //   Button::PRIMARY => POINTER_CHANGE_FIRSTBUTTON_DOWN,
-    //   Button::SECONDARY => POINTER_CHANGE_SECONDBUTTON_DOWN,
-    //   Button::AUXILARY => POINTER_CHANGE_THIRDBUTTON_DOWN,
-    //   Button::FOURTH => POINTER_CHANGE_FOURTHBUTTON_DOWN,
-    //   Button::FIFTH => POINTER_CHANGE_FIFTHBUTTON_DOWN,
-    //   Button::NONE => POINTER_CHANGE_NONE,
-    //   _ => POINTER_CHANGE_NONE,
-    // };
-
-    if event.is_primary {
-      pointer_flags |= POINTER_FLAG_PRIMARY;
-    }
-
-    let x = event.x * 1920.0;
-    let y = event.y * 1080.0;
-
-    let pressure = (event.pressure * 1024.0) as u32;
 
-    let pointer_info = POINTER_INFO {
-      pointerType: device_type,
-      pointerId: event.id,
-      frameId: 0,
-      pointerFlags: pointer_flags,
-      sourceDevice: HANDLE::default(),
-      hwndTarget: HWND::default(),
-      ptPixelLocation: POINT {
-        x: x as i32,
-        y: y as i32,
-      },
-      ptHimetricLocation: POINT::default(),
-      ptPixelLocationRaw: POINT {
-        x: x as i32,
-        y: y as i32,
-      },
-      ptHimetricLocationRaw: POINT::default(),
-      dwTime: 0,
-      historyCount: 1,
-      InputData: 0,
-      dwKeyStates: 0,
-      PerformanceCount: 0,
-      ButtonChangeType: button_change_type,
-    };
-
-    match device_type {
-      PT_TOUCH => {
-        let width_half = event.width / 2.0;
-        let height_half = event.height / 2.0;
-        let contact_area = RECT {
-          left: (x - width_half) as i32,
-          top: (y - height_half) as i32,
-          right: (x + width_half) as i32,
-          bottom: (y + height_half) as i32,
-        };
+// Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 
-        Some(POINTER_TYPE_INFO {
-          r#type: device_type,
-          Anonymous: POINTER_TYPE_INFO_0 {
-            touchInfo: POINTER_TOUCH_INFO {
-              pointerInfo: pointer_info,
-              touchFlags: TOUCH_FLAG_NONE,
-              touchMask: TOUCH_MASK_CONTACTAREA | TOUCH_MASK_PRESSURE,
-              rcContact: contact_area,
-              rcContactRaw: RECT::default(),
-              orientation: 0,
-              pressure,
-            },
-          },
-        })
-      }
-      PT_PEN => Some(POINTER_TYPE_INFO {
-        r#type: device_type,
-        Anonymous: POINTER_TYPE_INFO_0 {
-          penInfo: POINTER_PEN_INFO {
-            pointerInfo: pointer_info,
-            penFlags: PEN_FLAG_NONE,
-            penMask: PEN_MASK_PRESSURE | PEN_MASK_ROTATION | PEN_MASK_TILT_X | PEN_MASK_TILT_Y,
-            pressure,
-            rotation: event.twist,
-            tiltX: event.tilt_x,
-            tiltY: event.tilt_y,
-          },
-        },
-      }),
-      _ => None,
-    }
-  }
+/// Input devices, and the mapping from the client view onto the desktop for the events they inject
+struct Pointer {
+  devices: InputDevices,
+  mapping: Mapping,
 }
 
-struct PointerDevices {
-  touch: HSYNTHETICPOINTERDEVICE,
-  pen: HSYNTHETICPOINTERDEVICE,
-  touches: HashMap<u32, POINTER_TYPE_INFO>,
+#[tauri::command]
+async fn reset(state: State<'_, Arc<Mutex<Pointer>>>) -> Result<(), String> {
+  let mut state = state.lock().await;
+  state.devices.reset().map_err(|e| format!("{:?}", e))
 }
 
-impl Drop for PointerDevices {
-  fn drop(&mut self) {
-    unsafe {
-      DestroySyntheticPointerDevice(self.touch);
-      DestroySyntheticPointerDevice(self.pen);
-    }
-  }
+#[tauri::command]
+async fn inject(event: PointerEvent, state: State<'_, Arc<Mutex<Pointer>>>) -> Result<(), String> {
+  let mut state = state.lock().await;
+  let event = state.mapping.apply(&event);
+  state.devices.inject(event).map_err(|e| format!("{:?}", e))
 }
 
-impl PointerDevices {
-  fn new() -> windows::core::Result<Self> {
-    let touch = unsafe { CreateSyntheticPointerDevice(PT_TOUCH, MAX_CONTACTS as u32, POINTER_FEEDBACK_NONE)? };
-
-    let pen = unsafe { CreateSyntheticPointerDevice(PT_PEN, 1, POINTER_FEEDBACK_NONE)? };
-
-    Ok(PointerDevices {
-      touch,
-      pen,
-      touches: HashMap::new(),
-    })
-  }
-
-  fn inject(&mut self, event: PointerEvent) -> windows::core::Result<()> {
-    let info: POINTER_TYPE_INFO = match event.into() {
-      Some(info) => info,
-      None => return Ok(()),
-    };
-
-    if info.r#type == PT_TOUCH {
-      self.touches.insert(event.id, info);
-      let result = unsafe {
-        InjectSyntheticPointerInput(
-          self.touch,
-          self
-            .touches
-            .values()
-            .copied()
-            .collect::<Vec<POINTER_TYPE_INFO>>()
-            .as_slice(),
-        )
-      };
-      match event.event_type {
-        PointerEventType::Up | PointerEventType::Cancel => {
-          self.touches.remove(&event.id);
-        }
-        _ => {}
-      }
-      result?;
-    } else if info.r#type == PT_PEN {
-      unsafe { InjectSyntheticPointerInput(self.pen, &[info])? };
-    } else {
-      panic!("invalid pointer type");
-    }
-    Ok(())
-  }
+/// Size of the video element of the client in CSS pixels, which the stream is letterboxed in
+#[tauri::command]
+async fn viewport(width: f64, height: f64, state: State<'_, Arc<Mutex<Pointer>>>) -> Result<(), String> {
+  state.lock().await.mapping.view = Some((width, height));
+  Ok(())
 }
 
-// Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
-
+/// Curve for the pressure of pens, or none for the linear one
 #[tauri::command]
-async fn reset(state: State<'_, Arc<Mutex<PointerDevices>>>) -> Result<(), String> {
-  let mut state = state.lock().await;
-  state.touches.clear();
+async fn pressure(curve: Option<PressureCurve>, state: State<'_, Arc<Mutex<Pointer>>>) -> Result<(), String> {
+  let curve = curve.unwrap_or_default();
+  curve.validate()?;
+  state.lock().await.devices.set_pressure_curve(curve);
   Ok(())
 }
 
+/// Whether touches are rejected as palms while the pen is in use
 #[tauri::command]
-async fn inject(event: PointerEvent, state: State<'_, Arc<Mutex<PointerDevices>>>) -> Result<(), String> {
-  let mut state = state.lock().await;
-  state.inject(event).map_err(|e| format!("{:?}", e))
+async fn palm_rejection(enabled: bool, state: State<'_, Arc<Mutex<Pointer>>>) -> Result<(), String> {
+  state.lock().await.devices.set_palm_rejection(enabled);
+  Ok(())
 }
 
 pub fn init<R: Runtime>() -> TauriPlugin<R> {
   Builder::new("pointer")
-    .invoke_handler(tauri::generate_handler![reset, inject])
+    .invoke_handler(tauri::generate_handler![reset, inject, viewport, pressure, palm_rejection])
     .setup(|app| {
-      let devices = PointerDevices::new().expect("failed to create pointer devices");
-      app.manage(Arc::new(Mutex::new(devices)));
+      let backend = input::platform_backend()?;
+      let monitors = input::monitors()?;
+      let desktop = input::virtual_desktop(&monitors).ok_or("no monitors to inject input on")?;
+      // the screen share is of a whole monitor, which is most likely the primary one
+      let target = monitors[0].rect;
+      app.manage(Arc::new(Mutex::new(Pointer {
+        devices: InputDevices::new(backend),
+        mapping: Mapping::new(desktop, target),
+      })));
       Ok(())
     })
     .build()
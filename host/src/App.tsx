@@ -9,7 +9,7 @@ import {
   type SignalingMessage,
 } from '@remote-stylus/shared'
 import { useEffect, useState } from 'react'
-import { injectPointerEvent, resetPointerDevice } from './services/pointer'
+import { injectPointerEvent, resetPointerDevice, setViewport } from './services/pointer'
 
 const server = new SignalingServer()
 
@@ -140,6 +140,10 @@ export function Inner() {
       if (event.button < 0) event.button = 0 // TODO: move this to the client?
       injectPointerEvent(event)
     })
+    // the stream is letterboxed in the video element of the client, which pointer positions are relative to
+    peer.on('data:viewport', ({ width, height }: { width: number; height: number }) => {
+      setViewport(width, height)
+    })
   }
 
   async function onStream(peer: Peer, stream: MediaStream) {
@@ -8,3 +8,22 @@ export async function resetPointerDevice() {
 export async function injectPointerEvent(event: MsgpackPointerEventInfo) {
   await invoke('plugin:pointer|inject', { event })
 }
+
+export async function setViewport(width: number, height: number) {
+  await invoke('plugin:pointer|viewport', { width, height })
+}
+
+export interface PressureCurve {
+  gamma: number
+  bezier?: [number, number, number, number]
+  min: number
+  max: number
+}
+
+export async function setPressureCurve(curve: PressureCurve | null) {
+  await invoke('plugin:pointer|pressure', { curve })
+}
+
+export async function setPalmRejection(enabled: boolean) {
+  await invoke('plugin:pointer|palm_rejection', { enabled })
+}
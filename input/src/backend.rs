@@ -0,0 +1,103 @@
+use std::io;
+
+use crate::{KeyInput, MouseInput, PenInput, PointerEvent};
+
+/// A sink for injected input.
+///
+/// Pointer positions and contact sizes are normalized to the virtual desktop (0.0 - 1.0),
+/// i.e. the bounding box of all monitors, see `Mapping`.
+pub trait InputBackend: Send {
+  fn pen(&mut self, input: &PenInput) -> io::Result<()>;
+
+  /// Inject one frame containing every touch contact currently on the surface
+  fn touch(&mut self, contacts: &[PointerEvent]) -> io::Result<()>;
+
+  fn mouse(&mut self, input: &MouseInput) -> io::Result<()>;
+
+  fn key(&mut self, input: &KeyInput) -> io::Result<()>;
+}
+
+impl<B: InputBackend + ?Sized> InputBackend for Box<B> {
+  fn pen(&mut self, input: &PenInput) -> io::Result<()> {
+    (**self).pen(input)
+  }
+
+  fn touch(&mut self, contacts: &[PointerEvent]) -> io::Result<()> {
+    (**self).touch(contacts)
+  }
+
+  fn mouse(&mut self, input: &MouseInput) -> io::Result<()> {
+    (**self).mouse(input)
+  }
+
+  fn key(&mut self, input: &KeyInput) -> io::Result<()> {
+    (**self).key(input)
+  }
+}
+
+/// A backend that discards everything
+pub struct NullBackend;
+
+impl InputBackend for NullBackend {
+  fn pen(&mut self, _input: &PenInput) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn touch(&mut self, _contacts: &[PointerEvent]) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn mouse(&mut self, _input: &MouseInput) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn key(&mut self, _input: &KeyInput) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Record {
+  Pen(PenInput),
+  Touch(Vec<PointerEvent>),
+  Mouse(MouseInput),
+  Key(KeyInput),
+}
+
+/// A backend that keeps everything it receives, for tests and debugging
+#[derive(Default)]
+pub struct RecordingBackend {
+  pub records: Vec<Record>,
+}
+
+impl RecordingBackend {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn take(&mut self) -> Vec<Record> {
+    std::mem::take(&mut self.records)
+  }
+}
+
+impl InputBackend for RecordingBackend {
+  fn pen(&mut self, input: &PenInput) -> io::Result<()> {
+    self.records.push(Record::Pen(*input));
+    Ok(())
+  }
+
+  fn touch(&mut self, contacts: &[PointerEvent]) -> io::Result<()> {
+    self.records.push(Record::Touch(contacts.to_vec()));
+    Ok(())
+  }
+
+  fn mouse(&mut self, input: &MouseInput) -> io::Result<()> {
+    self.records.push(Record::Mouse(*input));
+    Ok(())
+  }
+
+  fn key(&mut self, input: &KeyInput) -> io::Result<()> {
+    self.records.push(Record::Key(*input));
+    Ok(())
+  }
+}
@@ -0,0 +1,223 @@
+// Pressure calibration from strokes drawn at a few levels of force
+//
+// The pressure of strokes drawn lightly, medium and firmly is recorded, and a curve is fitted that maps the
+// typical pressure of each level to evenly spaced output pressures. The fit is by least squares: `min` and `max`
+// on a grid, `gamma` in closed form for each pair of them, as it is linear in the logarithms, and finally the
+// Bézier control points by a pattern search for what the others leave.
+
+use serde::{Deserialize, Serialize};
+
+use crate::PressureCurve;
+
+/// Output pressure that each level is mapped to
+const TARGETS: [f64; 3] = [0.25, 0.5, 0.75];
+/// Fewest pressure samples for a level, which is about one short stroke
+const MIN_SAMPLES: usize = 20;
+/// Steps of the grid for `min` and `max`
+const GRID_STEPS: usize = 50;
+/// First and last step of the search for the Bézier control points
+const BEND_STEP: f64 = 0.25;
+const MIN_BEND_STEP: f64 = 1e-4;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StrokeLevel {
+  Light,
+  Medium,
+  Firm,
+}
+
+impl StrokeLevel {
+  fn index(self) -> usize {
+    match self {
+      StrokeLevel::Light => 0,
+      StrokeLevel::Medium => 1,
+      StrokeLevel::Firm => 2,
+    }
+  }
+}
+
+/// Pressures recorded for each level
+#[derive(Debug, Default, Clone)]
+pub struct Calibration {
+  samples: [Vec<f64>; 3],
+}
+
+impl Calibration {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Record the pressure of a pen touching the surface
+  pub fn record(&mut self, level: StrokeLevel, pressure: f64) {
+    // some pens report no pressure for the first contact
+    if pressure > 0.0 && pressure <= 1.0 {
+      self.samples[level.index()].push(pressure);
+    }
+  }
+
+  /// Median pressure of each level, from light to firm
+  pub fn levels(&self) -> Result<[f64; 3], String> {
+    let mut levels = [0.0; 3];
+    for (index, name) in ["light", "medium", "firm"].into_iter().enumerate() {
+      let mut samples = self.samples[index].clone();
+      if samples.len() < MIN_SAMPLES {
+        return Err(format!("too few {} strokes", name));
+      }
+      samples.sort_by(f64::total_cmp);
+      levels[index] = samples[samples.len() / 2];
+    }
+    if !(levels[0] < levels[1] && levels[1] < levels[2]) {
+      return Err(format!(
+        "the light, medium and firm strokes were not pressed apart: {:.3}, {:.3} and {:.3}",
+        levels[0], levels[1], levels[2]
+      ));
+    }
+    Ok(levels)
+  }
+}
+
+/// The curve that maps the median pressures of the levels closest to the targets
+pub fn fit_curve(levels: [f64; 3]) -> Result<PressureCurve, String> {
+  if !(0.0 < levels[0] && levels[0] < levels[1] && levels[1] < levels[2] && levels[2] <= 1.0) {
+    return Err(format!("the levels must increase between 0 and 1: {:?}", levels));
+  }
+  let mut best: Option<(f64, PressureCurve)> = None;
+  for i in 0..GRID_STEPS {
+    // the light strokes must not fall under the threshold
+    let min = levels[0] * i as f64 / GRID_STEPS as f64;
+    for j in 0..=GRID_STEPS {
+      let max = levels[2] + (1.0 - levels[2]) * j as f64 / GRID_STEPS as f64;
+      let logs = levels.map(|level| ((level - min) / (max - min)).ln());
+      let (mut product, mut square) = (0.0, 0.0);
+      for (log, target) in logs.iter().zip(TARGETS) {
+        product += log * target.ln();
+        square += log * log;
+      }
+      let curve = PressureCurve {
+        gamma: product / square,
+        bezier: None,
+        min,
+        max,
+      };
+      if curve.validate().is_err() {
+        continue;
+      }
+      let error = error(&curve, levels);
+      if best.as_ref().is_none_or(|(best, _)| error < *best) {
+        best = Some((error, curve));
+      }
+    }
+  }
+  let (error, curve) = best.ok_or_else(|| "no curve fits the strokes".to_string())?;
+  Ok(bend(curve, error, levels))
+}
+
+/// Add the Bézier that takes the curve closest to the targets, found by a pattern search from a straight line
+fn bend(curve: PressureCurve, straight: f64, levels: [f64; 3]) -> PressureCurve {
+  let mut points = [1.0 / 3.0, 1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0];
+  let mut best = straight;
+  let mut step = BEND_STEP;
+  while step > MIN_BEND_STEP {
+    let mut improved = false;
+    for index in 0..points.len() {
+      for delta in [step, -step] {
+        let mut candidate = points;
+        candidate[index] = (candidate[index] + delta).clamp(0.0, 1.0);
+        let error = error(
+          &PressureCurve {
+            bezier: Some(candidate),
+            ..curve
+          },
+          levels,
+        );
+        if error < best {
+          (best, points, improved) = (error, candidate, true);
+        }
+      }
+    }
+    if !improved {
+      step /= 2.0;
+    }
+  }
+  match best < straight {
+    true => PressureCurve {
+      bezier: Some(points),
+      ..curve
+    },
+    false => curve,
+  }
+}
+
+/// Squared distance of the levels from the targets
+fn error(curve: &PressureCurve, levels: [f64; 3]) -> f64 {
+  levels
+    .iter()
+    .zip(TARGETS)
+    .map(|(&level, target)| (curve.apply(level) - target).powi(2))
+    .sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Levels that the curve maps exactly to the targets
+  fn levels_of(gamma: f64) -> [f64; 3] {
+    TARGETS.map(|target| target.powf(1.0 / gamma))
+  }
+
+  #[test]
+  fn recovers_known_curves() {
+    for gamma in [1.0, 0.5, 2.0] {
+      let levels = levels_of(gamma);
+      let curve = fit_curve(levels).unwrap();
+      assert!((curve.gamma - gamma).abs() < 1e-6, "{} is not {}", curve.gamma, gamma);
+      for (level, target) in levels.into_iter().zip(TARGETS) {
+        let pressure = curve.apply(level);
+        assert!(
+          (pressure - target).abs() < 1e-6,
+          "{} maps to {}, not {}",
+          level,
+          pressure,
+          target
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn rejects_degenerate_levels() {
+    for levels in [
+      [0.5, 0.5, 0.5],
+      [0.3, 0.3, 0.6],
+      [0.3, 0.6, 0.6],
+      [0.7, 0.5, 0.3],
+      [0.3, 0.7, 0.5],
+      [0.0, 0.5, 0.7],
+      [0.3, 0.5, 1.5],
+      [f64::NAN, 0.5, 0.7],
+    ] {
+      assert!(fit_curve(levels).is_err(), "{:?} fits", levels);
+    }
+  }
+
+  #[test]
+  fn needs_enough_strokes_pressed_apart() {
+    let mut calibration = Calibration::new();
+    for (level, pressure) in [(StrokeLevel::Light, 0.2), (StrokeLevel::Medium, 0.5)] {
+      for _ in 0..MIN_SAMPLES {
+        calibration.record(level, pressure);
+      }
+    }
+    assert!(calibration.levels().is_err());
+    for _ in 0..MIN_SAMPLES {
+      calibration.record(StrokeLevel::Firm, 0.4);
+    }
+    assert!(calibration.levels().is_err());
+    for _ in 0..MIN_SAMPLES * 2 {
+      calibration.record(StrokeLevel::Firm, 0.8);
+    }
+    assert_eq!(calibration.levels(), Ok([0.2, 0.5, 0.8]));
+  }
+}
@@ -0,0 +1,324 @@
+// Touch contacts tracked across events, so that every frame describes the whole surface
+//
+// Each contact gets a slot for as long as it touches, and is reported with the slot as its id, so that backends
+// get small ids that stay the same for a contact and never exceed the device limit.
+
+use std::time::{Duration, Instant};
+
+use crate::{PointerEvent, PointerEventType, MAX_CONTACTS};
+
+/// How long the whole surface may go without events before it is assumed that the `up` of its contacts was lost.
+/// Browsers send no events for a finger that does not move, like a thumb resting while the other hand works, so a
+/// contact is never stale for as long as any other contact produces frames.
+const STALE_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy)]
+struct Contact {
+  /// Pointer id from the client
+  id: u32,
+  event: PointerEvent,
+}
+
+/// Keeps track of the touch contacts currently on the surface
+pub struct Contacts {
+  slots: [Option<Contact>; MAX_CONTACTS],
+  timeout: Duration,
+  /// When the last event arrived for any contact
+  updated: Option<Instant>,
+}
+
+impl Default for Contacts {
+  fn default() -> Self {
+    Self {
+      slots: [None; MAX_CONTACTS],
+      timeout: STALE_TIMEOUT,
+      updated: None,
+    }
+  }
+}
+
+impl Contacts {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Apply an event and return the frame to inject, which is empty if the event was ignored
+  pub fn update(&mut self, event: PointerEvent) -> Vec<PointerEvent> {
+    self.update_at(event, Instant::now())
+  }
+
+  /// Every contact in the frame is reported with the slot as its id, as `Move` unless it went down or up.
+  /// The frame also includes the contacts that were lifted with it, and as `Cancel` those that went stale while
+  /// the surface had no events at all.
+  pub fn update_at(&mut self, event: PointerEvent, now: Instant) -> Vec<PointerEvent> {
+    let mut changes: Vec<(usize, PointerEventType)> = vec![];
+    let idle = self
+      .updated
+      .is_some_and(|updated| now.saturating_duration_since(updated) > self.timeout);
+    self.updated = Some(now);
+    if idle {
+      for (slot, contact) in self.slots.iter().enumerate() {
+        if contact.is_some_and(|contact| contact.id != event.id) {
+          changes.push((slot, PointerEventType::Cancel));
+        }
+      }
+    }
+
+    let existing = self
+      .slots
+      .iter()
+      .position(|contact| contact.is_some_and(|c| c.id == event.id));
+    match (event.event_type, existing) {
+      (PointerEventType::Down, existing) => {
+        // a second `down` means that the `up` of the first one was lost, and a slot changes once per frame
+        if let Some(slot) = existing {
+          changes.push((slot, PointerEventType::Cancel));
+        }
+        let free = (0..MAX_CONTACTS).find(|&slot| self.slots[slot].is_none() && Some(slot) != existing);
+        let Some(slot) = free else {
+          // contacts beyond the device limit are refused, and so are their later events
+          return self.frame(changes);
+        };
+        self.slots[slot] = Some(Contact { id: event.id, event });
+        changes.push((slot, PointerEventType::Down));
+      }
+      (_, None) => {}
+      (event_type, Some(slot)) => {
+        self.slots[slot] = Some(Contact { id: event.id, event });
+        let event_type = match event_type {
+          PointerEventType::Leave => PointerEventType::Up,
+          event_type => event_type,
+        };
+        changes.push((slot, event_type));
+      }
+    }
+
+    self.frame(changes)
+  }
+
+  /// Lift every contact, returning the frame that cancels them
+  pub fn cancel_all(&mut self) -> Vec<PointerEvent> {
+    let changes = self
+      .slots
+      .iter()
+      .enumerate()
+      .filter(|(_, contact)| contact.is_some())
+      .map(|(slot, _)| (slot, PointerEventType::Cancel))
+      .collect();
+    self.frame(changes)
+  }
+
+  pub fn len(&self) -> usize {
+    self.slots.iter().flatten().count()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Build the frame for the changed slots, and free the slots of the contacts that were lifted
+  fn frame(&mut self, changes: Vec<(usize, PointerEventType)>) -> Vec<PointerEvent> {
+    if changes.is_empty() {
+      return vec![];
+    }
+
+    let mut frame = vec![];
+    let mut lifted = vec![];
+    for (slot, contact) in self.slots.iter().enumerate() {
+      let Some(contact) = contact else {
+        continue;
+      };
+      let event_type = match changes.iter().find(|(changed, _)| *changed == slot) {
+        Some(&(_, event_type)) => event_type,
+        None => PointerEventType::Move,
+      };
+      frame.push(PointerEvent {
+        event_type,
+        id: slot as u32,
+        ..contact.event
+      });
+      if let PointerEventType::Up | PointerEventType::Cancel = event_type {
+        lifted.push(slot);
+      }
+    }
+    for slot in lifted {
+      self.slots[slot] = None;
+    }
+    frame
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Button, PointerType};
+
+  use PointerEventType::{Cancel, Down, Move, Up};
+
+  fn touch(event_type: PointerEventType, id: u32, x: f64) -> PointerEvent {
+    PointerEvent {
+      event_type,
+      id,
+      pointer_type: PointerType::Touch,
+      is_primary: id == 0,
+      x,
+      y: 0.5,
+      button: Button::NONE,
+      buttons: Button::NONE,
+      width: 0.01,
+      height: 0.01,
+      pressure: 0.5,
+      tangential_pressure: 0.0,
+      tilt_x: 0.0,
+      tilt_y: 0.0,
+      twist: 0,
+    }
+  }
+
+  /// (type, slot, x) of each contact in a frame
+  fn summary(frame: &[PointerEvent]) -> Vec<(PointerEventType, u32, f64)> {
+    frame
+      .iter()
+      .map(|event| (event.event_type, event.id, event.x))
+      .collect()
+  }
+
+  #[test]
+  fn single_contact() {
+    let mut contacts = Contacts::new();
+    assert_eq!(summary(&contacts.update(touch(Down, 7, 0.1))), [(Down, 0, 0.1)]);
+    assert_eq!(summary(&contacts.update(touch(Move, 7, 0.2))), [(Move, 0, 0.2)]);
+    assert_eq!(summary(&contacts.update(touch(Up, 7, 0.3))), [(Up, 0, 0.3)]);
+    assert!(contacts.is_empty());
+  }
+
+  #[test]
+  fn unchanged_contacts_are_updates() {
+    let mut contacts = Contacts::new();
+    contacts.update(touch(Down, 7, 0.1));
+    assert_eq!(
+      summary(&contacts.update(touch(Down, 8, 0.5))),
+      [(Move, 0, 0.1), (Down, 1, 0.5)]
+    );
+    assert_eq!(
+      summary(&contacts.update(touch(Move, 8, 0.6))),
+      [(Move, 0, 0.1), (Move, 1, 0.6)]
+    );
+    assert_eq!(
+      summary(&contacts.update(touch(Up, 7, 0.2))),
+      [(Up, 0, 0.2), (Move, 1, 0.6)]
+    );
+    assert_eq!(summary(&contacts.update(touch(Move, 8, 0.7))), [(Move, 1, 0.7)]);
+  }
+
+  #[test]
+  fn slots_are_stable_and_reused() {
+    let mut contacts = Contacts::new();
+    contacts.update(touch(Down, 1, 0.1));
+    contacts.update(touch(Down, 2, 0.2));
+    contacts.update(touch(Up, 1, 0.1));
+    assert_eq!(
+      summary(&contacts.update(touch(Down, 3, 0.3))),
+      [(Down, 0, 0.3), (Move, 1, 0.2)]
+    );
+    assert_eq!(contacts.len(), 2);
+  }
+
+  #[test]
+  fn contacts_beyond_the_limit_are_refused() {
+    let mut contacts = Contacts::new();
+    for id in 0..MAX_CONTACTS as u32 {
+      contacts.update(touch(Down, id, 0.5));
+    }
+    assert!(contacts.update(touch(Down, 100, 0.5)).is_empty());
+    assert!(contacts.update(touch(Move, 100, 0.6)).is_empty());
+    assert!(contacts.update(touch(Up, 100, 0.6)).is_empty());
+    assert_eq!(contacts.len(), MAX_CONTACTS);
+
+    // the refused contact does not take a slot that frees up later
+    contacts.update(touch(Up, 0, 0.5));
+    assert!(contacts.update(touch(Move, 100, 0.7)).is_empty());
+  }
+
+  #[test]
+  fn events_of_unknown_contacts_are_ignored() {
+    let mut contacts = Contacts::new();
+    assert!(contacts.update(touch(Move, 1, 0.5)).is_empty());
+    assert!(contacts.update(touch(Up, 1, 0.5)).is_empty());
+    assert!(contacts.update(touch(Cancel, 1, 0.5)).is_empty());
+    assert!(contacts.is_empty());
+  }
+
+  #[test]
+  fn repeated_down_replaces_the_contact() {
+    let mut contacts = Contacts::new();
+    contacts.update(touch(Down, 1, 0.1));
+    assert_eq!(
+      summary(&contacts.update(touch(Down, 1, 0.9))),
+      [(Cancel, 0, 0.1), (Down, 1, 0.9)]
+    );
+    assert_eq!(summary(&contacts.update(touch(Move, 1, 0.8))), [(Move, 1, 0.8)]);
+    assert_eq!(contacts.len(), 1);
+  }
+
+  #[test]
+  fn stale_contacts_time_out() {
+    let start = Instant::now();
+    let mut contacts = Contacts::new();
+    contacts.update_at(touch(Down, 1, 0.1), start);
+    contacts.update_at(touch(Down, 2, 0.2), start + Duration::from_secs(1));
+
+    // the surface went without events for too long, except for the contact of the new event
+    let later = start + STALE_TIMEOUT + Duration::from_secs(2);
+    assert_eq!(
+      summary(&contacts.update_at(touch(Move, 2, 0.3), later)),
+      [(Cancel, 0, 0.1), (Move, 1, 0.3)]
+    );
+    assert_eq!(contacts.len(), 1);
+
+    // a contact that timed out is gone, even if its events come back
+    assert!(contacts.update_at(touch(Move, 1, 0.4), later).is_empty());
+  }
+
+  #[test]
+  fn moving_contacts_do_not_time_out() {
+    let start = Instant::now();
+    let mut contacts = Contacts::new();
+    contacts.update_at(touch(Down, 1, 0.1), start);
+    let later = start + STALE_TIMEOUT * 2;
+    assert_eq!(
+      summary(&contacts.update_at(touch(Move, 1, 0.2), later)),
+      [(Move, 0, 0.2)]
+    );
+  }
+
+  #[test]
+  fn resting_contacts_do_not_time_out_while_others_tap() {
+    let start = Instant::now();
+    let mut contacts = Contacts::new();
+    contacts.update_at(touch(Down, 1, 0.1), start);
+    // a tap every few seconds for far longer than the timeout, while the first finger rests without events
+    for tap in 1..(STALE_TIMEOUT.as_secs() * 3 / 5) {
+      let now = start + Duration::from_secs(5 * tap);
+      assert_eq!(
+        summary(&contacts.update_at(touch(Down, 2, 0.5), now)),
+        [(Move, 0, 0.1), (Down, 1, 0.5)]
+      );
+      assert_eq!(
+        summary(&contacts.update_at(touch(Up, 2, 0.5), now)),
+        [(Move, 0, 0.1), (Up, 1, 0.5)]
+      );
+    }
+    assert_eq!(contacts.len(), 1);
+  }
+
+  #[test]
+  fn cancel_all() {
+    let mut contacts = Contacts::new();
+    contacts.update(touch(Down, 1, 0.1));
+    contacts.update(touch(Down, 2, 0.2));
+    assert_eq!(summary(&contacts.cancel_all()), [(Cancel, 0, 0.1), (Cancel, 1, 0.2)]);
+    assert!(contacts.is_empty());
+    assert!(contacts.cancel_all().is_empty());
+  }
+}
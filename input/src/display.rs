@@ -0,0 +1,172 @@
+// Geometry of the monitors that make up the desktop
+//
+// Positions are in physical pixels of the virtual desktop, whose origin is the top-left corner of the primary
+// monitor, so monitors to the left of or above it have negative coordinates.
+
+use std::{io, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub struct Rect {
+  pub x: i32,
+  pub y: i32,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl Rect {
+  /// Smallest rectangle containing both
+  pub fn union(&self, other: &Rect) -> Rect {
+    let left = self.x.min(other.x);
+    let top = self.y.min(other.y);
+    let right = (self.x + self.width as i32).max(other.x + other.width as i32);
+    let bottom = (self.y + self.height as i32).max(other.y + other.height as i32);
+    Rect {
+      x: left,
+      y: top,
+      width: (right - left) as u32,
+      height: (bottom - top) as u32,
+    }
+  }
+}
+
+impl FromStr for Rect {
+  type Err = String;
+
+  /// Parse an X11-style geometry like `1920x1080+0+0`, where `-` starts a negative offset
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let error = || format!("expected WIDTHxHEIGHT+X+Y: {}", s);
+    let (width, rest) = s.split_once('x').ok_or_else(error)?;
+    let (height, offsets) = rest.split_at(rest.find(['+', '-']).ok_or_else(error)?);
+    let (x, y) = offsets.split_at(offsets[1..].find(['+', '-']).ok_or_else(error)? + 1);
+    let offset = |offset: &str| offset.strip_prefix('+').unwrap_or(offset).parse().map_err(|_| error());
+    Ok(Rect {
+      x: offset(x)?,
+      y: offset(y)?,
+      width: width.parse().map_err(|_| error())?,
+      height: height.parse().map_err(|_| error())?,
+    })
+  }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Monitor {
+  pub name: String,
+  pub rect: Rect,
+  pub primary: bool,
+}
+
+/// Monitors of this host, with the primary one first
+pub fn monitors() -> io::Result<Vec<Monitor>> {
+  #[cfg(windows)]
+  let monitors = crate::win32::monitors();
+  #[cfg(target_os = "linux")]
+  let monitors = xrandr_monitors();
+  #[cfg(not(any(windows, target_os = "linux")))]
+  let monitors: io::Result<Vec<Monitor>> = Err(io::Error::new(
+    io::ErrorKind::Unsupported,
+    "monitors are not supported on this platform",
+  ));
+
+  let mut monitors = monitors?;
+  monitors.sort_by_key(|monitor| !monitor.primary);
+  Ok(monitors)
+}
+
+/// Bounding box of all the monitors, which backends normalize positions to
+pub fn virtual_desktop(monitors: &[Monitor]) -> Option<Rect> {
+  monitors.iter().map(|monitor| monitor.rect).reduce(|a, b| a.union(&b))
+}
+
+/// Ask the X server, which also works for XWayland
+#[cfg(target_os = "linux")]
+fn xrandr_monitors() -> io::Result<Vec<Monitor>> {
+  let output = std::process::Command::new("xrandr").arg("--listmonitors").output()?;
+  if !output.status.success() {
+    return Err(io::Error::other("xrandr failed"));
+  }
+  Ok(
+    String::from_utf8_lossy(&output.stdout)
+      .lines()
+      .filter_map(parse_xrandr_monitor)
+      .collect(),
+  )
+}
+
+/// Parse a line like ` 0: +*eDP-1 1920/344x1080/194+0+0  eDP-1`
+#[cfg(target_os = "linux")]
+fn parse_xrandr_monitor(line: &str) -> Option<Monitor> {
+  let mut fields = line.split_whitespace();
+  fields.next()?.strip_suffix(':')?;
+  let flags = fields.next()?;
+  let geometry = fields.next()?;
+
+  let (width, rest) = geometry.split_once('/')?;
+  let (_, rest) = rest.split_once('x')?;
+  let (height, rest) = rest.split_once('/')?;
+  let mut offsets = rest.split('+').skip(1);
+  Some(Monitor {
+    name: flags.trim_start_matches(['+', '*']).to_string(),
+    rect: Rect {
+      x: offsets.next()?.parse().ok()?,
+      y: offsets.next()?.parse().ok()?,
+      width: width.parse().ok()?,
+      height: height.parse().ok()?,
+    },
+    primary: flags.contains('*'),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_geometries() {
+    let rect = |x, y, width, height| Rect { x, y, width, height };
+    assert_eq!("1920x1080+0+0".parse(), Ok(rect(0, 0, 1920, 1080)));
+    assert_eq!("1280x800+320+140".parse(), Ok(rect(320, 140, 1280, 800)));
+    assert_eq!("1920x1080-1920+0".parse(), Ok(rect(-1920, 0, 1920, 1080)));
+    assert_eq!("1920x1080+0-1080".parse(), Ok(rect(0, -1080, 1920, 1080)));
+  }
+
+  #[test]
+  fn rejects_invalid_geometries() {
+    for geometry in [
+      "",
+      "1920x1080",
+      "1920x1080+0",
+      "1920x1080+0+",
+      "x1080+0+0",
+      "-1x1080+0+0",
+      "1920+0+0",
+    ] {
+      assert!(geometry.parse::<Rect>().is_err(), "{}", geometry);
+    }
+  }
+
+  #[test]
+  fn unites_rects() {
+    let left = Rect {
+      x: -1920,
+      y: 0,
+      width: 1920,
+      height: 1080,
+    };
+    let right = Rect {
+      x: 0,
+      y: -200,
+      width: 2560,
+      height: 1440,
+    };
+    let desktop = Rect {
+      x: -1920,
+      y: -200,
+      width: 4480,
+      height: 1440,
+    };
+    assert_eq!(left.union(&right), desktop);
+    assert_eq!(virtual_desktop(&[]), None);
+  }
+}
@@ -0,0 +1,313 @@
+// Input events as they are sent by the client, and the platform-neutral inputs handed to backends.
+
+use std::f64::consts::FRAC_PI_2;
+
+use bitflags::bitflags;
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum PointerEventType {
+  #[serde(rename = "down")]
+  Down,
+  #[serde(rename = "move")]
+  Move,
+  #[serde(rename = "up")]
+  Up,
+  #[serde(rename = "cancel")]
+  Cancel,
+  /// A hovering pen went out of range without touching
+  #[serde(rename = "leave")]
+  Leave,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum PointerType {
+  #[serde(rename = "mouse")]
+  Mouse,
+  #[serde(rename = "pen")]
+  Pen,
+  #[serde(rename = "touch")]
+  Touch,
+}
+
+bitflags! {
+  #[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+  pub struct Button: u8 {
+      const NONE = 0b0000_0000;
+      const PRIMARY = 0b0000_0001;
+      const SECONDARY = 0b0000_0010;
+      const AUXILARY = 0b0000_0100;
+      const FOURTH = 0b0000_1000;
+      const FIFTH = 0b0001_0000;
+      const ERASER = 0b0010_0000;
+  }
+}
+
+fn button_from<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Button, D::Error> {
+  let bits: u8 = Deserialize::deserialize(deserializer)?;
+  Button::from_bits(bits).ok_or(serde::de::Error::custom("invalid button bits"))
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(from = "ClientPointerEvent")]
+pub struct PointerEvent {
+  #[serde(rename = "eventType")]
+  pub event_type: PointerEventType,
+  #[serde(rename = "pointerId")]
+  pub id: u32,
+  #[serde(rename = "pointerType")]
+  pub pointer_type: PointerType,
+  #[serde(rename = "isPrimary")]
+  pub is_primary: bool,
+  #[serde(rename = "normalizedX")]
+  pub x: f64,
+  #[serde(rename = "normalizedY")]
+  pub y: f64,
+  pub button: Button,
+  pub buttons: Button,
+  pub width: f64,
+  pub height: f64,
+  /// Between 0 and 1
+  pub pressure: f64,
+  /// Between -1 and 1, like the finger wheel of an airbrush
+  #[serde(rename = "tangentialPressure")]
+  pub tangential_pressure: f64,
+  /// Between -90 and 90 degrees, towards the right and towards the user
+  #[serde(rename = "tiltX")]
+  pub tilt_x: f64,
+  #[serde(rename = "tiltY")]
+  pub tilt_y: f64,
+  /// Clockwise rotation of the pen around its axis, between 0 and 359 degrees
+  pub twist: u32,
+}
+
+/// A `PointerEvent` as the client sends it, which may be out of range and may give the tilt by angles instead
+#[derive(Deserialize)]
+struct ClientPointerEvent {
+  #[serde(rename = "eventType")]
+  event_type: PointerEventType,
+  #[serde(rename = "pointerId")]
+  id: u32,
+  #[serde(rename = "pointerType")]
+  pointer_type: PointerType,
+  #[serde(rename = "isPrimary")]
+  is_primary: bool,
+  #[serde(rename = "normalizedX")]
+  x: f64,
+  #[serde(rename = "normalizedY")]
+  y: f64,
+  #[serde(deserialize_with = "button_from")]
+  button: Button,
+  #[serde(deserialize_with = "button_from")]
+  buttons: Button,
+  width: f64,
+  height: f64,
+  pressure: f64,
+  #[serde(rename = "tangentialPressure", default)]
+  tangential_pressure: f64,
+  #[serde(rename = "tiltX", default)]
+  tilt_x: f64,
+  #[serde(rename = "tiltY", default)]
+  tilt_y: f64,
+  #[serde(default)]
+  twist: u32,
+  /// Angle between the pen and the surface in radians, which is more precise than the tilt where it is given
+  #[serde(rename = "altitudeAngle", default)]
+  altitude_angle: Option<f64>,
+  /// Direction that the pen leans towards in radians, clockwise from the right
+  #[serde(rename = "azimuthAngle", default)]
+  azimuth_angle: Option<f64>,
+}
+
+impl From<ClientPointerEvent> for PointerEvent {
+  fn from(event: ClientPointerEvent) -> Self {
+    let (tilt_x, tilt_y) = match (event.altitude_angle, event.azimuth_angle) {
+      (Some(altitude), Some(azimuth)) if altitude.is_finite() && azimuth.is_finite() => tilt(altitude, azimuth),
+      _ => (event.tilt_x, event.tilt_y),
+    };
+    PointerEvent {
+      event_type: event.event_type,
+      id: event.id,
+      pointer_type: event.pointer_type,
+      is_primary: event.is_primary,
+      x: event.x,
+      y: event.y,
+      button: event.button,
+      buttons: event.buttons,
+      width: event.width,
+      height: event.height,
+      pressure: clamp(event.pressure, 0.0, 1.0),
+      tangential_pressure: clamp(event.tangential_pressure, -1.0, 1.0),
+      tilt_x: clamp(tilt_x, -90.0, 90.0),
+      tilt_y: clamp(tilt_y, -90.0, 90.0),
+      // the azimuth is the direction that the pen leans towards, which says nothing about how far it is turned
+      // around its own axis, so the twist is only ever what the client reports
+      twist: event.twist % 360,
+    }
+  }
+}
+
+/// Tilt in degrees for the altitude and azimuth of the pen in radians.
+///
+/// The pen points along (cos(alt) cos(az), cos(alt) sin(az), sin(alt)) on a surface whose z axis faces the user,
+/// so the plane through the y axis that contains it is at atan(cos(alt) cos(az) / sin(alt)) from the z axis,
+/// which is atan(cos(az) / tan(alt)), and likewise for y with sin(az). `atan2` keeps this defined for a pen that
+/// lies flat, where it gives 90 degrees towards where it points, or 0 in the direction it is square to.
+fn tilt(altitude: f64, azimuth: f64) -> (f64, f64) {
+  let altitude = clamp(altitude, 0.0, FRAC_PI_2).tan();
+  // cos(PI / 2) and sin(2 * PI) are not quite 0, which would tip a flat pen over by 90 degrees
+  let direction = |value: f64| match value.abs() < 1e-9 {
+    true => 0.0,
+    false => value,
+  };
+  (
+    direction(azimuth.cos()).atan2(altitude).to_degrees(),
+    direction(azimuth.sin()).atan2(altitude).to_degrees(),
+  )
+}
+
+/// Clamp a value from the client, taking anything that is not a number as 0
+fn clamp(value: f64, min: f64, max: f64) -> f64 {
+  match value.is_nan() {
+    true => 0.0,
+    false => value.clamp(min, max),
+  }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MouseButton {
+  Left,
+  Right,
+  Middle,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum MouseInput {
+  /// Move the cursor to the normalized position on the desktop
+  Move {
+    x: f64,
+    y: f64,
+  },
+  Button {
+    button: MouseButton,
+    down: bool,
+  },
+  /// Scroll by the given number of wheel notches (positive is right / down)
+  Wheel {
+    dx: f64,
+    dy: f64,
+  },
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PenTool {
+  Pen,
+  Eraser,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PenButton {
+  /// The tip (or the eraser end) touching the surface
+  Tip,
+  Barrel,
+}
+
+/// State of the pen after at most one button transition, see `Pen`
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct PenInput {
+  /// Position, pressure and tilt, whose `buttons` are superseded by the fields below
+  pub event: PointerEvent,
+  pub tool: PenTool,
+  /// Whether the pen is tracked, which is false only for the input of it leaving
+  pub in_range: bool,
+  pub contact: bool,
+  pub barrel: bool,
+  /// Button that went down (true) or up (false) with this input
+  pub change: Option<(PenButton, bool)>,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct KeyInput {
+  /// USB HID usage ID on the keyboard page (0x07)
+  pub usage: u16,
+  pub down: bool,
+  /// Auto-repeat of a key that is already down
+  pub repeat: bool,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::f64::consts::PI;
+
+  fn assert_tilt(actual: (f64, f64), expected: (f64, f64)) {
+    assert!(
+      (actual.0 - expected.0).abs() < 1e-9 && (actual.1 - expected.1).abs() < 1e-9,
+      "{:?} is not {:?}",
+      actual,
+      expected
+    );
+  }
+
+  fn client(tilt: (f64, f64), angles: Option<(f64, f64)>) -> ClientPointerEvent {
+    ClientPointerEvent {
+      event_type: PointerEventType::Move,
+      id: 1,
+      pointer_type: PointerType::Pen,
+      is_primary: true,
+      x: 0.5,
+      y: 0.5,
+      button: Button::NONE,
+      buttons: Button::NONE,
+      width: 1.0,
+      height: 1.0,
+      pressure: 0.5,
+      tangential_pressure: 0.0,
+      tilt_x: tilt.0,
+      tilt_y: tilt.1,
+      twist: 0,
+      altitude_angle: angles.map(|(altitude, _)| altitude),
+      azimuth_angle: angles.map(|(_, azimuth)| azimuth),
+    }
+  }
+
+  #[test]
+  fn upright_pens_do_not_tilt() {
+    for azimuth in [0.0, 1.0, PI, 5.0] {
+      assert_tilt(tilt(FRAC_PI_2, azimuth), (0.0, 0.0));
+    }
+  }
+
+  #[test]
+  fn flat_pens_tilt_towards_where_they_point() {
+    assert_tilt(tilt(0.0, 0.0), (90.0, 0.0));
+    assert_tilt(tilt(0.0, FRAC_PI_2), (0.0, 90.0));
+    assert_tilt(tilt(0.0, PI), (-90.0, 0.0));
+    assert_tilt(tilt(0.0, 2.0 * PI), (90.0, 0.0));
+  }
+
+  #[test]
+  fn leaning_pens_tilt_by_the_altitude() {
+    assert_tilt(tilt(PI / 4.0, 0.0), (45.0, 0.0));
+    assert_tilt(tilt(PI / 4.0, 3.0 * FRAC_PI_2), (0.0, -45.0));
+  }
+
+  #[test]
+  fn clamps_angles_out_of_range() {
+    // a NaN altitude counts as 0, which is a flat pen
+    assert_tilt(tilt(f64::NAN, 0.0), (90.0, 0.0));
+    assert_tilt(tilt(-1.0, 0.0), (90.0, 0.0));
+    assert_tilt(tilt(PI, 0.0), (0.0, 0.0));
+  }
+
+  #[test]
+  fn converts_client_tilt() {
+    let event = PointerEvent::from(client((10.0, 20.0), Some((0.0, 0.0))));
+    assert_tilt((event.tilt_x, event.tilt_y), (90.0, 0.0));
+    // angles that are not numbers leave the tilt as the client gives it
+    let event = PointerEvent::from(client((10.0, 20.0), Some((f64::NAN, 0.0))));
+    assert_tilt((event.tilt_x, event.tilt_y), (10.0, 20.0));
+    let event = PointerEvent::from(client((f64::NAN, 120.0), None));
+    assert_tilt((event.tilt_x, event.tilt_y), (0.0, 90.0));
+  }
+}
@@ -0,0 +1,538 @@
+// Touches recognized as gestures, for desktop applications that handle raw touch poorly
+//
+// Two fingers either pan, which scrolls, pinch, which zooms with ctrl+wheel, or rotate, which presses a key
+// chord for every step. Which one is decided once the fingers have moved far enough, and holds until one of
+// them lifts. A tap with three fingers presses another chord, and single fingers are ignored.
+
+use std::{
+  f64::consts::PI,
+  io,
+  time::{Duration, Instant},
+};
+
+use crate::{
+  keyboard::LEFT_CONTROL, Chord, Contacts, InputBackend, Keyboard, MouseInput, PointerEvent, PointerEventType,
+  MAX_CONTACTS,
+};
+
+/// Longest touch that still counts as a tap, from the first finger down to the last one up
+const TAP_TIMEOUT: Duration = Duration::from_millis(300);
+/// Farthest a finger may move during a tap, normalized to the desktop
+const TAP_SLOP: f64 = 0.01;
+/// How far the fingers move together before they pan, normalized to the desktop
+const PAN_SLOP: f64 = 0.02;
+/// How much the distance between the fingers changes before they pinch, as a log ratio
+const PINCH_SLOP: f64 = 0.15;
+/// How far the fingers turn before they rotate, in radians
+const ROTATE_SLOP: f64 = PI / 12.0;
+/// Wheel notches for panning across the whole desktop
+const PAN_SCALE: f64 = 30.0;
+/// Wheel notches for doubling the distance between the fingers is about 5
+const ZOOM_SCALE: f64 = 7.0;
+/// Rotation per chord press, in radians
+const ROTATE_STEP: f64 = PI / 12.0;
+
+/// Key chords that gestures press, none of which are pressed if unset
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct GestureKeys {
+  pub rotate_clockwise: Option<Chord>,
+  pub rotate_counterclockwise: Option<Chord>,
+  pub three_finger_tap: Option<Chord>,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Gesture {
+  /// Not moved far enough to tell
+  Pending,
+  Pan,
+  Pinch,
+  Rotate,
+}
+
+/// Two fingers on the surface
+#[derive(Debug, Clone, Copy)]
+struct Pair {
+  center: (f64, f64),
+  distance: f64,
+  /// Direction from the first finger to the second, clockwise on the desktop
+  angle: f64,
+}
+
+impl Pair {
+  fn of(a: &PointerEvent, b: &PointerEvent) -> Self {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    Self {
+      center: ((a.x + b.x) / 2.0, (a.y + b.y) / 2.0),
+      distance: dx.hypot(dy).max(f64::EPSILON),
+      angle: dy.atan2(dx),
+    }
+  }
+
+  /// Clockwise turn from `other` to this pair, between -PI and PI
+  fn turn(&self, other: &Pair) -> f64 {
+    let turn = (self.angle - other.angle) % (2.0 * PI);
+    match turn {
+      turn if turn > PI => turn - 2.0 * PI,
+      turn if turn < -PI => turn + 2.0 * PI,
+      turn => turn,
+    }
+  }
+}
+
+/// Two fingers from the moment they are the only ones down
+#[derive(Debug)]
+struct Motion {
+  gesture: Gesture,
+  start: Pair,
+  last: Pair,
+  /// Rotation that did not make up a whole step yet
+  turned: f64,
+}
+
+/// Touches from the first finger down to the last one up
+#[derive(Debug)]
+struct Sequence {
+  started: Instant,
+  /// Most fingers on the surface at once
+  fingers: usize,
+  /// Whether any finger moved too far for a tap
+  moved: bool,
+  cancelled: bool,
+}
+
+pub struct Gestures {
+  keys: GestureKeys,
+  contacts: Contacts,
+  /// Where each finger went down
+  starts: [Option<(f64, f64)>; MAX_CONTACTS],
+  sequence: Option<Sequence>,
+  motion: Option<Motion>,
+  /// Whether control is held for a pinch
+  zooming: bool,
+}
+
+impl Default for Gestures {
+  fn default() -> Self {
+    Self {
+      keys: GestureKeys::default(),
+      contacts: Contacts::new(),
+      starts: [None; MAX_CONTACTS],
+      sequence: None,
+      motion: None,
+      zooming: false,
+    }
+  }
+}
+
+impl Gestures {
+  pub fn new(keys: GestureKeys) -> Self {
+    Self {
+      keys,
+      ..Default::default()
+    }
+  }
+
+  pub fn set_keys(&mut self, keys: GestureKeys) {
+    self.keys = keys;
+  }
+
+  /// Keys are pressed through the `keyboard`, so that they leave the keys held by the client down
+  pub fn handle(
+    &mut self,
+    event: PointerEvent,
+    keyboard: &mut Keyboard,
+    backend: &mut impl InputBackend,
+  ) -> io::Result<()> {
+    self.handle_at(event, Instant::now(), keyboard, backend)
+  }
+
+  pub fn handle_at(
+    &mut self,
+    event: PointerEvent,
+    now: Instant,
+    keyboard: &mut Keyboard,
+    backend: &mut impl InputBackend,
+  ) -> io::Result<()> {
+    let frame = self.contacts.update_at(event, now);
+    if frame.is_empty() {
+      return Ok(());
+    }
+
+    let sequence = self.sequence.get_or_insert(Sequence {
+      started: now,
+      fingers: 0,
+      moved: false,
+      cancelled: false,
+    });
+    sequence.fingers = sequence.fingers.max(frame.len());
+    for contact in &frame {
+      let slot = contact.id as usize;
+      let start = *self.starts[slot].get_or_insert((contact.x, contact.y));
+      if (contact.x - start.0).hypot(contact.y - start.1) > TAP_SLOP {
+        sequence.moved = true;
+      }
+      match contact.event_type {
+        PointerEventType::Up | PointerEventType::Leave => self.starts[slot] = None,
+        PointerEventType::Cancel => {
+          self.starts[slot] = None;
+          sequence.cancelled = true;
+        }
+        PointerEventType::Down | PointerEventType::Move => {}
+      }
+    }
+
+    // a gesture holds until the pair of fingers changes
+    let touching =
+      |contact: &PointerEvent| matches!(contact.event_type, PointerEventType::Down | PointerEventType::Move);
+    match frame.as_slice() {
+      [a, b] if touching(a) && touching(b) => {
+        let pair = Pair::of(a, b);
+        let changed = [a, b]
+          .iter()
+          .any(|contact| contact.event_type == PointerEventType::Down);
+        match (&self.motion, changed) {
+          (Some(_), false) => self.step(pair, keyboard, backend)?,
+          _ => {
+            self.end_motion(keyboard, backend)?;
+            self.motion = Some(Motion {
+              gesture: Gesture::Pending,
+              start: pair,
+              last: pair,
+              turned: 0.0,
+            });
+          }
+        }
+      }
+      _ => self.end_motion(keyboard, backend)?,
+    }
+
+    if self.starts.iter().any(Option::is_some) {
+      return Ok(());
+    }
+    let Some(sequence) = self.sequence.take() else {
+      return Ok(());
+    };
+    let tap = !sequence.moved && !sequence.cancelled && now.duration_since(sequence.started) <= TAP_TIMEOUT;
+    match (tap, sequence.fingers, &self.keys.three_finger_tap) {
+      (true, 3, Some(chord)) => keyboard.tap(chord, backend),
+      _ => Ok(()),
+    }
+  }
+
+  /// Lift every finger and release control if a pinch holds it
+  pub fn release(&mut self, keyboard: &mut Keyboard, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.contacts.cancel_all();
+    self.starts = [None; MAX_CONTACTS];
+    self.sequence = None;
+    self.end_motion(keyboard, backend)
+  }
+
+  /// Recognize or continue the gesture with the new position of the fingers
+  fn step(&mut self, pair: Pair, keyboard: &mut Keyboard, backend: &mut impl InputBackend) -> io::Result<()> {
+    let Some(motion) = &mut self.motion else {
+      return Ok(());
+    };
+    let last = std::mem::replace(&mut motion.last, pair);
+    match motion.gesture {
+      Gesture::Pending => {
+        motion.gesture = recognize(&motion.start, &pair);
+        if motion.gesture == Gesture::Pending {
+          return Ok(());
+        }
+        // the movement that it took to recognize the gesture is part of it
+        motion.last = motion.start;
+        self.step(pair, keyboard, backend)
+      }
+      // the content follows the fingers, so moving them up scrolls down
+      Gesture::Pan => backend.mouse(&MouseInput::Wheel {
+        dx: (last.center.0 - pair.center.0) * PAN_SCALE,
+        dy: (last.center.1 - pair.center.1) * PAN_SCALE,
+      }),
+      // spreading the fingers zooms in, like scrolling up
+      Gesture::Pinch => {
+        if !self.zooming {
+          self.zooming = true;
+          keyboard.hold(LEFT_CONTROL, backend)?;
+        }
+        backend.mouse(&MouseInput::Wheel {
+          dx: 0.0,
+          dy: -(pair.distance / last.distance).ln() * ZOOM_SCALE,
+        })
+      }
+      Gesture::Rotate => {
+        motion.turned += pair.turn(&last);
+        while motion.turned.abs() >= ROTATE_STEP {
+          let chord = match motion.turned > 0.0 {
+            true => &self.keys.rotate_clockwise,
+            false => &self.keys.rotate_counterclockwise,
+          };
+          motion.turned -= ROTATE_STEP.copysign(motion.turned);
+          if let Some(chord) = chord {
+            keyboard.tap(chord, backend)?;
+          }
+        }
+        Ok(())
+      }
+    }
+  }
+
+  fn end_motion(&mut self, keyboard: &mut Keyboard, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.motion = None;
+    if !self.zooming {
+      return Ok(());
+    }
+    self.zooming = false;
+    keyboard.unhold(LEFT_CONTROL, backend)
+  }
+}
+
+/// Which gesture the fingers make, if they moved far enough from where they started
+fn recognize(start: &Pair, pair: &Pair) -> Gesture {
+  let (dx, dy) = (pair.center.0 - start.center.0, pair.center.1 - start.center.1);
+  if (pair.distance / start.distance).ln().abs() > PINCH_SLOP {
+    Gesture::Pinch
+  } else if pair.turn(start).abs() > ROTATE_SLOP {
+    Gesture::Rotate
+  } else if dx.hypot(dy) > PAN_SLOP {
+    Gesture::Pan
+  } else {
+    Gesture::Pending
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Button, KeyEvent, KeyEventType, KeyInput, Modifiers, PointerType, Record, RecordingBackend};
+
+  use PointerEventType::{Down, Move, Up};
+
+  const LEFT: (f64, f64) = (0.4, 0.5);
+  const RIGHT: (f64, f64) = (0.6, 0.5);
+
+  /// Gestures fed by touches that each come 10ms after the last one
+  struct Surface {
+    gestures: Gestures,
+    keyboard: Keyboard,
+    backend: RecordingBackend,
+    now: Instant,
+    fingers: [(f64, f64); 2],
+  }
+
+  impl Surface {
+    fn new(keys: GestureKeys) -> Self {
+      Self {
+        gestures: Gestures::new(keys),
+        keyboard: Keyboard::default(),
+        backend: RecordingBackend::new(),
+        now: Instant::now(),
+        fingers: [LEFT, RIGHT],
+      }
+    }
+
+    fn touch(&mut self, event_type: PointerEventType, id: u32, (x, y): (f64, f64)) {
+      self.now += Duration::from_millis(10);
+      let event = PointerEvent {
+        event_type,
+        id,
+        pointer_type: PointerType::Touch,
+        is_primary: id == 0,
+        x,
+        y,
+        button: Button::NONE,
+        buttons: Button::NONE,
+        width: 0.01,
+        height: 0.01,
+        pressure: 0.5,
+        tangential_pressure: 0.0,
+        tilt_x: 0.0,
+        tilt_y: 0.0,
+        twist: 0,
+      };
+      let Self {
+        gestures,
+        keyboard,
+        backend,
+        now,
+        ..
+      } = self;
+      gestures.handle_at(event, *now, keyboard, backend).unwrap();
+    }
+
+    fn key(&mut self, event_type: KeyEventType, modifiers: Modifiers) {
+      let event = KeyEvent {
+        event_type,
+        code: "ControlLeft".to_string(),
+        key: "Control".to_string(),
+        modifiers,
+        repeat: false,
+      };
+      self.keyboard.handle(&event, &mut self.backend).unwrap();
+    }
+
+    fn down(&mut self) {
+      self.touch(Down, 0, self.fingers[0]);
+      self.touch(Down, 1, self.fingers[1]);
+    }
+
+    /// Move both fingers to their new positions in small steps, one finger after the other
+    fn slide(&mut self, to: impl Fn(f64) -> [(f64, f64); 2]) {
+      const STEPS: usize = 20;
+      for step in 1..=STEPS {
+        let fingers = to(step as f64 / STEPS as f64);
+        for (id, finger) in fingers.into_iter().enumerate() {
+          self.fingers[id] = finger;
+          self.touch(Move, id as u32, finger);
+        }
+      }
+    }
+
+    fn up(&mut self) {
+      self.touch(Up, 0, self.fingers[0]);
+      self.touch(Up, 1, self.fingers[1]);
+    }
+  }
+
+  fn key(usage: u16, down: bool) -> Record {
+    Record::Key(KeyInput {
+      usage,
+      down,
+      repeat: false,
+    })
+  }
+
+  /// Sum of the wheel notches, and the records that are no wheel
+  fn wheel(records: Vec<Record>) -> ((f64, f64), Vec<Record>) {
+    let mut notches = (0.0, 0.0);
+    let mut others = Vec::new();
+    for record in records {
+      match record {
+        Record::Mouse(MouseInput::Wheel { dx, dy }) => notches = (notches.0 + dx, notches.1 + dy),
+        record => others.push(record),
+      }
+    }
+    (notches, others)
+  }
+
+  fn assert_near(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-9, "{} is not {}", actual, expected);
+  }
+
+  #[test]
+  fn scrolls_when_panning() {
+    let mut surface = Surface::new(GestureKeys::default());
+    surface.down();
+    surface.slide(|t| [(LEFT.0, LEFT.1 - 0.2 * t), (RIGHT.0, RIGHT.1 - 0.2 * t)]);
+    surface.up();
+
+    // the content follows the fingers, so moving them up scrolls down
+    let ((dx, dy), others) = wheel(surface.backend.take());
+    assert_near(dx, 0.0);
+    assert_near(dy, 0.2 * PAN_SCALE);
+    assert_eq!(others, []);
+  }
+
+  #[test]
+  fn zooms_with_control_when_pinching() {
+    let mut surface = Surface::new(GestureKeys::default());
+    surface.down();
+    surface.slide(|t| [(LEFT.0 - 0.1 * t, LEFT.1), (RIGHT.0 + 0.1 * t, RIGHT.1)]);
+    let records = surface.backend.take();
+    assert_eq!(records[0], key(LEFT_CONTROL, true));
+    // doubling the distance between the fingers zooms in
+    let ((dx, dy), others) = wheel(records[1..].to_vec());
+    assert_near(dx, 0.0);
+    assert_near(dy, -2f64.ln() * ZOOM_SCALE);
+    assert_eq!(others, []);
+
+    surface.touch(Up, 0, surface.fingers[0]);
+    assert_eq!(surface.backend.take(), [key(LEFT_CONTROL, false)]);
+    surface.touch(Up, 1, surface.fingers[1]);
+    assert_eq!(surface.backend.take(), []);
+  }
+
+  #[test]
+  fn pinching_leaves_the_control_of_the_client_down() {
+    let mut surface = Surface::new(GestureKeys::default());
+    surface.key(KeyEventType::Down, Modifiers::CONTROL);
+    assert_eq!(surface.backend.take(), [key(LEFT_CONTROL, true)]);
+
+    surface.down();
+    surface.slide(|t| [(LEFT.0 - 0.1 * t, LEFT.1), (RIGHT.0 + 0.1 * t, RIGHT.1)]);
+    surface.up();
+    let (_, others) = wheel(surface.backend.take());
+    assert_eq!(others, []);
+
+    // and the other way around, the pinch holds control until it ends
+    surface.down();
+    surface.slide(|t| [(0.3 + 0.1 * t, LEFT.1), (0.7 - 0.1 * t, RIGHT.1)]);
+    surface.key(KeyEventType::Up, Modifiers::NONE);
+    let (_, others) = wheel(surface.backend.take());
+    assert_eq!(others, []);
+    surface.up();
+    assert_eq!(surface.backend.take(), [key(LEFT_CONTROL, false)]);
+  }
+
+  #[test]
+  fn presses_a_chord_for_every_step_of_a_rotation() {
+    let mut surface = Surface::new(GestureKeys {
+      rotate_clockwise: Some("r".parse().unwrap()),
+      rotate_counterclockwise: Some("shift+r".parse().unwrap()),
+      three_finger_tap: None,
+    });
+    const R: u16 = 0x15;
+    const SHIFT: u16 = 0xe1;
+    let turned = |angle: f64| {
+      let (dx, dy) = (0.1 * angle.cos(), 0.1 * angle.sin());
+      [(0.5 - dx, 0.5 - dy), (0.5 + dx, 0.5 + dy)]
+    };
+
+    // clockwise on the desktop, where y points down, and a bit more than four steps
+    surface.down();
+    surface.slide(|t| turned(4.5 * ROTATE_STEP * t));
+    surface.up();
+    assert_eq!(surface.backend.take(), vec![[key(R, true), key(R, false)]; 4].concat());
+
+    surface.fingers = turned(0.0);
+    surface.down();
+    surface.slide(|t| turned(-2.5 * ROTATE_STEP * t));
+    surface.up();
+    let shift_r = [key(SHIFT, true), key(R, true), key(R, false), key(SHIFT, false)];
+    assert_eq!(surface.backend.take(), vec![shift_r; 2].concat());
+  }
+
+  #[test]
+  fn presses_a_chord_for_a_three_finger_tap() {
+    let mut surface = Surface::new(GestureKeys {
+      rotate_clockwise: None,
+      rotate_counterclockwise: None,
+      three_finger_tap: Some("ctrl+z".parse().unwrap()),
+    });
+    let undo = [
+      key(LEFT_CONTROL, true),
+      key(0x1d, true),
+      key(0x1d, false),
+      key(LEFT_CONTROL, false),
+    ];
+
+    for id in 0..3 {
+      surface.touch(Down, id, (0.4 + 0.1 * id as f64, 0.5));
+    }
+    for id in 0..3 {
+      surface.touch(Up, id, (0.4 + 0.1 * id as f64, 0.5));
+    }
+    assert_eq!(surface.backend.take(), undo);
+
+    // a slow tap does nothing, and neither does a tap with two fingers
+    for id in 0..3 {
+      surface.touch(Down, id, (0.4 + 0.1 * id as f64, 0.5));
+    }
+    surface.now += TAP_TIMEOUT;
+    for id in 0..3 {
+      surface.touch(Up, id, (0.4 + 0.1 * id as f64, 0.5));
+    }
+    surface.down();
+    surface.up();
+    assert_eq!(surface.backend.take(), []);
+  }
+}
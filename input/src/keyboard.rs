@@ -0,0 +1,757 @@
+// Keyboard events received from the client, translated to USB HID usages
+//
+// https://www.w3.org/TR/uievents-code/
+// https://usb.org/sites/default/files/hut1_5.pdf (Keyboard/Keypad Page 0x07)
+
+use std::{collections::HashMap, io, str::FromStr};
+
+use bitflags::bitflags;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{InputBackend, KeyInput};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum KeyEventType {
+  #[serde(rename = "down")]
+  Down,
+  #[serde(rename = "up")]
+  Up,
+}
+
+bitflags! {
+  #[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+  pub struct Modifiers: u8 {
+      const NONE = 0b0000_0000;
+      const SHIFT = 0b0000_0001;
+      const CONTROL = 0b0000_0010;
+      const ALT = 0b0000_0100;
+      const META = 0b0000_1000;
+  }
+}
+
+fn modifiers_from<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Modifiers, D::Error> {
+  let bits: u8 = Deserialize::deserialize(deserializer)?;
+  Modifiers::from_bits(bits).ok_or(serde::de::Error::custom("invalid modifier bits"))
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct KeyEvent {
+  #[serde(rename = "eventType")]
+  pub event_type: KeyEventType,
+  /// Physical key, e.g. `KeyA` (may be empty for software keyboards)
+  pub code: String,
+  /// Character or named key produced by the client's layout, e.g. `a` or `Enter`
+  pub key: String,
+  #[serde(default, deserialize_with = "modifiers_from")]
+  pub modifiers: Modifiers,
+  #[serde(default)]
+  pub repeat: bool,
+}
+
+/// Keyboard layout of the host, used when the client does not report the physical key
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Layout {
+  #[default]
+  Us,
+  Jis,
+}
+
+impl FromStr for Layout {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "us" => Ok(Layout::Us),
+      "jis" => Ok(Layout::Jis),
+      _ => Err(format!("unknown keyboard layout: {}", s)),
+    }
+  }
+}
+
+pub(crate) const LEFT_CONTROL: u16 = 0xe0;
+const LEFT_SHIFT: u16 = 0xe1;
+const LEFT_ALT: u16 = 0xe2;
+const LEFT_META: u16 = 0xe3;
+const RIGHT_CONTROL: u16 = 0xe4;
+const RIGHT_SHIFT: u16 = 0xe5;
+const RIGHT_ALT: u16 = 0xe6;
+const RIGHT_META: u16 = 0xe7;
+
+const MODIFIER_KEYS: [(Modifiers, u16, u16); 4] = [
+  (Modifiers::SHIFT, LEFT_SHIFT, RIGHT_SHIFT),
+  (Modifiers::CONTROL, LEFT_CONTROL, RIGHT_CONTROL),
+  (Modifiers::ALT, LEFT_ALT, RIGHT_ALT),
+  (Modifiers::META, LEFT_META, RIGHT_META),
+];
+
+/// Translate a DOM `code` to a HID usage
+pub fn usage_from_code(code: &str) -> Option<u16> {
+  let usage = match code {
+    "KeyA" => 0x04,
+    "KeyB" => 0x05,
+    "KeyC" => 0x06,
+    "KeyD" => 0x07,
+    "KeyE" => 0x08,
+    "KeyF" => 0x09,
+    "KeyG" => 0x0a,
+    "KeyH" => 0x0b,
+    "KeyI" => 0x0c,
+    "KeyJ" => 0x0d,
+    "KeyK" => 0x0e,
+    "KeyL" => 0x0f,
+    "KeyM" => 0x10,
+    "KeyN" => 0x11,
+    "KeyO" => 0x12,
+    "KeyP" => 0x13,
+    "KeyQ" => 0x14,
+    "KeyR" => 0x15,
+    "KeyS" => 0x16,
+    "KeyT" => 0x17,
+    "KeyU" => 0x18,
+    "KeyV" => 0x19,
+    "KeyW" => 0x1a,
+    "KeyX" => 0x1b,
+    "KeyY" => 0x1c,
+    "KeyZ" => 0x1d,
+    "Digit1" => 0x1e,
+    "Digit2" => 0x1f,
+    "Digit3" => 0x20,
+    "Digit4" => 0x21,
+    "Digit5" => 0x22,
+    "Digit6" => 0x23,
+    "Digit7" => 0x24,
+    "Digit8" => 0x25,
+    "Digit9" => 0x26,
+    "Digit0" => 0x27,
+    "Enter" => 0x28,
+    "Escape" => 0x29,
+    "Backspace" => 0x2a,
+    "Tab" => 0x2b,
+    "Space" => 0x2c,
+    "Minus" => 0x2d,
+    "Equal" => 0x2e,
+    "BracketLeft" => 0x2f,
+    "BracketRight" => 0x30,
+    "Backslash" => 0x31,
+    "IntlHash" => 0x32,
+    "Semicolon" => 0x33,
+    "Quote" => 0x34,
+    "Backquote" => 0x35,
+    "Comma" => 0x36,
+    "Period" => 0x37,
+    "Slash" => 0x38,
+    "CapsLock" => 0x39,
+    "F1" => 0x3a,
+    "F2" => 0x3b,
+    "F3" => 0x3c,
+    "F4" => 0x3d,
+    "F5" => 0x3e,
+    "F6" => 0x3f,
+    "F7" => 0x40,
+    "F8" => 0x41,
+    "F9" => 0x42,
+    "F10" => 0x43,
+    "F11" => 0x44,
+    "F12" => 0x45,
+    "PrintScreen" => 0x46,
+    "ScrollLock" => 0x47,
+    "Pause" => 0x48,
+    "Insert" => 0x49,
+    "Home" => 0x4a,
+    "PageUp" => 0x4b,
+    "Delete" => 0x4c,
+    "End" => 0x4d,
+    "PageDown" => 0x4e,
+    "ArrowRight" => 0x4f,
+    "ArrowLeft" => 0x50,
+    "ArrowDown" => 0x51,
+    "ArrowUp" => 0x52,
+    "NumLock" => 0x53,
+    "NumpadDivide" => 0x54,
+    "NumpadMultiply" => 0x55,
+    "NumpadSubtract" => 0x56,
+    "NumpadAdd" => 0x57,
+    "NumpadEnter" => 0x58,
+    "Numpad1" => 0x59,
+    "Numpad2" => 0x5a,
+    "Numpad3" => 0x5b,
+    "Numpad4" => 0x5c,
+    "Numpad5" => 0x5d,
+    "Numpad6" => 0x5e,
+    "Numpad7" => 0x5f,
+    "Numpad8" => 0x60,
+    "Numpad9" => 0x61,
+    "Numpad0" => 0x62,
+    "NumpadDecimal" => 0x63,
+    "IntlBackslash" => 0x64,
+    "ContextMenu" => 0x65,
+    "Power" => 0x66,
+    "NumpadEqual" => 0x67,
+    "F13" => 0x68,
+    "F14" => 0x69,
+    "F15" => 0x6a,
+    "F16" => 0x6b,
+    "F17" => 0x6c,
+    "F18" => 0x6d,
+    "F19" => 0x6e,
+    "F20" => 0x6f,
+    "F21" => 0x70,
+    "F22" => 0x71,
+    "F23" => 0x72,
+    "F24" => 0x73,
+    "AudioVolumeMute" => 0x7f,
+    "AudioVolumeUp" => 0x80,
+    "AudioVolumeDown" => 0x81,
+    "NumpadComma" => 0x85,
+    "IntlRo" => 0x87,
+    "KanaMode" => 0x88,
+    "IntlYen" => 0x89,
+    "Convert" => 0x8a,
+    "NonConvert" => 0x8b,
+    "Lang1" => 0x90,
+    "Lang2" => 0x91,
+    "ControlLeft" => LEFT_CONTROL,
+    "ShiftLeft" => LEFT_SHIFT,
+    "AltLeft" => LEFT_ALT,
+    "MetaLeft" => LEFT_META,
+    "ControlRight" => RIGHT_CONTROL,
+    "ShiftRight" => RIGHT_SHIFT,
+    "AltRight" => RIGHT_ALT,
+    "MetaRight" => RIGHT_META,
+    _ => return None,
+  };
+  Some(usage)
+}
+
+/// Translate a DOM `key` to a HID usage on the given host layout, and whether shift has to be held for it
+pub fn usage_from_key(key: &str, layout: Layout) -> Option<(u16, bool)> {
+  // named keys mostly share their name with the code
+  match key {
+    " " => return Some((0x2c, false)),
+    "Shift" => return Some((LEFT_SHIFT, false)),
+    "Control" => return Some((LEFT_CONTROL, false)),
+    "Alt" => return Some((LEFT_ALT, false)),
+    "Meta" => return Some((LEFT_META, false)),
+    _ => {}
+  }
+  if key.chars().count() > 1 {
+    return usage_from_code(key).map(|usage| (usage, false));
+  }
+
+  let c = key.chars().next()?;
+  if c.is_ascii_lowercase() {
+    return Some((0x04 + (c as u16 - 'a' as u16), false));
+  }
+  if c.is_ascii_uppercase() {
+    return Some((0x04 + (c as u16 - 'A' as u16), true));
+  }
+  if c == '0' {
+    return Some((0x27, false));
+  }
+  if c.is_ascii_digit() {
+    return Some((0x1e + (c as u16 - '1' as u16), false));
+  }
+
+  let usage = match layout {
+    Layout::Us => match c {
+      '!' => (0x1e, true),
+      '@' => (0x1f, true),
+      '#' => (0x20, true),
+      '$' => (0x21, true),
+      '%' => (0x22, true),
+      '^' => (0x23, true),
+      '&' => (0x24, true),
+      '*' => (0x25, true),
+      '(' => (0x26, true),
+      ')' => (0x27, true),
+      '-' => (0x2d, false),
+      '_' => (0x2d, true),
+      '=' => (0x2e, false),
+      '+' => (0x2e, true),
+      '[' => (0x2f, false),
+      '{' => (0x2f, true),
+      ']' => (0x30, false),
+      '}' => (0x30, true),
+      '\\' => (0x31, false),
+      '|' => (0x31, true),
+      ';' => (0x33, false),
+      ':' => (0x33, true),
+      '\'' => (0x34, false),
+      '"' => (0x34, true),
+      '`' => (0x35, false),
+      '~' => (0x35, true),
+      ',' => (0x36, false),
+      '<' => (0x36, true),
+      '.' => (0x37, false),
+      '>' => (0x37, true),
+      '/' => (0x38, false),
+      '?' => (0x38, true),
+      _ => return None,
+    },
+    Layout::Jis => match c {
+      '!' => (0x1e, true),
+      '"' => (0x1f, true),
+      '#' => (0x20, true),
+      '$' => (0x21, true),
+      '%' => (0x22, true),
+      '&' => (0x23, true),
+      '\'' => (0x24, true),
+      '(' => (0x25, true),
+      ')' => (0x26, true),
+      '-' => (0x2d, false),
+      '=' => (0x2d, true),
+      '^' => (0x2e, false),
+      '~' => (0x2e, true),
+      '@' => (0x2f, false),
+      '`' => (0x2f, true),
+      '[' => (0x30, false),
+      '{' => (0x30, true),
+      ']' => (0x32, false),
+      '}' => (0x32, true),
+      ';' => (0x33, false),
+      '+' => (0x33, true),
+      ':' => (0x34, false),
+      '*' => (0x34, true),
+      ',' => (0x36, false),
+      '<' => (0x36, true),
+      '.' => (0x37, false),
+      '>' => (0x37, true),
+      '/' => (0x38, false),
+      '?' => (0x38, true),
+      '\\' | '¥' => (0x89, false),
+      '|' => (0x89, true),
+      '_' => (0x87, true),
+      _ => return None,
+    },
+  };
+  Some(usage)
+}
+
+/// Keys that the host presses together on its own, like `ctrl+z`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Chord {
+  pub modifiers: Modifiers,
+  /// DOM `key` of the last key, which may need shift on its own
+  pub key: String,
+}
+
+impl FromStr for Chord {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut modifiers = Modifiers::NONE;
+    let mut key = s;
+    // the key itself may be `+`, as in `ctrl++`
+    while let Some((name, rest)) = key.split_once('+').filter(|(_, rest)| !rest.is_empty()) {
+      modifiers |= match name.to_ascii_lowercase().as_str() {
+        "shift" => Modifiers::SHIFT,
+        "ctrl" | "control" => Modifiers::CONTROL,
+        "alt" => Modifiers::ALT,
+        "meta" | "super" | "win" | "cmd" => Modifiers::META,
+        _ => return Err(format!("unknown modifier in {}: {}", s, name)),
+      };
+      key = rest;
+    }
+    if usage_from_key(key, Layout::Us).is_none() && usage_from_key(key, Layout::Jis).is_none() {
+      return Err(format!("unknown key in {}: {}", s, key));
+    }
+    Ok(Chord {
+      modifiers,
+      key: key.to_string(),
+    })
+  }
+}
+
+impl Chord {
+  /// Usages to press in order and release in reverse, or none if the key does not exist on the layout
+  pub fn usages(&self, layout: Layout) -> Option<Vec<u16>> {
+    let (usage, needs_shift) = usage_from_key(&self.key, layout)?;
+    let mut modifiers = self.modifiers;
+    if needs_shift {
+      modifiers |= Modifiers::SHIFT;
+    }
+    let mut usages: Vec<u16> = MODIFIER_KEYS
+      .iter()
+      .filter(|(modifier, _, _)| modifiers.contains(*modifier))
+      .map(|(_, left, _)| *left)
+      .collect();
+    usages.push(usage);
+    Some(usages)
+  }
+}
+
+/// A key pressed on behalf of a client key
+#[derive(Debug, Clone, Copy)]
+struct Stroke {
+  usage: u16,
+  /// Whether shift was pressed only to produce this key
+  shift: bool,
+}
+
+/// Translates client key events and keeps track of the keys held on the host
+#[derive(Default)]
+pub struct Keyboard {
+  layout: Layout,
+  /// Usages currently held down for the client, in the order they were pressed
+  held: Vec<u16>,
+  /// Usages held down by the host itself, like control during a pinch, with how often each is held
+  holds: HashMap<u16, usize>,
+  /// Strokes by the client key (its code, or its key if there is no code)
+  strokes: HashMap<String, Stroke>,
+}
+
+impl Keyboard {
+  pub fn new(layout: Layout) -> Self {
+    Self {
+      layout,
+      ..Default::default()
+    }
+  }
+
+  pub fn layout(&self) -> Layout {
+    self.layout
+  }
+
+  pub fn set_layout(&mut self, layout: Layout) {
+    self.layout = layout;
+  }
+
+  pub fn handle(&mut self, event: &KeyEvent, backend: &mut impl InputBackend) -> io::Result<()> {
+    let id = match event.code.is_empty() || event.code == "Unidentified" {
+      true => event.key.clone(),
+      false => event.code.clone(),
+    };
+
+    let resolved = match usage_from_code(&event.code) {
+      Some(usage) => Some((usage, false)),
+      None => usage_from_key(&event.key, self.layout),
+    };
+
+    match event.event_type {
+      KeyEventType::Down => {
+        let Some((usage, needs_shift)) = resolved else {
+          return Ok(());
+        };
+        self.sync_modifiers(event.modifiers, usage, backend)?;
+
+        if event.repeat && self.held.contains(&usage) {
+          return backend.key(&KeyInput {
+            usage,
+            down: true,
+            repeat: true,
+          });
+        }
+
+        let shift = needs_shift && !self.is_held(Modifiers::SHIFT);
+        if shift {
+          self.press(LEFT_SHIFT, backend)?;
+        }
+        self.press(usage, backend)?;
+        self.strokes.insert(id, Stroke { usage, shift });
+      }
+      KeyEventType::Up => {
+        let stroke = match self.strokes.remove(&id) {
+          Some(stroke) => stroke,
+          None => match resolved {
+            Some((usage, _)) => Stroke { usage, shift: false },
+            None => return Ok(()),
+          },
+        };
+        self.release(stroke.usage, backend)?;
+        if stroke.shift {
+          self.release(LEFT_SHIFT, backend)?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Press a chord and release it again, leaving the keys that the client holds down
+  pub fn tap(&mut self, chord: &Chord, backend: &mut impl InputBackend) -> io::Result<()> {
+    let Some(usages) = chord.usages(self.layout) else {
+      return Ok(());
+    };
+    for &usage in &usages {
+      self.hold(usage, backend)?;
+    }
+    for &usage in usages.iter().rev() {
+      self.unhold(usage, backend)?;
+    }
+    Ok(())
+  }
+
+  /// Hold a key down on behalf of the host, until `unhold` is called as often
+  pub(crate) fn hold(&mut self, usage: u16, backend: &mut impl InputBackend) -> io::Result<()> {
+    let down = self.is_down(usage);
+    *self.holds.entry(usage).or_default() += 1;
+    match down {
+      true => Ok(()),
+      false => key(usage, true, backend),
+    }
+  }
+
+  pub(crate) fn unhold(&mut self, usage: u16, backend: &mut impl InputBackend) -> io::Result<()> {
+    let Some(count) = self.holds.get_mut(&usage) else {
+      return Ok(());
+    };
+    *count -= 1;
+    if *count > 0 {
+      return Ok(());
+    }
+    self.holds.remove(&usage);
+    match self.is_down(usage) {
+      true => Ok(()),
+      false => key(usage, false, backend),
+    }
+  }
+
+  /// Release every key and modifier held on the host
+  pub fn release_all(&mut self, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.strokes.clear();
+    let mut usages: Vec<u16> = self.holds.drain().map(|(usage, _)| usage).collect();
+    usages.retain(|usage| !self.held.contains(usage));
+    usages.append(&mut self.held);
+    let mut result = Ok(());
+    while let Some(usage) = usages.pop() {
+      result = result.and(key(usage, false, backend));
+    }
+    result
+  }
+
+  fn is_down(&self, usage: u16) -> bool {
+    self.held.contains(&usage) || self.holds.contains_key(&usage)
+  }
+
+  fn is_held(&self, modifier: Modifiers) -> bool {
+    MODIFIER_KEYS
+      .iter()
+      .filter(|(m, _, _)| *m == modifier)
+      .any(|(_, left, right)| self.held.contains(left) || self.held.contains(right))
+  }
+
+  /// Bring the held modifiers in line with the modifier state reported by the client,
+  /// in case the client did not send events for the modifier keys themselves
+  fn sync_modifiers(&mut self, modifiers: Modifiers, usage: u16, backend: &mut impl InputBackend) -> io::Result<()> {
+    for (modifier, left, right) in MODIFIER_KEYS {
+      if usage == left || usage == right {
+        continue;
+      }
+      let want = modifiers.contains(modifier);
+      let have = self.is_held(modifier);
+      if want && !have {
+        self.press(left, backend)?;
+      } else if !want && have {
+        self.release(left, backend)?;
+        self.release(right, backend)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn press(&mut self, usage: u16, backend: &mut impl InputBackend) -> io::Result<()> {
+    if self.held.contains(&usage) {
+      return Ok(());
+    }
+    let down = self.is_down(usage);
+    self.held.push(usage);
+    match down {
+      true => Ok(()),
+      false => key(usage, true, backend),
+    }
+  }
+
+  fn release(&mut self, usage: u16, backend: &mut impl InputBackend) -> io::Result<()> {
+    let Some(index) = self.held.iter().position(|held| *held == usage) else {
+      return Ok(());
+    };
+    self.held.remove(index);
+    match self.is_down(usage) {
+      true => Ok(()),
+      false => key(usage, false, backend),
+    }
+  }
+}
+
+fn key(usage: u16, down: bool, backend: &mut impl InputBackend) -> io::Result<()> {
+  backend.key(&KeyInput {
+    usage,
+    down,
+    repeat: false,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Record, RecordingBackend};
+
+  use KeyEventType::{Down, Up};
+
+  fn event(event_type: KeyEventType, code: &str, key: &str, modifiers: Modifiers) -> KeyEvent {
+    KeyEvent {
+      event_type,
+      code: code.to_string(),
+      key: key.to_string(),
+      modifiers,
+      repeat: false,
+    }
+  }
+
+  fn keys(backend: &mut RecordingBackend) -> Vec<(u16, bool)> {
+    backend
+      .take()
+      .into_iter()
+      .map(|record| match record {
+        Record::Key(input) => (input.usage, input.down),
+        record => panic!("unexpected record: {:?}", record),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn maps_keys_on_the_layout() {
+    assert_eq!(usage_from_key("@", Layout::Us), Some((0x1f, true)));
+    assert_eq!(usage_from_key("@", Layout::Jis), Some((0x2f, false)));
+    assert_eq!(usage_from_key("\"", Layout::Jis), Some((0x1f, true)));
+    assert_eq!(usage_from_key("¥", Layout::Jis), Some((0x89, false)));
+    assert_eq!(usage_from_key("_", Layout::Jis), Some((0x87, true)));
+    assert_eq!(usage_from_key("¥", Layout::Us), None);
+  }
+
+  #[test]
+  fn types_jis_keys_without_a_code() {
+    let mut backend = RecordingBackend::new();
+    let mut keyboard = Keyboard::new(Layout::Jis);
+    keyboard
+      .handle(&event(Down, "", "@", Modifiers::NONE), &mut backend)
+      .unwrap();
+    keyboard
+      .handle(&event(Up, "", "@", Modifiers::NONE), &mut backend)
+      .unwrap();
+    assert_eq!(keys(&mut backend), [(0x2f, true), (0x2f, false)]);
+  }
+
+  #[test]
+  fn shifts_characters_that_need_it() {
+    let mut backend = RecordingBackend::new();
+    let mut keyboard = Keyboard::new(Layout::Us);
+    keyboard
+      .handle(&event(Down, "", "A", Modifiers::NONE), &mut backend)
+      .unwrap();
+    keyboard
+      .handle(&event(Up, "", "A", Modifiers::NONE), &mut backend)
+      .unwrap();
+    assert_eq!(
+      keys(&mut backend),
+      [(LEFT_SHIFT, true), (0x04, true), (0x04, false), (LEFT_SHIFT, false)]
+    );
+
+    // shift that the client holds stays down
+    keyboard
+      .handle(&event(Down, "ShiftLeft", "Shift", Modifiers::SHIFT), &mut backend)
+      .unwrap();
+    keyboard
+      .handle(&event(Down, "", "A", Modifiers::SHIFT), &mut backend)
+      .unwrap();
+    keyboard
+      .handle(&event(Up, "", "A", Modifiers::SHIFT), &mut backend)
+      .unwrap();
+    assert_eq!(keys(&mut backend), [(LEFT_SHIFT, true), (0x04, true), (0x04, false)]);
+  }
+
+  #[test]
+  fn syncs_modifiers_with_the_client() {
+    let mut backend = RecordingBackend::new();
+    let mut keyboard = Keyboard::new(Layout::Us);
+    // the client never sent control itself
+    keyboard
+      .handle(&event(Down, "KeyZ", "z", Modifiers::CONTROL), &mut backend)
+      .unwrap();
+    keyboard
+      .handle(&event(Up, "KeyZ", "z", Modifiers::CONTROL), &mut backend)
+      .unwrap();
+    keyboard
+      .handle(&event(Down, "KeyZ", "z", Modifiers::NONE), &mut backend)
+      .unwrap();
+    assert_eq!(
+      keys(&mut backend),
+      [
+        (LEFT_CONTROL, true),
+        (0x1d, true),
+        (0x1d, false),
+        (LEFT_CONTROL, false),
+        (0x1d, true)
+      ]
+    );
+  }
+
+  #[test]
+  fn repeats_held_keys() {
+    let mut backend = RecordingBackend::new();
+    let mut keyboard = Keyboard::new(Layout::Us);
+    let down = event(Down, "KeyA", "a", Modifiers::NONE);
+    keyboard.handle(&down, &mut backend).unwrap();
+    keyboard
+      .handle(&KeyEvent { repeat: true, ..down }, &mut backend)
+      .unwrap();
+    let records = backend.take();
+    assert_eq!(
+      records,
+      [
+        Record::Key(KeyInput {
+          usage: 0x04,
+          down: true,
+          repeat: false
+        }),
+        Record::Key(KeyInput {
+          usage: 0x04,
+          down: true,
+          repeat: true
+        }),
+      ]
+    );
+  }
+
+  #[test]
+  fn releases_every_held_key() {
+    let mut backend = RecordingBackend::new();
+    let mut keyboard = Keyboard::new(Layout::Us);
+    keyboard
+      .handle(&event(Down, "ShiftLeft", "Shift", Modifiers::SHIFT), &mut backend)
+      .unwrap();
+    keyboard
+      .handle(&event(Down, "KeyA", "A", Modifiers::SHIFT), &mut backend)
+      .unwrap();
+    keyboard.hold(LEFT_CONTROL, &mut backend).unwrap();
+    keyboard.hold(LEFT_SHIFT, &mut backend).unwrap();
+    backend.take();
+
+    keyboard.release_all(&mut backend).unwrap();
+    let mut released = keys(&mut backend);
+    released.sort();
+    assert_eq!(released, [(0x04, false), (LEFT_CONTROL, false), (LEFT_SHIFT, false)]);
+
+    // nothing is left to release
+    keyboard.unhold(LEFT_CONTROL, &mut backend).unwrap();
+    keyboard
+      .handle(&event(Up, "KeyA", "A", Modifiers::NONE), &mut backend)
+      .unwrap();
+    keyboard.release_all(&mut backend).unwrap();
+    assert!(backend.take().is_empty());
+  }
+
+  #[test]
+  fn taps_chords_around_held_keys() {
+    let mut backend = RecordingBackend::new();
+    let mut keyboard = Keyboard::new(Layout::Us);
+    keyboard
+      .handle(&event(Down, "ControlLeft", "Control", Modifiers::CONTROL), &mut backend)
+      .unwrap();
+    backend.take();
+    keyboard.tap(&"ctrl+shift+z".parse().unwrap(), &mut backend).unwrap();
+    assert_eq!(
+      keys(&mut backend),
+      [(LEFT_SHIFT, true), (0x1d, true), (0x1d, false), (LEFT_SHIFT, false)]
+    );
+  }
+}
@@ -0,0 +1,318 @@
+// Platform-neutral input injection shared by the hosts.
+//
+// Events received from the client are dispatched by `InputDevices` to an `InputBackend`,
+// which does the actual injection on the platform (synthetic pointer devices on Windows, uinput on Linux).
+
+use std::{io, time::Instant};
+
+mod backend;
+mod calibration;
+mod contacts;
+mod display;
+mod event;
+mod gestures;
+mod keyboard;
+mod mapping;
+mod mouse;
+mod palm;
+mod pen;
+mod pressure;
+mod trackpad;
+#[cfg(target_os = "linux")]
+mod uinput;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(target_os = "linux")]
+pub use crate::uinput::UinputBackend;
+#[cfg(windows)]
+pub use crate::win32::Win32Backend;
+pub use crate::{
+  backend::{InputBackend, NullBackend, Record, RecordingBackend},
+  calibration::{fit_curve, Calibration, StrokeLevel},
+  contacts::Contacts,
+  display::{monitors, virtual_desktop, Monitor, Rect},
+  event::{
+    Button, KeyInput, MouseButton, MouseInput, PenButton, PenInput, PenTool, PointerEvent, PointerEventType,
+    PointerType,
+  },
+  gestures::{GestureKeys, Gestures},
+  keyboard::{usage_from_code, usage_from_key, Chord, KeyEvent, KeyEventType, Keyboard, Layout, Modifiers},
+  mapping::{Area, Aspect, Mapping, Rotation},
+  mouse::Mouse,
+  palm::{Palm, PalmSize},
+  pen::{Barrel, Pen},
+  pressure::PressureCurve,
+  trackpad::{Acceleration, Trackpad},
+};
+
+/// The maximum number of simultaneous touch contacts
+pub const MAX_CONTACTS: usize = 10;
+
+/// Create the injection backend for the current platform
+pub fn platform_backend() -> io::Result<Box<dyn InputBackend>> {
+  #[cfg(windows)]
+  return Ok(Box::new(Win32Backend::new()?));
+
+  #[cfg(target_os = "linux")]
+  return Ok(Box::new(UinputBackend::new()?));
+
+  #[cfg(not(any(windows, target_os = "linux")))]
+  return Err(io::Error::new(
+    io::ErrorKind::Unsupported,
+    "input injection is not supported on this platform",
+  ));
+}
+
+/// Check whether `platform_backend` can inject input, without creating any devices
+pub fn probe_platform() -> io::Result<()> {
+  #[cfg(windows)]
+  return Win32Backend::probe();
+
+  #[cfg(target_os = "linux")]
+  return UinputBackend::probe();
+
+  #[cfg(not(any(windows, target_os = "linux")))]
+  return Err(io::Error::new(
+    io::ErrorKind::Unsupported,
+    "input injection is not supported on this platform",
+  ));
+}
+
+pub struct InputDevices<B: InputBackend = Box<dyn InputBackend>> {
+  backend: B,
+  touches: Contacts,
+  pen: Pen,
+  mouse: Mouse,
+  trackpad: Trackpad,
+  gestures: Gestures,
+  keyboard: Keyboard,
+  palm: Palm,
+  pressure: PressureCurve,
+}
+
+impl<B: InputBackend> InputDevices<B> {
+  pub fn new(backend: B) -> Self {
+    Self {
+      backend,
+      touches: Contacts::new(),
+      pen: Pen::default(),
+      mouse: Mouse::default(),
+      trackpad: Trackpad::default(),
+      gestures: Gestures::default(),
+      keyboard: Keyboard::default(),
+      palm: Palm::new(),
+      pressure: PressureCurve::default(),
+    }
+  }
+
+  pub fn with_layout(mut self, layout: Layout) -> Self {
+    self.keyboard.set_layout(layout);
+    self
+  }
+
+  pub fn with_barrel(mut self, barrel: Barrel) -> Self {
+    self.pen.set_barrel(barrel);
+    self
+  }
+
+  /// Pen pressure at which emulated mouse clicks start
+  pub fn with_click_pressure(mut self, click_pressure: f64) -> Self {
+    self.mouse.set_click_pressure(click_pressure);
+    self
+  }
+
+  /// Curve applied to the pressure of pens, before anything else sees it
+  pub fn with_pressure_curve(mut self, pressure: PressureCurve) -> Self {
+    self.pressure = pressure;
+    self
+  }
+
+  pub fn set_pressure_curve(&mut self, pressure: PressureCurve) {
+    self.pressure = pressure;
+  }
+
+  /// Whether touches are rejected as palms while the pen is in use, which is the default
+  pub fn with_palm_rejection(mut self, enabled: bool) -> Self {
+    self.palm.set_enabled(enabled);
+    self
+  }
+
+  pub fn set_palm_rejection(&mut self, enabled: bool) {
+    self.palm.set_enabled(enabled);
+  }
+
+  /// How touches move the cursor in trackpad mode
+  pub fn with_acceleration(mut self, acceleration: Acceleration) -> Self {
+    self.trackpad.set_acceleration(acceleration);
+    self
+  }
+
+  /// Chords that touch gestures press
+  pub fn with_gesture_keys(mut self, keys: GestureKeys) -> Self {
+    self.gestures.set_keys(keys);
+    self
+  }
+
+  pub fn backend(&self) -> &B {
+    &self.backend
+  }
+
+  pub fn backend_mut(&mut self) -> &mut B {
+    &mut self.backend
+  }
+
+  /// Cancel every touch contact, and release the pen, the mouse buttons and every key held on the host
+  pub fn reset(&mut self) -> io::Result<()> {
+    self.palm.cancel_touches();
+    let touches = self.cancel_touches();
+    let pen = self.pen.release(&mut self.backend);
+    let mouse = self.mouse.release_all(&mut self.backend);
+    self
+      .keyboard
+      .release_all(&mut self.backend)
+      .and(pen)
+      .and(mouse)
+      .and(touches)
+  }
+
+  /// Cancel every touch contact, whichever way touches are injected
+  fn cancel_touches(&mut self) -> io::Result<()> {
+    let frame = self.touches.cancel_all();
+    let touches = match frame.is_empty() {
+      true => Ok(()),
+      false => self.backend.touch(&frame),
+    };
+    let mouse = self.mouse.release_type(PointerType::Touch, &mut self.backend);
+    let trackpad = self.trackpad.release(&mut self.backend);
+    let gestures = self.gestures.release(&mut self.keyboard, &mut self.backend);
+    touches.and(mouse).and(trackpad).and(gestures)
+  }
+
+  /// Apply the pressure curve to pens, reject touches that are palms by turning them into cancels, and cancel every
+  /// touch when the pen comes down. Events of touches that were rejected before are dropped.
+  fn prepare(&mut self, event: PointerEvent) -> io::Result<Option<PointerEvent>> {
+    let now = Instant::now();
+    match event.pointer_type {
+      PointerType::Pen => {
+        if self.palm.pen_at(&event, now) {
+          self.cancel_touches()?;
+        }
+        Ok(Some(PointerEvent {
+          pressure: self.pressure.apply(event.pressure),
+          ..event
+        }))
+      }
+      PointerType::Touch => Ok(self.palm.touch_at(event, now)),
+      PointerType::Mouse => Ok(Some(event)),
+    }
+  }
+
+  pub fn key(&mut self, event: &KeyEvent) -> io::Result<()> {
+    self.keyboard.handle(event, &mut self.backend)
+  }
+
+  pub fn inject(&mut self, event: PointerEvent) -> io::Result<()> {
+    let Some(event) = self.prepare(event)? else {
+      return Ok(());
+    };
+    match event.pointer_type {
+      PointerType::Touch => {
+        let frame = self.touches.update(event);
+        if frame.is_empty() {
+          return Ok(());
+        }
+        self.backend.touch(&frame)
+      }
+      PointerType::Pen => self.pen.handle(&event, &mut self.backend),
+      PointerType::Mouse => self.mouse.handle(&event, &mut self.backend),
+    }
+  }
+
+  /// Inject any pointer as the mouse, for applications that ignore pens and touches
+  pub fn emulate_mouse(&mut self, event: PointerEvent) -> io::Result<()> {
+    let Some(event) = self.prepare(event)? else {
+      return Ok(());
+    };
+    self.mouse.handle(&event, &mut self.backend)
+  }
+
+  /// Inject a touch as a finger on a trackpad, which moves the cursor relative to where it is
+  pub fn trackpad(&mut self, event: PointerEvent) -> io::Result<()> {
+    let Some(event) = self.prepare(event)? else {
+      return Ok(());
+    };
+    self.trackpad.handle(event, &mut self.backend)
+  }
+
+  /// Inject touches as the scrolling, zooming and key chords of the gestures they make
+  pub fn gestures(&mut self, event: PointerEvent) -> io::Result<()> {
+    let Some(event) = self.prepare(event)? else {
+      return Ok(());
+    };
+    self.gestures.handle(event, &mut self.keyboard, &mut self.backend)
+  }
+
+  /// When `expire` has to be called next, for input that is held back until a timeout
+  pub fn deadline(&self) -> Option<Instant> {
+    self.trackpad.deadline()
+  }
+
+  /// Send the input whose timeout has passed
+  pub fn expire(&mut self) -> io::Result<()> {
+    self.trackpad.expire(&mut self.backend)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pointer(pointer_type: PointerType, pressure: f64) -> PointerEvent {
+    PointerEvent {
+      event_type: PointerEventType::Down,
+      id: 1,
+      pointer_type,
+      is_primary: true,
+      x: 0.5,
+      y: 0.5,
+      button: Button::PRIMARY,
+      buttons: Button::PRIMARY,
+      width: 0.01,
+      height: 0.01,
+      pressure,
+      tangential_pressure: 0.0,
+      tilt_x: 0.0,
+      tilt_y: 0.0,
+      twist: 0,
+    }
+  }
+
+  #[test]
+  fn applies_the_pressure_curve_to_pens() {
+    let curve = PressureCurve {
+      gamma: 2.0,
+      ..Default::default()
+    };
+    let mut devices = InputDevices::new(RecordingBackend::new()).with_pressure_curve(curve);
+    // touches come first, as the pen would reject them as palms
+    devices.inject(pointer(PointerType::Touch, 0.5)).unwrap();
+    devices.inject(pointer(PointerType::Pen, 0.5)).unwrap();
+    let records = devices.backend_mut().take();
+    let [Record::Touch(touches), .., Record::Pen(pen)] = &records[..] else {
+      panic!("unexpected records: {:?}", records);
+    };
+    assert_eq!(pen.event.pressure, 0.25);
+    assert_eq!(touches[0].pressure, 0.5);
+
+    devices.set_pressure_curve(PressureCurve::default());
+    devices
+      .inject(PointerEvent {
+        event_type: PointerEventType::Move,
+        ..pointer(PointerType::Pen, 0.5)
+      })
+      .unwrap();
+    let records = devices.backend_mut().take();
+    assert!(matches!(records[..], [Record::Pen(PenInput { event, .. })] if event.pressure == 0.5));
+  }
+}
@@ -0,0 +1,490 @@
+// Mapping from the client view to the desktop
+//
+// The client reports positions normalized to its view of the stream, and contact sizes in its own pixels.
+// The mapping places them in the target region of the virtual desktop, or in a fixed active area like the
+// one of a drawing tablet, and normalizes them to the whole virtual desktop for the backends.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{PointerEvent, Rect};
+
+/// How the target is fitted into the client view when their aspect ratios differ, like CSS `object-fit`
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aspect {
+  /// Fill the view, distorting the target
+  Stretch,
+  /// Fit the whole target in the view, with bars on two sides
+  #[default]
+  Letterbox,
+  /// Fill the view, cutting off two sides of the target
+  Crop,
+}
+
+impl FromStr for Aspect {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "stretch" => Ok(Aspect::Stretch),
+      "letterbox" => Ok(Aspect::Letterbox),
+      "crop" => Ok(Aspect::Crop),
+      _ => Err(format!("unknown aspect mode: {}", s)),
+    }
+  }
+}
+
+/// Clockwise turn of the client surface relative to the desktop, for clients whose display does not follow it
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Rotation {
+  #[default]
+  Deg0,
+  /// Portrait, with the top of the surface on the right of the desktop
+  Deg90,
+  /// Left-handed, upside down
+  Deg180,
+  /// Portrait, with the top of the surface on the left of the desktop
+  Deg270,
+}
+
+impl FromStr for Rotation {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "0" => Ok(Rotation::Deg0),
+      "90" => Ok(Rotation::Deg90),
+      "180" => Ok(Rotation::Deg180),
+      "270" => Ok(Rotation::Deg270),
+      _ => Err(format!("rotation must be 0, 90, 180 or 270: {}", s)),
+    }
+  }
+}
+
+impl Rotation {
+  /// Normalized position on the desktop for a normalized position on the surface
+  fn position(self, x: f64, y: f64) -> (f64, f64) {
+    match self {
+      Rotation::Deg0 => (x, y),
+      Rotation::Deg90 => (1.0 - y, x),
+      Rotation::Deg180 => (1.0 - x, 1.0 - y),
+      Rotation::Deg270 => (y, 1.0 - x),
+    }
+  }
+
+  /// Direction on the desktop for a direction on the surface
+  fn vector<T: std::ops::Neg<Output = T>>(self, x: T, y: T) -> (T, T) {
+    match self {
+      Rotation::Deg0 => (x, y),
+      Rotation::Deg90 => (-y, x),
+      Rotation::Deg180 => (-x, -y),
+      Rotation::Deg270 => (y, -x),
+    }
+  }
+
+  /// Extent on the desktop for an extent on the surface
+  fn size(self, width: f64, height: f64) -> (f64, f64) {
+    match self {
+      Rotation::Deg0 | Rotation::Deg180 => (width, height),
+      Rotation::Deg90 | Rotation::Deg270 => (height, width),
+    }
+  }
+
+  fn degrees(self) -> u32 {
+    match self {
+      Rotation::Deg0 => 0,
+      Rotation::Deg90 => 90,
+      Rotation::Deg180 => 180,
+      Rotation::Deg270 => 270,
+    }
+  }
+}
+
+/// Part of the desktop that the client surface controls, like the active area of a drawing tablet
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct Area {
+  /// Fixed region of the virtual desktop, or the target if unset, in which case the surface is only the part
+  /// of the view showing the stream
+  pub rect: Option<Rect>,
+  /// Keep the proportions of the region by leaving a band of the surface unused, instead of distorting
+  pub lock_aspect: bool,
+  pub rotation: Rotation,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Mapping {
+  /// Bounding box of all monitors
+  pub desktop: Rect,
+  /// Region of the desktop shown in the client view
+  pub target: Rect,
+  /// Size of the client view in its own pixels, until which the view is assumed to have the aspect of the target
+  pub view: Option<(f64, f64)>,
+  pub aspect: Aspect,
+  pub area: Area,
+}
+
+/// Where an event lands on the desktop
+struct Placement {
+  /// Position in virtual-desktop pixels
+  x: f64,
+  y: f64,
+  /// Desktop pixels per view pixel, along the desktop axes
+  scale_x: f64,
+  scale_y: f64,
+}
+
+impl Mapping {
+  pub fn new(desktop: Rect, target: Rect) -> Self {
+    Self {
+      desktop,
+      target,
+      view: None,
+      aspect: Aspect::default(),
+      area: Area::default(),
+    }
+  }
+
+  fn view(&self) -> Option<(f64, f64)> {
+    self.view.filter(|&(width, height)| width > 0.0 && height > 0.0)
+  }
+
+  /// Where the target appears in the view, as normalized (x, y, width, height)
+  fn content(&self) -> (f64, f64, f64, f64) {
+    let Some((view_width, view_height)) = self.view() else {
+      return (0.0, 0.0, 1.0, 1.0);
+    };
+    if self.target.width == 0 || self.target.height == 0 {
+      return (0.0, 0.0, 1.0, 1.0);
+    }
+
+    // greater than 1 if the target is wider than the view
+    let ratio = (self.target.width as f64 / self.target.height as f64) / (view_width / view_height);
+    let fill_height = match self.aspect {
+      Aspect::Stretch => return (0.0, 0.0, 1.0, 1.0),
+      Aspect::Letterbox => ratio < 1.0,
+      Aspect::Crop => ratio > 1.0,
+    };
+    if fill_height {
+      (0.5 - ratio / 2.0, 0.0, ratio, 1.0)
+    } else {
+      (0.0, 0.5 - 0.5 / ratio, 1.0, 1.0 / ratio)
+    }
+  }
+
+  fn place(&self, x: f64, y: f64) -> Placement {
+    // the surface is the whole view for an active area, and the picture otherwise
+    let (left, top, width, height) = match self.area.rect {
+      Some(_) => (0.0, 0.0, 1.0, 1.0),
+      None => self.content(),
+    };
+    // positions on the bars of a letterbox stick to the nearest edge
+    let x = ((x - left) / width).clamp(0.0, 1.0);
+    let y = ((y - top) / height).clamp(0.0, 1.0);
+    let (mut x, mut y) = self.area.rotation.position(x, y);
+
+    let region = self.area.rect.unwrap_or(self.target);
+    let surface = self
+      .view()
+      .map(|(view_width, view_height)| self.area.rotation.size(width * view_width, height * view_height));
+
+    // fraction of the surface in use along the desktop axes
+    let (mut used_x, mut used_y) = (1.0, 1.0);
+    if let (true, Some((surface_width, surface_height))) = (self.area.lock_aspect, surface) {
+      if region.width > 0 && region.height > 0 {
+        // greater than 1 if the surface is wider than the region
+        let ratio = (surface_width / surface_height) / (region.width as f64 / region.height as f64);
+        if ratio > 1.0 {
+          used_x = 1.0 / ratio;
+        } else {
+          used_y = ratio;
+        }
+        x = ((x - (1.0 - used_x) / 2.0) / used_x).clamp(0.0, 1.0);
+        y = ((y - (1.0 - used_y) / 2.0) / used_y).clamp(0.0, 1.0);
+      }
+    }
+
+    let (scale_x, scale_y) = match surface {
+      Some((surface_width, surface_height)) => (
+        region.width as f64 / (used_x * surface_width),
+        region.height as f64 / (used_y * surface_height),
+      ),
+      None => (1.0, 1.0),
+    };
+    Placement {
+      x: region.x as f64 + x * region.width as f64,
+      y: region.y as f64 + y * region.height as f64,
+      scale_x,
+      scale_y,
+    }
+  }
+
+  /// Position in virtual-desktop pixels for a normalized position in the view
+  pub fn to_desktop(&self, x: f64, y: f64) -> (f64, f64) {
+    let placement = self.place(x, y);
+    (placement.x, placement.y)
+  }
+
+  /// Convert an event from the client into one normalized to the virtual desktop
+  pub fn apply(&self, event: &PointerEvent) -> PointerEvent {
+    let Placement { x, y, scale_x, scale_y } = self.place(event.x, event.y);
+    let rotation = self.area.rotation;
+    let (width, height) = rotation.size(event.width, event.height);
+    let (tilt_x, tilt_y) = rotation.vector(event.tilt_x, event.tilt_y);
+    let desktop_width = self.desktop.width.max(1) as f64;
+    let desktop_height = self.desktop.height.max(1) as f64;
+    PointerEvent {
+      x: (x - self.desktop.x as f64) / desktop_width,
+      y: (y - self.desktop.y as f64) / desktop_height,
+      width: width * scale_x / desktop_width,
+      height: height * scale_y / desktop_height,
+      tilt_x,
+      tilt_y,
+      twist: (event.twist + rotation.degrees()) % 360,
+      ..*event
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Button, PointerEventType, PointerType};
+
+  const FULL_HD: Rect = Rect {
+    x: 0,
+    y: 0,
+    width: 1920,
+    height: 1080,
+  };
+
+  fn event(x: f64, y: f64) -> PointerEvent {
+    PointerEvent {
+      event_type: PointerEventType::Move,
+      id: 0,
+      pointer_type: PointerType::Pen,
+      is_primary: true,
+      x,
+      y,
+      button: Button::NONE,
+      buttons: Button::NONE,
+      width: 10.0,
+      height: 10.0,
+      pressure: 0.5,
+      tangential_pressure: 0.0,
+      tilt_x: 0.0,
+      tilt_y: 0.0,
+      twist: 0,
+    }
+  }
+
+  fn position(mapping: &Mapping, x: f64, y: f64) -> (f64, f64) {
+    let event = mapping.apply(&event(x, y));
+    (event.x, event.y)
+  }
+
+  fn assert_near((x, y): (f64, f64), (expected_x, expected_y): (f64, f64)) {
+    assert!(
+      (x - expected_x).abs() < 1e-9 && (y - expected_y).abs() < 1e-9,
+      "({}, {}) is not ({}, {})",
+      x,
+      y,
+      expected_x,
+      expected_y
+    );
+  }
+
+  /// A 16:9 target in a square view
+  fn square_view(aspect: Aspect) -> Mapping {
+    Mapping {
+      view: Some((1000.0, 1000.0)),
+      aspect,
+      ..Mapping::new(FULL_HD, FULL_HD)
+    }
+  }
+
+  #[test]
+  fn maps_the_view_onto_the_target_without_its_size() {
+    let mapping = Mapping::new(FULL_HD, FULL_HD);
+    assert_near(position(&mapping, 0.25, 0.75), (0.25, 0.75));
+  }
+
+  #[test]
+  fn stretches_the_target_over_the_view() {
+    let mapping = square_view(Aspect::Stretch);
+    assert_near(position(&mapping, 0.0, 0.0), (0.0, 0.0));
+    assert_near(position(&mapping, 0.25, 0.75), (0.25, 0.75));
+  }
+
+  #[test]
+  fn skips_the_bars_of_a_letterbox() {
+    let mapping = square_view(Aspect::Letterbox);
+    // the picture is 1000x562.5 in the middle of the view
+    let top = (1.0 - 0.5625) / 2.0;
+    assert_near(position(&mapping, 0.5, 0.5), (0.5, 0.5));
+    assert_near(position(&mapping, 0.0, top), (0.0, 0.0));
+    assert_near(position(&mapping, 1.0, 1.0 - top), (1.0, 1.0));
+    assert_near(position(&mapping, 0.25, top + 0.5625 / 4.0), (0.25, 0.25));
+    // positions on the bars stick to the nearest edge
+    assert_near(position(&mapping, 0.5, 0.05), (0.5, 0.0));
+    assert_near(position(&mapping, 0.5, 0.95), (0.5, 1.0));
+  }
+
+  #[test]
+  fn cuts_off_the_sides_of_a_crop() {
+    let mapping = square_view(Aspect::Crop);
+    // the picture is 1777.8x1000, of which the middle 1000 are shown
+    let hidden = (1.0 - 1000.0 / (1000.0 * 16.0 / 9.0)) / 2.0;
+    assert_near(position(&mapping, 0.5, 0.5), (0.5, 0.5));
+    assert_near(position(&mapping, 0.0, 0.0), (hidden, 0.0));
+    assert_near(position(&mapping, 1.0, 1.0), (1.0 - hidden, 1.0));
+  }
+
+  #[test]
+  fn scales_contact_sizes_to_the_desktop() {
+    let event = square_view(Aspect::Letterbox).apply(&event(0.5, 0.5));
+    // 1000 view pixels show 1920 desktop pixels in both directions
+    assert_near(
+      (event.width, event.height),
+      (10.0 * 1.92 / 1920.0, 10.0 * 1.92 / 1080.0),
+    );
+  }
+
+  #[test]
+  fn normalizes_to_the_whole_desktop() {
+    let desktop = Rect {
+      x: -1920,
+      width: 3840,
+      ..FULL_HD
+    };
+    let left = Rect { x: -1920, ..FULL_HD };
+    assert_near(position(&Mapping::new(desktop, left), 0.5, 0.5), (0.25, 0.5));
+    assert_near(position(&Mapping::new(desktop, FULL_HD), 0.5, 0.5), (0.75, 0.5));
+  }
+
+  #[test]
+  fn maps_the_whole_view_onto_a_fixed_area() {
+    let area = Rect {
+      x: 100,
+      y: 100,
+      width: 800,
+      height: 600,
+    };
+    let mapping = Mapping {
+      area: Area {
+        rect: Some(area),
+        ..Area::default()
+      },
+      ..square_view(Aspect::Letterbox)
+    };
+    // the bars of the letterbox are part of the surface then
+    assert_near(position(&mapping, 0.0, 0.0), (100.0 / 1920.0, 100.0 / 1080.0));
+    assert_near(position(&mapping, 0.5, 0.5), (500.0 / 1920.0, 400.0 / 1080.0));
+    assert_near(position(&mapping, 1.0, 1.0), (900.0 / 1920.0, 700.0 / 1080.0));
+  }
+
+  const SQUARE: Rect = Rect {
+    x: 0,
+    y: 0,
+    width: 1000,
+    height: 1000,
+  };
+
+  fn rotated(rotation: Rotation) -> Mapping {
+    Mapping {
+      area: Area {
+        rotation,
+        ..Area::default()
+      },
+      ..Mapping::new(SQUARE, SQUARE)
+    }
+  }
+
+  #[test]
+  fn rotates_positions() {
+    let expected = [
+      (Rotation::Deg0, (0.2, 0.1)),
+      (Rotation::Deg90, (0.9, 0.2)),
+      (Rotation::Deg180, (0.8, 0.9)),
+      (Rotation::Deg270, (0.1, 0.8)),
+    ];
+    for (rotation, expected) in expected {
+      assert_near(position(&rotated(rotation), 0.2, 0.1), expected);
+    }
+  }
+
+  #[test]
+  fn rotates_tilt_twist_and_size() {
+    let expected = [
+      (Rotation::Deg0, (10.0, 20.0), 350, (4.0, 2.0)),
+      (Rotation::Deg90, (-20.0, 10.0), 80, (2.0, 4.0)),
+      (Rotation::Deg180, (-10.0, -20.0), 170, (4.0, 2.0)),
+      (Rotation::Deg270, (20.0, -10.0), 260, (2.0, 4.0)),
+    ];
+    for (rotation, tilt, twist, size) in expected {
+      let event = rotated(rotation).apply(&PointerEvent {
+        tilt_x: 10.0,
+        tilt_y: 20.0,
+        twist: 350,
+        width: 4.0,
+        height: 2.0,
+        ..event(0.5, 0.5)
+      });
+      assert_near((event.tilt_x, event.tilt_y), tilt);
+      assert_eq!(event.twist, twist, "{:?}", rotation);
+      assert_near((event.width * 1000.0, event.height * 1000.0), size);
+    }
+  }
+
+  /// A fixed square area on a view twice as wide as high, or as high as wide when turned
+  fn locked(rotation: Rotation) -> Mapping {
+    let view = match rotation {
+      Rotation::Deg0 | Rotation::Deg180 => (1000.0, 500.0),
+      Rotation::Deg90 | Rotation::Deg270 => (500.0, 1000.0),
+    };
+    Mapping {
+      view: Some(view),
+      area: Area {
+        rect: Some(SQUARE),
+        lock_aspect: true,
+        rotation,
+      },
+      ..Mapping::new(SQUARE, SQUARE)
+    }
+  }
+
+  #[test]
+  fn leaves_a_band_unused_with_a_locked_aspect() {
+    let mapping = locked(Rotation::Deg0);
+    // the middle half of the width is used
+    assert_near(position(&mapping, 0.25, 0.0), (0.0, 0.0));
+    assert_near(position(&mapping, 0.5, 0.5), (0.5, 0.5));
+    assert_near(position(&mapping, 0.75, 1.0), (1.0, 1.0));
+    assert_near(position(&mapping, 0.1, 0.5), (0.0, 0.5));
+    assert_near(position(&mapping, 0.95, 0.5), (1.0, 0.5));
+    // and a round contact stays round
+    let event = mapping.apply(&event(0.5, 0.5));
+    assert_near((event.width, event.height), (0.02, 0.02));
+
+    let unlocked = Mapping {
+      area: Area {
+        lock_aspect: false,
+        ..mapping.area
+      },
+      ..mapping
+    };
+    assert_near(position(&unlocked, 0.25, 0.0), (0.25, 0.0));
+  }
+
+  #[test]
+  fn locks_the_aspect_of_the_turned_surface() {
+    let mapping = locked(Rotation::Deg90);
+    // the top of the portrait view is on the right of the desktop, and the middle half of its height is used
+    assert_near(position(&mapping, 0.5, 0.25), (1.0, 0.5));
+    assert_near(position(&mapping, 0.0, 0.5), (0.5, 0.0));
+    assert_near(position(&mapping, 0.5, 0.75), (0.0, 0.5));
+    let event = mapping.apply(&event(0.5, 0.5));
+    assert_near((event.width, event.height), (0.02, 0.02));
+  }
+}
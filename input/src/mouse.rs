@@ -0,0 +1,274 @@
+// Mouse input from mouse pointers, and emulated from pens and touches for applications that only handle the mouse
+//
+// Only one pointer drives the cursor at a time, so that a second finger cannot make it jump. A pen clicks once its
+// pressure reaches a threshold, and releases when the pressure drops well below it, so that a stroke does not
+// break up into several clicks.
+
+use std::io;
+
+use crate::{Button, InputBackend, MouseButton, MouseInput, PointerEvent, PointerEventType, PointerType};
+
+/// Pen pressure at which the left button goes down
+const CLICK_PRESSURE: f64 = 0.1;
+/// Fraction of the click pressure below which the left button goes up again
+const RELEASE_RATIO: f64 = 0.5;
+
+const BUTTONS: [MouseButton; 3] = [MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+
+pub struct Mouse {
+  click_pressure: f64,
+  /// Buttons held on the host
+  held: Vec<MouseButton>,
+  /// Pointer driving the cursor while it touches or holds a button
+  pointer: Option<(PointerType, u32)>,
+}
+
+impl Default for Mouse {
+  fn default() -> Self {
+    Self {
+      click_pressure: CLICK_PRESSURE,
+      held: vec![],
+      pointer: None,
+    }
+  }
+}
+
+impl Mouse {
+  pub fn new(click_pressure: f64) -> Self {
+    Self {
+      click_pressure,
+      ..Default::default()
+    }
+  }
+
+  pub fn set_click_pressure(&mut self, click_pressure: f64) {
+    self.click_pressure = click_pressure;
+  }
+
+  /// Move the cursor to the pointer and bring the buttons in line with it
+  pub fn handle(&mut self, event: &PointerEvent, backend: &mut impl InputBackend) -> io::Result<()> {
+    let pointer = (event.pointer_type, event.id);
+    if self.pointer.is_some_and(|driving| driving != pointer) {
+      return Ok(());
+    }
+    // a cancelled pointer, like a palm, neither moves the cursor nor clicks, but lets go of what it holds
+    if event.event_type == PointerEventType::Cancel {
+      return match self.pointer {
+        Some(_) => self.release_all(backend),
+        None => Ok(()),
+      };
+    }
+
+    let in_range = matches!(event.event_type, PointerEventType::Down | PointerEventType::Move);
+    let wanted = match in_range {
+      true => self.buttons(event),
+      false => vec![],
+    };
+    self.pointer = match wanted.is_empty() {
+      true => None,
+      false => Some(pointer),
+    };
+
+    backend.mouse(&MouseInput::Move { x: event.x, y: event.y })?;
+    for button in BUTTONS {
+      match (self.held.contains(&button), wanted.contains(&button)) {
+        (true, false) => self.release(button, backend)?,
+        (false, true) => self.press(button, backend)?,
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  /// Release every button held on the host
+  pub fn release_all(&mut self, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.pointer = None;
+    let mut result = Ok(());
+    while let Some(button) = self.held.pop() {
+      result = result.and(backend.mouse(&MouseInput::Button { button, down: false }));
+    }
+    result
+  }
+
+  /// Release the buttons if a pointer of the given type holds them, like a touch that turned out to be a palm
+  pub fn release_type(&mut self, pointer_type: PointerType, backend: &mut impl InputBackend) -> io::Result<()> {
+    match self.pointer {
+      Some((driving, _)) if driving == pointer_type => self.release_all(backend),
+      _ => Ok(()),
+    }
+  }
+
+  /// Buttons that the pointer holds down
+  fn buttons(&self, event: &PointerEvent) -> Vec<MouseButton> {
+    let mut buttons = vec![];
+    match event.pointer_type {
+      PointerType::Mouse => {
+        for (bit, button) in [
+          (Button::PRIMARY, MouseButton::Left),
+          (Button::SECONDARY, MouseButton::Right),
+          (Button::AUXILARY, MouseButton::Middle),
+        ] {
+          if event.buttons.contains(bit) {
+            buttons.push(button);
+          }
+        }
+      }
+      PointerType::Pen => {
+        let threshold = match self.held.contains(&MouseButton::Left) {
+          true => self.click_pressure * RELEASE_RATIO,
+          false => self.click_pressure,
+        };
+        if event.buttons.contains(Button::PRIMARY) && event.pressure >= threshold {
+          buttons.push(MouseButton::Left);
+        }
+        if event.buttons.contains(Button::SECONDARY) {
+          buttons.push(MouseButton::Right);
+        }
+        // the eraser end is free for panning and the like
+        if event.buttons.contains(Button::ERASER) {
+          buttons.push(MouseButton::Middle);
+        }
+      }
+      // fingers may not report pressure at all
+      PointerType::Touch => buttons.push(MouseButton::Left),
+    }
+    buttons
+  }
+
+  fn press(&mut self, button: MouseButton, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.held.push(button);
+    backend.mouse(&MouseInput::Button { button, down: true })
+  }
+
+  fn release(&mut self, button: MouseButton, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.held.retain(|held| *held != button);
+    backend.mouse(&MouseInput::Button { button, down: false })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Record, RecordingBackend};
+
+  use PointerEventType::{Down, Move, Up};
+
+  fn pointer(pointer_type: PointerType, event_type: PointerEventType, id: u32, x: f64, pressure: f64) -> PointerEvent {
+    PointerEvent {
+      event_type,
+      id,
+      pointer_type,
+      is_primary: id == 0,
+      x,
+      y: 0.5,
+      button: Button::NONE,
+      buttons: match event_type {
+        Down | Move => Button::PRIMARY,
+        _ => Button::NONE,
+      },
+      width: 0.01,
+      height: 0.01,
+      pressure,
+      tangential_pressure: 0.0,
+      tilt_x: 0.0,
+      tilt_y: 0.0,
+      twist: 0,
+    }
+  }
+
+  /// Transitions of the buttons, leaving out the moves
+  fn clicks(backend: &mut RecordingBackend) -> Vec<(MouseButton, bool)> {
+    backend
+      .take()
+      .into_iter()
+      .filter_map(|record| match record {
+        Record::Mouse(MouseInput::Button { button, down }) => Some((button, down)),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn clicks_once_the_pen_presses_hard_enough() {
+    let mut backend = RecordingBackend::new();
+    let mut mouse = Mouse::new(0.25);
+    for pressure in [0.125, 0.25, 0.25] {
+      mouse
+        .handle(&pointer(PointerType::Pen, Move, 1, 0.5, pressure), &mut backend)
+        .unwrap();
+    }
+    assert_eq!(clicks(&mut backend), [(MouseButton::Left, true)]);
+
+    // it stays down until the pressure falls below half of the click pressure
+    for pressure in [0.2, 0.125, 0.0625, 0.2] {
+      mouse
+        .handle(&pointer(PointerType::Pen, Move, 1, 0.5, pressure), &mut backend)
+        .unwrap();
+    }
+    assert_eq!(clicks(&mut backend), [(MouseButton::Left, false)]);
+  }
+
+  #[test]
+  fn follows_one_pointer_at_a_time() {
+    let mut backend = RecordingBackend::new();
+    let mut mouse = Mouse::default();
+    mouse
+      .handle(&pointer(PointerType::Touch, Down, 1, 0.25, 0.5), &mut backend)
+      .unwrap();
+    mouse
+      .handle(&pointer(PointerType::Touch, Down, 2, 0.75, 0.5), &mut backend)
+      .unwrap();
+    mouse
+      .handle(&pointer(PointerType::Touch, Up, 2, 0.75, 0.5), &mut backend)
+      .unwrap();
+    mouse
+      .handle(&pointer(PointerType::Touch, Up, 1, 0.5, 0.5), &mut backend)
+      .unwrap();
+    // the second finger can drive the cursor once the first lifted
+    mouse
+      .handle(&pointer(PointerType::Touch, Down, 2, 0.75, 0.5), &mut backend)
+      .unwrap();
+    let left = |down| {
+      Record::Mouse(MouseInput::Button {
+        button: MouseButton::Left,
+        down,
+      })
+    };
+    assert_eq!(
+      backend.take(),
+      [
+        Record::Mouse(MouseInput::Move { x: 0.25, y: 0.5 }),
+        left(true),
+        Record::Mouse(MouseInput::Move { x: 0.5, y: 0.5 }),
+        left(false),
+        Record::Mouse(MouseInput::Move { x: 0.75, y: 0.5 }),
+        left(true),
+      ]
+    );
+  }
+
+  #[test]
+  fn releases_the_buttons_of_a_pointer_type() {
+    let mut backend = RecordingBackend::new();
+    let mut mouse = Mouse::default();
+    mouse
+      .handle(&pointer(PointerType::Touch, Down, 1, 0.5, 0.5), &mut backend)
+      .unwrap();
+    mouse.release_type(PointerType::Pen, &mut backend).unwrap();
+    mouse.release_type(PointerType::Touch, &mut backend).unwrap();
+    assert_eq!(
+      clicks(&mut backend),
+      [(MouseButton::Left, true), (MouseButton::Left, false)]
+    );
+
+    // the cancelled touch no longer holds anything
+    mouse
+      .handle(
+        &pointer(PointerType::Touch, PointerEventType::Cancel, 1, 0.5, 0.5),
+        &mut backend,
+      )
+      .unwrap();
+    mouse.release_type(PointerType::Touch, &mut backend).unwrap();
+    assert!(backend.take().is_empty());
+  }
+}
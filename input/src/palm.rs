@@ -0,0 +1,319 @@
+// Rejection of touches that are most likely the palm of the hand holding the pen
+//
+// Touches are cancelled while the pen is in range or was just active, and when they are larger than a finger.
+// A touch that was cancelled stays rejected until it lifts, as its later events belong to a contact that no
+// longer exists. So does every touch that is down when the pen comes down.
+
+use std::{
+  collections::HashSet,
+  time::{Duration, Instant},
+};
+
+use crate::{Button, PointerEvent, PointerEventType, PointerType};
+
+/// How long after the last pen input touches are still rejected, as the palm rests before and after a stroke
+const PEN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Size from which a touch counts as a palm, in client pixels, unlimited if unset
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct PalmSize {
+  pub width: Option<f64>,
+  pub height: Option<f64>,
+}
+
+impl PalmSize {
+  /// Turn a touch that is too large into a cancel, which lifts it if it counted as a finger so far.
+  /// This needs the size that the client reports, so it comes before the `Mapping`.
+  pub fn reject(&self, event: PointerEvent) -> PointerEvent {
+    let too_large = |size: f64, limit: Option<f64>| limit.is_some_and(|limit| size > limit);
+    let palm = event.pointer_type == PointerType::Touch
+      && (too_large(event.width, self.width) || too_large(event.height, self.height));
+    match palm {
+      true => PointerEvent {
+        event_type: PointerEventType::Cancel,
+        ..event
+      },
+      false => event,
+    }
+  }
+}
+
+/// Keeps track of the pen for rejecting touches
+#[derive(Debug, Default)]
+pub struct Palm {
+  /// Whether the pen is hovering or touching, as far as the client tells
+  in_range: bool,
+  contact: bool,
+  /// When the last pen input arrived
+  seen: Option<Instant>,
+  /// Touches that are down and not rejected
+  touches: HashSet<u32>,
+  /// Touches that were cancelled, whose events are dropped until they lift or their id starts a new touch
+  rejected: HashSet<u32>,
+  /// Whether touches are let through whatever the pen does
+  disabled: bool,
+}
+
+impl Palm {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Reject touches because of the pen, or only the ones that were rejected before and did not lift yet
+  pub fn set_enabled(&mut self, enabled: bool) {
+    self.disabled = !enabled;
+  }
+
+  /// Note pen input, and return whether the pen just came down, so that the touches that are down get cancelled
+  pub fn pen_at(&mut self, event: &PointerEvent, now: Instant) -> bool {
+    let in_range = matches!(event.event_type, PointerEventType::Down | PointerEventType::Move);
+    // the barrel button may be pressed while the pen hovers
+    let contact = in_range && event.buttons.intersects(Button::PRIMARY | Button::ERASER);
+    let came_down = contact && !self.contact && !self.disabled;
+    self.in_range = in_range;
+    self.contact = contact;
+    self.seen = Some(now);
+    if came_down {
+      self.rejected.extend(self.touches.drain());
+    }
+    came_down
+  }
+
+  /// Reject the touches that are down, as they were cancelled
+  pub fn cancel_touches(&mut self) {
+    self.rejected.extend(self.touches.drain());
+  }
+
+  /// Turn a touch into a cancel if it is a palm, or drop it if it belongs to a touch that was rejected before
+  pub fn touch_at(&mut self, event: PointerEvent, now: Instant) -> Option<PointerEvent> {
+    let id = event.id;
+    match event.event_type {
+      // a new touch may reuse the id of one that was rejected
+      PointerEventType::Down => {
+        self.rejected.remove(&id);
+      }
+      PointerEventType::Up | PointerEventType::Leave if self.rejected.remove(&id) => return None,
+      _ if self.rejected.contains(&id) => return None,
+      _ => {}
+    }
+
+    // a cancel may be a touch that `PalmSize` rejected, whose next events may look like a finger again
+    let rejected = event.event_type == PointerEventType::Cancel || self.rejects_touches_at(now);
+    match event.event_type {
+      PointerEventType::Up | PointerEventType::Leave => {
+        self.touches.remove(&id);
+      }
+      _ if rejected => {
+        self.touches.remove(&id);
+        self.rejected.insert(id);
+      }
+      _ => {
+        self.touches.insert(id);
+      }
+    }
+    match rejected {
+      true => Some(PointerEvent {
+        event_type: PointerEventType::Cancel,
+        ..event
+      }),
+      false => Some(event),
+    }
+  }
+
+  /// Whether touches are palms at the moment
+  pub fn rejects_touches_at(&self, now: Instant) -> bool {
+    !self.disabled
+      && (self.in_range
+        || self
+          .seen
+          .is_some_and(|seen| now.saturating_duration_since(seen) < PEN_TIMEOUT))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Mouse, MouseButton, MouseInput, Record, RecordingBackend};
+
+  use PointerEventType::{Cancel, Down, Leave, Move, Up};
+
+  fn event(pointer_type: PointerType, event_type: PointerEventType, id: u32, buttons: Button) -> PointerEvent {
+    PointerEvent {
+      event_type,
+      id,
+      pointer_type,
+      is_primary: true,
+      x: 0.5,
+      y: 0.5,
+      button: Button::NONE,
+      buttons,
+      width: 0.01,
+      height: 0.01,
+      pressure: 0.5,
+      tangential_pressure: 0.0,
+      tilt_x: 0.0,
+      tilt_y: 0.0,
+      twist: 0,
+    }
+  }
+
+  fn pen(event_type: PointerEventType, buttons: Button) -> PointerEvent {
+    event(PointerType::Pen, event_type, 1, buttons)
+  }
+
+  fn touch(event_type: PointerEventType, id: u32) -> PointerEvent {
+    let buttons = match event_type {
+      Down | Move => Button::PRIMARY,
+      Up | Cancel | Leave => Button::NONE,
+    };
+    event(PointerType::Touch, event_type, id, buttons)
+  }
+
+  /// Touches emulating the mouse, like `InputDevices::emulate_mouse`
+  struct Emulation {
+    palm: Palm,
+    mouse: Mouse,
+    backend: RecordingBackend,
+    start: Instant,
+  }
+
+  impl Emulation {
+    fn new() -> Self {
+      Self {
+        palm: Palm::new(),
+        mouse: Mouse::default(),
+        backend: RecordingBackend::new(),
+        start: Instant::now(),
+      }
+    }
+
+    fn pen(&mut self, event: PointerEvent, millis: u64) {
+      self.palm.pen_at(&event, self.start + Duration::from_millis(millis));
+    }
+
+    fn touch(&mut self, event: PointerEvent, millis: u64) {
+      if let Some(event) = self.palm.touch_at(event, self.start + Duration::from_millis(millis)) {
+        self.mouse.handle(&event, &mut self.backend).unwrap();
+      }
+    }
+  }
+
+  fn clicked(records: &[Record]) -> bool {
+    records
+      .iter()
+      .any(|record| matches!(record, Record::Mouse(MouseInput::Button { down: true, .. })))
+  }
+
+  #[test]
+  fn rejects_palms_while_the_pen_hovers() {
+    let mut emulation = Emulation::new();
+    emulation.pen(pen(Move, Button::NONE), 0);
+    emulation.touch(touch(Down, 2), 10);
+    emulation.touch(touch(Move, 2), 20);
+    // the pen goes away, but the palm is still the palm
+    emulation.pen(pen(Leave, Button::NONE), 30);
+    emulation.touch(touch(Move, 2), 1000);
+    emulation.touch(touch(Up, 2), 1010);
+    assert_eq!(emulation.backend.take(), []);
+
+    // a new finger clicks again
+    emulation.touch(touch(Down, 3), 1020);
+    emulation.touch(touch(Up, 3), 1030);
+    assert!(clicked(&emulation.backend.take()));
+  }
+
+  #[test]
+  fn palms_outlast_the_pen_timeout() {
+    let mut emulation = Emulation::new();
+    emulation.pen(pen(Down, Button::PRIMARY), 0);
+    emulation.pen(pen(Up, Button::NONE), 10);
+    emulation.pen(pen(Leave, Button::NONE), 20);
+    emulation.touch(touch(Down, 2), 100);
+    emulation.touch(touch(Move, 2), 100 + PEN_TIMEOUT.as_millis() as u64 * 2);
+    emulation.touch(touch(Up, 2), 100 + PEN_TIMEOUT.as_millis() as u64 * 3);
+    assert_eq!(emulation.backend.take(), []);
+  }
+
+  #[test]
+  fn rejects_touches_that_are_down_when_the_pen_comes_down() {
+    let mut emulation = Emulation::new();
+    emulation.touch(touch(Down, 2), 0);
+    assert!(clicked(&emulation.backend.take()));
+    emulation.pen(pen(Down, Button::PRIMARY), 10);
+    // `InputDevices` cancels the touches for the mouse as the pen comes down
+    emulation.mouse.release_all(&mut emulation.backend).unwrap();
+    emulation.backend.take();
+    emulation.pen(pen(Leave, Button::NONE), 20);
+
+    emulation.touch(touch(Move, 2), 1000);
+    emulation.touch(touch(Up, 2), 1010);
+    assert_eq!(emulation.backend.take(), []);
+  }
+
+  #[test]
+  fn comes_down_with_the_tip_or_the_eraser() {
+    let mut palm = Palm::new();
+    let now = Instant::now();
+    assert!(!palm.pen_at(&pen(Move, Button::SECONDARY), now));
+    assert!(palm.pen_at(&pen(Move, Button::PRIMARY | Button::SECONDARY), now));
+    assert!(!palm.pen_at(&pen(Move, Button::SECONDARY), now));
+    assert!(palm.pen_at(&pen(Down, Button::ERASER), now));
+  }
+
+  #[test]
+  fn lets_touches_through_when_disabled() {
+    let mut emulation = Emulation::new();
+    emulation.touch(touch(Down, 2), 0);
+    emulation.palm.set_enabled(false);
+    emulation.pen(pen(Down, Button::PRIMARY), 10);
+    emulation.touch(touch(Up, 2), 20);
+    emulation.backend.take();
+    // a finger still clicks while the pen touches
+    emulation.touch(touch(Down, 3), 30);
+    assert!(clicked(&emulation.backend.take()));
+  }
+
+  #[test]
+  fn palms_stay_rejected_when_they_shrink() {
+    let mut emulation = Emulation::new();
+    // as `PalmSize` turns a large touch into a cancel
+    emulation.touch(touch(Cancel, 2), 0);
+    emulation.touch(touch(Move, 2), 10);
+    emulation.touch(touch(Up, 2), 20);
+    assert_eq!(emulation.backend.take(), []);
+  }
+
+  #[test]
+  fn cancels_let_go_of_the_mouse_without_moving_it() {
+    let mut emulation = Emulation::new();
+    emulation.touch(touch(Down, 2), 0);
+    emulation.backend.take();
+    emulation.touch(touch(Cancel, 2), 10);
+    assert_eq!(
+      emulation.backend.take(),
+      [Record::Mouse(MouseInput::Button {
+        button: MouseButton::Left,
+        down: false
+      })]
+    );
+  }
+
+  #[test]
+  fn rejects_large_touches() {
+    let size = PalmSize {
+      width: Some(40.0),
+      height: None,
+    };
+    let finger = PointerEvent {
+      width: 20.0,
+      ..touch(Move, 2)
+    };
+    let palm = PointerEvent {
+      width: 60.0,
+      ..touch(Move, 2)
+    };
+    assert_eq!(size.reject(finger).event_type, Move);
+    assert_eq!(size.reject(palm).event_type, Cancel);
+    assert_eq!(PalmSize::default().reject(palm).event_type, Move);
+  }
+}
@@ -0,0 +1,381 @@
+// Pen state tracked across events, so that backends get the transitions of the tip and the buttons
+//
+// Browsers report the tip as the primary button, the barrel button as the secondary one, and the eraser end as
+// a button of its own, all only while they are pressed. A pen that moves without any of them is hovering, which
+// backends inject as in range without contact, so that applications can show a brush cursor. It leaves range
+// when it is lifted, or with a `Leave` event if it only hovered.
+
+use std::{io, str::FromStr};
+
+use crate::{
+  Button, InputBackend, MouseButton, MouseInput, PenButton, PenInput, PenTool, PointerEvent, PointerEventType,
+};
+
+/// What the barrel button does
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Barrel {
+  /// Report it as the barrel button of the pen, and leave the rest to the applications
+  #[default]
+  Button,
+  /// Click the right mouse button where the pen is
+  RightClick,
+}
+
+impl FromStr for Barrel {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "button" => Ok(Barrel::Button),
+      "right-click" => Ok(Barrel::RightClick),
+      _ => Err(format!("unknown barrel action: {}", s)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+  event: PointerEvent,
+  tool: PenTool,
+  contact: bool,
+  barrel: bool,
+}
+
+/// Turns pen events into inputs with one button transition each, and remembers the buttons held on the host
+#[derive(Default)]
+pub struct Pen {
+  barrel: Barrel,
+  /// Until the pen leaves range
+  state: Option<State>,
+}
+
+impl Pen {
+  pub fn new(barrel: Barrel) -> Self {
+    Self { barrel, state: None }
+  }
+
+  pub fn set_barrel(&mut self, barrel: Barrel) {
+    self.barrel = barrel;
+  }
+
+  pub fn handle(&mut self, event: &PointerEvent, backend: &mut impl InputBackend) -> io::Result<()> {
+    let in_range = matches!(event.event_type, PointerEventType::Down | PointerEventType::Move);
+    let contact = in_range && event.buttons.intersects(Button::PRIMARY | Button::ERASER);
+    let barrel = in_range && event.buttons.contains(Button::SECONDARY);
+    // which end is used is only known while it touches, so the tool stays the same until then
+    let tool = match (event.buttons.contains(Button::ERASER), contact, self.state) {
+      (true, _, _) => PenTool::Eraser,
+      (false, true, _) => PenTool::Pen,
+      (false, false, Some(state)) => state.tool,
+      (false, false, None) => PenTool::Pen,
+    };
+
+    let mut state = match self.state {
+      // the other end has to leave range before this one enters
+      Some(state) if state.tool != tool => {
+        self.leave(event, backend)?;
+        None
+      }
+      state => state,
+    }
+    .unwrap_or(State {
+      event: *event,
+      tool,
+      contact: false,
+      barrel: false,
+    });
+    if !in_range && self.state.is_none() {
+      return Ok(());
+    }
+    state.event = *event;
+
+    let mut changed = false;
+    // releases go first, and the barrel goes before the tip, like on a physical pen
+    let mut changes = [(PenButton::Barrel, barrel), (PenButton::Tip, contact)];
+    changes.sort_by_key(|&(_, down)| down);
+    for (button, down) in changes {
+      let held = match button {
+        PenButton::Tip => &mut state.contact,
+        PenButton::Barrel => &mut state.barrel,
+      };
+      if *held != down {
+        *held = down;
+        self.emit(state, true, Some((button, down)), backend)?;
+        changed = true;
+      }
+    }
+    if !changed && in_range {
+      self.emit(state, true, None, backend)?;
+    }
+
+    self.state = Some(state);
+    if !in_range {
+      self.leave(event, backend)?;
+    }
+    Ok(())
+  }
+
+  /// Lift the pen and take it out of range, if it is in range
+  pub fn release(&mut self, backend: &mut impl InputBackend) -> io::Result<()> {
+    let Some(state) = self.state else {
+      return Ok(());
+    };
+    let event = PointerEvent {
+      event_type: PointerEventType::Cancel,
+      buttons: Button::NONE,
+      ..state.event
+    };
+    self.handle(&event, backend)
+  }
+
+  fn leave(&mut self, event: &PointerEvent, backend: &mut impl InputBackend) -> io::Result<()> {
+    let Some(mut state) = self.state.take() else {
+      return Ok(());
+    };
+    state.event = *event;
+    for button in [PenButton::Barrel, PenButton::Tip] {
+      let held = match button {
+        PenButton::Tip => &mut state.contact,
+        PenButton::Barrel => &mut state.barrel,
+      };
+      if *held {
+        *held = false;
+        self.emit(state, true, Some((button, false)), backend)?;
+      }
+    }
+    self.emit(state, false, None, backend)
+  }
+
+  fn emit(
+    &self,
+    state: State,
+    in_range: bool,
+    change: Option<(PenButton, bool)>,
+    backend: &mut impl InputBackend,
+  ) -> io::Result<()> {
+    if let (Barrel::RightClick, Some((PenButton::Barrel, down))) = (self.barrel, change) {
+      backend.mouse(&MouseInput::Move {
+        x: state.event.x,
+        y: state.event.y,
+      })?;
+      return backend.mouse(&MouseInput::Button {
+        button: MouseButton::Right,
+        down,
+      });
+    }
+    backend.pen(&PenInput {
+      event: state.event,
+      tool: state.tool,
+      in_range,
+      contact: state.contact,
+      barrel: state.barrel && self.barrel == Barrel::Button,
+      change,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{PointerType, Record, RecordingBackend};
+
+  use PointerEventType::{Down, Leave, Move, Up};
+
+  fn pen_event(event_type: PointerEventType, buttons: Button) -> PointerEvent {
+    PointerEvent {
+      event_type,
+      id: 1,
+      pointer_type: PointerType::Pen,
+      is_primary: true,
+      x: 0.5,
+      y: 0.25,
+      button: Button::NONE,
+      buttons,
+      width: 0.0,
+      height: 0.0,
+      pressure: match buttons.intersects(Button::PRIMARY | Button::ERASER) {
+        true => 0.5,
+        false => 0.0,
+      },
+      tangential_pressure: 0.0,
+      tilt_x: 0.0,
+      tilt_y: 0.0,
+      twist: 0,
+    }
+  }
+
+  /// Tool, in range, contact, barrel and change of an input
+  type Summary = (PenTool, bool, bool, bool, Option<(PenButton, bool)>);
+
+  fn inputs(backend: &mut RecordingBackend) -> Vec<Summary> {
+    backend
+      .take()
+      .into_iter()
+      .map(|record| match record {
+        Record::Pen(input) => (input.tool, input.in_range, input.contact, input.barrel, input.change),
+        record => panic!("unexpected record: {:?}", record),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn reports_the_barrel_button() {
+    let mut backend = RecordingBackend::new();
+    let mut pen = Pen::new(Barrel::Button);
+    pen.handle(&pen_event(Down, Button::PRIMARY), &mut backend).unwrap();
+    pen
+      .handle(&pen_event(Move, Button::PRIMARY | Button::SECONDARY), &mut backend)
+      .unwrap();
+    pen
+      .handle(&pen_event(Move, Button::PRIMARY | Button::SECONDARY), &mut backend)
+      .unwrap();
+    pen.handle(&pen_event(Move, Button::PRIMARY), &mut backend).unwrap();
+    assert_eq!(
+      inputs(&mut backend),
+      [
+        (PenTool::Pen, true, true, false, Some((PenButton::Tip, true))),
+        (PenTool::Pen, true, true, true, Some((PenButton::Barrel, true))),
+        (PenTool::Pen, true, true, true, None),
+        (PenTool::Pen, true, true, false, Some((PenButton::Barrel, false))),
+      ]
+    );
+  }
+
+  #[test]
+  fn releases_before_pressing() {
+    let mut backend = RecordingBackend::new();
+    let mut pen = Pen::new(Barrel::Button);
+    pen
+      .handle(&pen_event(Down, Button::PRIMARY | Button::SECONDARY), &mut backend)
+      .unwrap();
+    pen.handle(&pen_event(Move, Button::SECONDARY), &mut backend).unwrap();
+    pen.handle(&pen_event(Move, Button::PRIMARY), &mut backend).unwrap();
+    assert_eq!(
+      inputs(&mut backend),
+      [
+        (PenTool::Pen, true, false, true, Some((PenButton::Barrel, true))),
+        (PenTool::Pen, true, true, true, Some((PenButton::Tip, true))),
+        (PenTool::Pen, true, false, true, Some((PenButton::Tip, false))),
+        (PenTool::Pen, true, false, false, Some((PenButton::Barrel, false))),
+        (PenTool::Pen, true, true, false, Some((PenButton::Tip, true))),
+      ]
+    );
+  }
+
+  #[test]
+  fn switches_to_the_eraser() {
+    let mut backend = RecordingBackend::new();
+    let mut pen = Pen::new(Barrel::Button);
+    pen.handle(&pen_event(Down, Button::PRIMARY), &mut backend).unwrap();
+    pen.handle(&pen_event(Up, Button::NONE), &mut backend).unwrap();
+    backend.take();
+
+    // the pen end still hovers when the eraser end touches
+    pen.handle(&pen_event(Move, Button::NONE), &mut backend).unwrap();
+    pen.handle(&pen_event(Down, Button::ERASER), &mut backend).unwrap();
+    // and the eraser stays the tool while it hovers
+    pen.handle(&pen_event(Move, Button::NONE), &mut backend).unwrap();
+    pen.handle(&pen_event(Down, Button::PRIMARY), &mut backend).unwrap();
+    assert_eq!(
+      inputs(&mut backend),
+      [
+        (PenTool::Pen, true, false, false, None),
+        (PenTool::Pen, false, false, false, None),
+        (PenTool::Eraser, true, true, false, Some((PenButton::Tip, true))),
+        (PenTool::Eraser, true, false, false, Some((PenButton::Tip, false))),
+        (PenTool::Eraser, false, false, false, None),
+        (PenTool::Pen, true, true, false, Some((PenButton::Tip, true))),
+      ]
+    );
+  }
+
+  #[test]
+  fn right_clicks_with_the_barrel() {
+    let mut backend = RecordingBackend::new();
+    let mut pen = Pen::new(Barrel::RightClick);
+    pen.handle(&pen_event(Move, Button::NONE), &mut backend).unwrap();
+    backend.take();
+    pen.handle(&pen_event(Move, Button::SECONDARY), &mut backend).unwrap();
+    pen.handle(&pen_event(Move, Button::SECONDARY), &mut backend).unwrap();
+    pen.handle(&pen_event(Move, Button::NONE), &mut backend).unwrap();
+    let click = |down| {
+      [
+        Record::Mouse(MouseInput::Move { x: 0.5, y: 0.25 }),
+        Record::Mouse(MouseInput::Button {
+          button: MouseButton::Right,
+          down,
+        }),
+      ]
+    };
+    let hover = Record::Pen(PenInput {
+      event: pen_event(Move, Button::SECONDARY),
+      tool: PenTool::Pen,
+      in_range: true,
+      contact: false,
+      barrel: false,
+      change: None,
+    });
+    assert_eq!(backend.take(), [&click(true)[..], &[hover], &click(false)].concat());
+  }
+
+  #[test]
+  fn hovers_before_touching() {
+    let mut backend = RecordingBackend::new();
+    let mut pen = Pen::new(Barrel::Button);
+    pen.handle(&pen_event(Move, Button::NONE), &mut backend).unwrap();
+    pen.handle(&pen_event(Move, Button::NONE), &mut backend).unwrap();
+    pen.handle(&pen_event(Down, Button::PRIMARY), &mut backend).unwrap();
+    pen.handle(&pen_event(Up, Button::NONE), &mut backend).unwrap();
+    assert_eq!(
+      inputs(&mut backend),
+      [
+        (PenTool::Pen, true, false, false, None),
+        (PenTool::Pen, true, false, false, None),
+        (PenTool::Pen, true, true, false, Some((PenButton::Tip, true))),
+        (PenTool::Pen, true, false, false, Some((PenButton::Tip, false))),
+        (PenTool::Pen, false, false, false, None),
+      ]
+    );
+
+    // nothing is left in range
+    pen.release(&mut backend).unwrap();
+    assert!(backend.take().is_empty());
+  }
+
+  #[test]
+  fn leaves_after_hovering() {
+    let mut backend = RecordingBackend::new();
+    let mut pen = Pen::new(Barrel::Button);
+    pen.handle(&pen_event(Move, Button::SECONDARY), &mut backend).unwrap();
+    pen.handle(&pen_event(Leave, Button::NONE), &mut backend).unwrap();
+    // a leave of a pen that is out of range already is dropped
+    pen.handle(&pen_event(Leave, Button::NONE), &mut backend).unwrap();
+    assert_eq!(
+      inputs(&mut backend),
+      [
+        (PenTool::Pen, true, false, true, Some((PenButton::Barrel, true))),
+        (PenTool::Pen, true, false, false, Some((PenButton::Barrel, false))),
+        (PenTool::Pen, false, false, false, None),
+      ]
+    );
+  }
+
+  #[test]
+  fn releases_a_pen_in_contact() {
+    let mut backend = RecordingBackend::new();
+    let mut pen = Pen::new(Barrel::Button);
+    pen
+      .handle(&pen_event(Down, Button::PRIMARY | Button::SECONDARY), &mut backend)
+      .unwrap();
+    backend.take();
+    pen.release(&mut backend).unwrap();
+    assert_eq!(
+      inputs(&mut backend),
+      [
+        (PenTool::Pen, true, true, false, Some((PenButton::Barrel, false))),
+        (PenTool::Pen, true, false, false, Some((PenButton::Tip, false))),
+        (PenTool::Pen, false, false, false, None),
+      ]
+    );
+  }
+}
@@ -0,0 +1,225 @@
+// Pressure curves, which reshape the pen pressure reported by the client before it is injected
+//
+// The pressure between `min` and `max` is stretched to the full range, raised to `gamma`, and then put through
+// an optional cubic Bézier like CSS `cubic-bezier()`. Every valid curve maps 0 to 0 and 1 to 1, and never
+// decreases in between, so that pressing harder never gives a lighter line.
+
+use serde::{Deserialize, Serialize};
+
+/// Bisection steps for solving the Bézier, which is exact to well below what the backends can tell apart
+const BEZIER_STEPS: u32 = 32;
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PressureCurve {
+  /// Exponent of the pressure, where less than 1 makes light strokes heavier and more than 1 lighter
+  pub gamma: f64,
+  /// Control points (x1, y1, x2, y2) of a cubic Bézier from (0, 0) to (1, 1), all between 0 and 1
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub bezier: Option<[f64; 4]>,
+  /// Pressure up to which the pen gives none at all
+  pub min: f64,
+  /// Pressure from which the pen gives the full pressure
+  pub max: f64,
+}
+
+impl Default for PressureCurve {
+  fn default() -> Self {
+    Self {
+      gamma: 1.0,
+      bezier: None,
+      min: 0.0,
+      max: 1.0,
+    }
+  }
+}
+
+impl PressureCurve {
+  /// Check the ranges that keep the curve within 0 and 1 and never decreasing
+  pub fn validate(&self) -> Result<(), String> {
+    if !(self.gamma.is_finite() && self.gamma > 0.0) {
+      return Err(format!("gamma must be positive: {}", self.gamma));
+    }
+    if !(0.0 <= self.min && self.min < self.max && self.max <= 1.0) {
+      return Err(format!(
+        "min and max must be 0 <= min < max <= 1: {} and {}",
+        self.min, self.max
+      ));
+    }
+    if let Some(points) = self.bezier {
+      if !points.iter().all(|point| (0.0..=1.0).contains(point)) {
+        return Err(format!("bezier control points must be between 0 and 1: {:?}", points));
+      }
+    }
+    Ok(())
+  }
+
+  pub fn apply(&self, pressure: f64) -> f64 {
+    if pressure.is_nan() || pressure <= self.min {
+      return 0.0;
+    }
+    if pressure >= self.max {
+      return 1.0;
+    }
+    let x = ((pressure - self.min) / (self.max - self.min)).powf(self.gamma);
+    let y = match self.bezier {
+      Some([x1, y1, x2, y2]) => bezier(y1, y2, solve_bezier(x1, x2, x)),
+      None => x,
+    };
+    y.clamp(0.0, 1.0)
+  }
+}
+
+/// One coordinate of a cubic Bézier from 0 to 1 with the given control points, at `t`
+fn bezier(p1: f64, p2: f64, t: f64) -> f64 {
+  let s = 1.0 - t;
+  3.0 * s * s * t * p1 + 3.0 * s * t * t * p2 + t * t * t
+}
+
+/// The `t` at which the Bézier reaches `x`, which is unique as the x coordinate never decreases
+fn solve_bezier(x1: f64, x2: f64, x: f64) -> f64 {
+  let (mut low, mut high) = (0.0, 1.0);
+  for _ in 0..BEZIER_STEPS {
+    let t = (low + high) / 2.0;
+    if bezier(x1, x2, t) < x {
+      low = t;
+    } else {
+      high = t;
+    }
+  }
+  (low + high) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+  use proptest::prelude::*;
+
+  use super::*;
+
+  fn curve() -> impl Strategy<Value = PressureCurve> {
+    let unit = 0.0..=1.0f64;
+    (
+      0.1..10.0f64,
+      prop::option::of([unit.clone(), unit.clone(), unit.clone(), unit]),
+      0.0..0.5f64,
+      0.5..=1.0f64,
+    )
+      .prop_map(|(gamma, bezier, min, max)| PressureCurve {
+        gamma,
+        bezier,
+        min,
+        max,
+      })
+  }
+
+  proptest! {
+    #[test]
+    fn generated_curves_are_valid(curve in curve()) {
+      prop_assert_eq!(curve.validate(), Ok(()));
+    }
+
+    #[test]
+    fn keeps_the_endpoints(curve in curve()) {
+      prop_assert_eq!(curve.apply(0.0), 0.0);
+      prop_assert_eq!(curve.apply(1.0), 1.0);
+    }
+
+    #[test]
+    fn stays_within_range(curve in curve(), pressure in -1.0..2.0f64) {
+      let output = curve.apply(pressure);
+      prop_assert!((0.0..=1.0).contains(&output), "{} gave {}", pressure, output);
+    }
+
+    #[test]
+    fn never_decreases(curve in curve(), a in 0.0..=1.0f64, b in 0.0..=1.0f64) {
+      let (low, high) = (a.min(b), a.max(b));
+      prop_assert!(curve.apply(low) <= curve.apply(high), "{} gave more than {}", low, high);
+    }
+
+    #[test]
+    fn never_decreases_across_the_range(curve in curve()) {
+      let outputs: Vec<f64> = (0..=1000).map(|step| curve.apply(step as f64 / 1000.0)).collect();
+      for pair in outputs.windows(2) {
+        prop_assert!(pair[0] <= pair[1], "{:?}", pair);
+      }
+    }
+  }
+
+  #[test]
+  fn linear_by_default() {
+    let curve = PressureCurve::default();
+    for pressure in [0.0, 0.1, 0.25, 0.5, 0.9, 1.0] {
+      assert_eq!(curve.apply(pressure), pressure);
+    }
+  }
+
+  #[test]
+  fn straight_bezier_is_linear() {
+    let curve = PressureCurve {
+      bezier: Some([0.25, 0.25, 0.75, 0.75]),
+      ..Default::default()
+    };
+    for pressure in [0.1, 0.25, 0.5, 0.9] {
+      assert!((curve.apply(pressure) - pressure).abs() < 1e-6);
+    }
+  }
+
+  #[test]
+  fn gamma_bends_the_middle() {
+    let soft = PressureCurve {
+      gamma: 0.5,
+      ..Default::default()
+    };
+    let firm = PressureCurve {
+      gamma: 2.0,
+      ..Default::default()
+    };
+    assert!((soft.apply(0.25) - 0.5).abs() < 1e-9);
+    assert!((firm.apply(0.5) - 0.25).abs() < 1e-9);
+  }
+
+  #[test]
+  fn threshold_and_clamp() {
+    let curve = PressureCurve {
+      min: 0.1,
+      max: 0.8,
+      ..Default::default()
+    };
+    assert_eq!(curve.apply(0.05), 0.0);
+    assert_eq!(curve.apply(0.1), 0.0);
+    assert!((curve.apply(0.45) - 0.5).abs() < 1e-9);
+    assert_eq!(curve.apply(0.8), 1.0);
+    assert_eq!(curve.apply(0.95), 1.0);
+    assert_eq!(curve.apply(f64::NAN), 0.0);
+  }
+
+  #[test]
+  fn rejects_invalid_curves() {
+    let invalid = [
+      PressureCurve {
+        gamma: 0.0,
+        ..Default::default()
+      },
+      PressureCurve {
+        gamma: f64::INFINITY,
+        ..Default::default()
+      },
+      PressureCurve {
+        min: 0.5,
+        max: 0.5,
+        ..Default::default()
+      },
+      PressureCurve {
+        max: 1.5,
+        ..Default::default()
+      },
+      PressureCurve {
+        bezier: Some([0.5, 1.2, 0.5, 0.5]),
+        ..Default::default()
+      },
+    ];
+    for curve in invalid {
+      assert!(curve.validate().is_err(), "{:?}", curve);
+    }
+  }
+}
@@ -0,0 +1,513 @@
+// Touches used like the trackpad of a laptop, moving the cursor from where it is instead of to the finger
+//
+// One finger moves the cursor, two fingers scroll, a tap clicks, a two-finger tap right-clicks, and a touch right
+// after a tap drags.
+// The click of a tap is held back until no drag can follow it any more, so `expire` has to be called once the
+// `deadline` has passed.
+
+use std::{
+  io,
+  time::{Duration, Instant},
+};
+
+use crate::{Contacts, InputBackend, MouseButton, MouseInput, PointerEvent, PointerEventType, MAX_CONTACTS};
+
+/// Longest touch that still counts as a tap
+const TAP_TIMEOUT: Duration = Duration::from_millis(200);
+/// How soon after a tap the next touch has to start to drag
+const DRAG_TIMEOUT: Duration = Duration::from_millis(200);
+/// Farthest a finger may move during a tap, normalized to the desktop
+const TAP_SLOP: f64 = 0.01;
+/// Speed above which movements are no longer accelerated further, in desktops per second
+const MAX_SPEED: f64 = 4.0;
+/// Shortest time that a movement is assumed to take, as events may arrive in bursts
+const MIN_INTERVAL: Duration = Duration::from_millis(4);
+/// Wheel notches for moving two fingers across the whole desktop
+const SCROLL_SCALE: f64 = 30.0;
+
+/// Gain of the cursor over the finger, which grows with the speed of the finger
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Acceleration {
+  /// Gain of slow movements
+  pub sensitivity: f64,
+  /// Additional gain per desktop per second of speed
+  pub acceleration: f64,
+}
+
+impl Default for Acceleration {
+  fn default() -> Self {
+    Self {
+      sensitivity: 1.0,
+      acceleration: 1.0,
+    }
+  }
+}
+
+impl Acceleration {
+  pub fn gain(&self, speed: f64) -> f64 {
+    self.sensitivity * (1.0 + self.acceleration * speed.clamp(0.0, MAX_SPEED))
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Touch {
+  start: (f64, f64),
+  last: (f64, f64),
+  /// When the finger last moved
+  moved: Instant,
+}
+
+/// Touches from the first finger down to the last one up
+#[derive(Debug)]
+struct Sequence {
+  started: Instant,
+  /// Most fingers on the surface at once
+  fingers: usize,
+  /// Whether any finger moved too far for a tap
+  moved: bool,
+  cancelled: bool,
+  /// Whether the left button is held, as the sequence started right after a tap
+  drag: bool,
+}
+
+pub struct Trackpad {
+  acceleration: Acceleration,
+  contacts: Contacts,
+  touches: [Option<Touch>; MAX_CONTACTS],
+  /// Normalized to the desktop, and unknown until the first touch
+  cursor: Option<(f64, f64)>,
+  sequence: Option<Sequence>,
+  /// When the click of the last tap is sent, unless a drag starts before
+  pending: Option<Instant>,
+}
+
+impl Default for Trackpad {
+  fn default() -> Self {
+    Self {
+      acceleration: Acceleration::default(),
+      contacts: Contacts::new(),
+      touches: [None; MAX_CONTACTS],
+      cursor: None,
+      sequence: None,
+      pending: None,
+    }
+  }
+}
+
+impl Trackpad {
+  pub fn new(acceleration: Acceleration) -> Self {
+    Self {
+      acceleration,
+      ..Default::default()
+    }
+  }
+
+  pub fn set_acceleration(&mut self, acceleration: Acceleration) {
+    self.acceleration = acceleration;
+  }
+
+  /// When `expire` has to be called to send a held back click
+  pub fn deadline(&self) -> Option<Instant> {
+    self.pending
+  }
+
+  pub fn handle(&mut self, event: PointerEvent, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.handle_at(event, Instant::now(), backend)
+  }
+
+  pub fn handle_at(&mut self, event: PointerEvent, now: Instant, backend: &mut impl InputBackend) -> io::Result<()> {
+    // a click whose timeout went unnoticed comes before anything else
+    self.expire_at(now, backend)?;
+
+    let frame = self.contacts.update_at(event, now);
+    if frame.is_empty() {
+      return Ok(());
+    }
+
+    if self.sequence.is_none() {
+      let drag = self.pending.take().is_some();
+      self.sequence = Some(Sequence {
+        started: now,
+        fingers: 0,
+        moved: false,
+        cancelled: false,
+        drag,
+      });
+      // the position of the host cursor is unknown, so it starts out under the first finger
+      if self.cursor.is_none() {
+        self.cursor = Some((frame[0].x, frame[0].y));
+        backend.mouse(&MouseInput::Move {
+          x: frame[0].x,
+          y: frame[0].y,
+        })?;
+      }
+      if drag {
+        press(MouseButton::Left, true, backend)?;
+      }
+    }
+
+    // a single finger moves the cursor, and two scroll
+    let moved = |contact: &PointerEvent| match contact.event_type {
+      PointerEventType::Move => self.touches[contact.id as usize],
+      _ => None,
+    };
+    match frame.as_slice() {
+      [contact] => {
+        if let Some(touch) = moved(contact) {
+          self.move_cursor(touch, contact, now, backend)?;
+        }
+      }
+      [a, b] => {
+        if let (Some(touch_a), Some(touch_b)) = (moved(a), moved(b)) {
+          // the content follows the fingers, so moving them up scrolls down
+          let dx = (touch_a.last.0 + touch_b.last.0 - a.x - b.x) / 2.0;
+          let dy = (touch_a.last.1 + touch_b.last.1 - a.y - b.y) / 2.0;
+          if dx != 0.0 || dy != 0.0 {
+            backend.mouse(&MouseInput::Wheel {
+              dx: dx * SCROLL_SCALE,
+              dy: dy * SCROLL_SCALE,
+            })?;
+          }
+        }
+      }
+      _ => {}
+    }
+
+    let Some(sequence) = &mut self.sequence else {
+      return Ok(());
+    };
+    for contact in &frame {
+      let slot = contact.id as usize;
+      let position = (contact.x, contact.y);
+      let touch = self.touches[slot].get_or_insert(Touch {
+        start: position,
+        last: position,
+        moved: now,
+      });
+      if (position.0 - touch.start.0).hypot(position.1 - touch.start.1) > TAP_SLOP {
+        sequence.moved = true;
+      }
+      if position != touch.last {
+        touch.last = position;
+        touch.moved = now;
+      }
+      match contact.event_type {
+        PointerEventType::Up | PointerEventType::Leave => self.touches[slot] = None,
+        PointerEventType::Cancel => {
+          self.touches[slot] = None;
+          sequence.cancelled = true;
+        }
+        PointerEventType::Down | PointerEventType::Move => {}
+      }
+    }
+    sequence.fingers = sequence.fingers.max(frame.len());
+
+    if self.touches.iter().any(Option::is_some) {
+      return Ok(());
+    }
+    let Some(sequence) = self.sequence.take() else {
+      return Ok(());
+    };
+    let tap = !sequence.moved && !sequence.cancelled && now.duration_since(sequence.started) <= TAP_TIMEOUT;
+    if sequence.drag {
+      press(MouseButton::Left, false, backend)?;
+      // the second tap of a double tap
+      if tap {
+        click(MouseButton::Left, backend)?;
+      }
+    } else if tap {
+      match sequence.fingers {
+        1 => self.pending = Some(now + DRAG_TIMEOUT),
+        2 => click(MouseButton::Right, backend)?,
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  /// Send the click of a tap once no drag can follow it any more
+  pub fn expire(&mut self, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.expire_at(Instant::now(), backend)
+  }
+
+  pub fn expire_at(&mut self, now: Instant, backend: &mut impl InputBackend) -> io::Result<()> {
+    match self.pending {
+      Some(deadline) if deadline <= now => {
+        self.pending = None;
+        click(MouseButton::Left, backend)
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Lift every finger and release the button of a drag, dropping a click that is held back
+  pub fn release(&mut self, backend: &mut impl InputBackend) -> io::Result<()> {
+    self.contacts.cancel_all();
+    self.touches = [None; MAX_CONTACTS];
+    self.pending = None;
+    match self.sequence.take() {
+      Some(Sequence { drag: true, .. }) => press(MouseButton::Left, false, backend),
+      _ => Ok(()),
+    }
+  }
+
+  fn move_cursor(
+    &mut self,
+    touch: Touch,
+    contact: &PointerEvent,
+    now: Instant,
+    backend: &mut impl InputBackend,
+  ) -> io::Result<()> {
+    let (dx, dy) = (contact.x - touch.last.0, contact.y - touch.last.1);
+    if dx == 0.0 && dy == 0.0 {
+      return Ok(());
+    }
+    let interval = now.saturating_duration_since(touch.moved).max(MIN_INTERVAL);
+    let gain = self.acceleration.gain(dx.hypot(dy) / interval.as_secs_f64());
+
+    let (x, y) = self.cursor.unwrap_or((contact.x, contact.y));
+    let (x, y) = ((x + dx * gain).clamp(0.0, 1.0), (y + dy * gain).clamp(0.0, 1.0));
+    self.cursor = Some((x, y));
+    backend.mouse(&MouseInput::Move { x, y })
+  }
+}
+
+fn press(button: MouseButton, down: bool, backend: &mut impl InputBackend) -> io::Result<()> {
+  backend.mouse(&MouseInput::Button { button, down })
+}
+
+fn click(button: MouseButton, backend: &mut impl InputBackend) -> io::Result<()> {
+  press(button, true, backend)?;
+  press(button, false, backend)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Button, PointerType, Record, RecordingBackend};
+
+  use PointerEventType::{Down, Move, Up};
+
+  fn touch(event_type: PointerEventType, id: u32, x: f64, y: f64) -> PointerEvent {
+    PointerEvent {
+      event_type,
+      id,
+      pointer_type: PointerType::Touch,
+      is_primary: id == 0,
+      x,
+      y,
+      button: Button::NONE,
+      buttons: Button::NONE,
+      width: 0.01,
+      height: 0.01,
+      pressure: 0.5,
+      tangential_pressure: 0.0,
+      tilt_x: 0.0,
+      tilt_y: 0.0,
+      twist: 0,
+    }
+  }
+
+  fn ms(start: Instant, millis: u64) -> Instant {
+    start + Duration::from_millis(millis)
+  }
+
+  fn button(button: MouseButton, down: bool) -> Record {
+    Record::Mouse(MouseInput::Button { button, down })
+  }
+
+  /// A trackpad whose cursor was placed by a first touch, and its backend with that placement taken
+  fn placed(start: Instant) -> (Trackpad, RecordingBackend) {
+    let mut trackpad = Trackpad::default();
+    let mut backend = RecordingBackend::new();
+    trackpad
+      .handle_at(touch(Down, 0, 0.5, 0.5), start, &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Move, 0, 0.7, 0.5), ms(start, 10), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 0, 0.7, 0.5), ms(start, 20), &mut backend)
+      .unwrap();
+    backend.take();
+    (trackpad, backend)
+  }
+
+  #[test]
+  fn holds_back_the_click_of_a_tap() {
+    let start = Instant::now();
+    let (mut trackpad, mut backend) = placed(start);
+
+    trackpad
+      .handle_at(touch(Down, 0, 0.3, 0.3), ms(start, 100), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 0, 0.3, 0.3), ms(start, 250), &mut backend)
+      .unwrap();
+    assert_eq!(backend.take(), []);
+    assert_eq!(trackpad.deadline(), Some(ms(start, 250) + DRAG_TIMEOUT));
+
+    trackpad.expire_at(ms(start, 400), &mut backend).unwrap();
+    assert_eq!(backend.take(), []);
+    trackpad.expire_at(ms(start, 450), &mut backend).unwrap();
+    assert_eq!(
+      backend.take(),
+      [button(MouseButton::Left, true), button(MouseButton::Left, false)]
+    );
+    assert_eq!(trackpad.deadline(), None);
+  }
+
+  #[test]
+  fn slow_touches_do_not_click() {
+    let start = Instant::now();
+    let (mut trackpad, mut backend) = placed(start);
+
+    trackpad
+      .handle_at(touch(Down, 0, 0.3, 0.3), ms(start, 100), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 0, 0.3, 0.3), ms(start, 400), &mut backend)
+      .unwrap();
+    assert_eq!(trackpad.deadline(), None);
+    trackpad.expire_at(ms(start, 1000), &mut backend).unwrap();
+    assert_eq!(backend.take(), []);
+  }
+
+  #[test]
+  fn pending_click_comes_before_later_touches() {
+    let start = Instant::now();
+    let (mut trackpad, mut backend) = placed(start);
+
+    trackpad
+      .handle_at(touch(Down, 0, 0.3, 0.3), ms(start, 100), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 0, 0.3, 0.3), ms(start, 150), &mut backend)
+      .unwrap();
+    // the next touch starts too late to drag, so the tap clicks first and the touch only moves the cursor
+    trackpad
+      .handle_at(touch(Down, 0, 0.3, 0.3), ms(start, 500), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Move, 0, 0.4, 0.3), ms(start, 600), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 0, 0.4, 0.3), ms(start, 700), &mut backend)
+      .unwrap();
+    let records = backend.take();
+    assert_eq!(
+      records[..2],
+      [button(MouseButton::Left, true), button(MouseButton::Left, false)]
+    );
+    assert!(matches!(records[2..], [Record::Mouse(MouseInput::Move { .. })]));
+    assert_eq!(trackpad.deadline(), None);
+  }
+
+  #[test]
+  fn drags_after_a_tap() {
+    let start = Instant::now();
+    let (mut trackpad, mut backend) = placed(start);
+
+    trackpad
+      .handle_at(touch(Down, 0, 0.3, 0.3), ms(start, 100), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 0, 0.3, 0.3), ms(start, 150), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Down, 0, 0.3, 0.3), ms(start, 250), &mut backend)
+      .unwrap();
+    assert_eq!(backend.take(), [button(MouseButton::Left, true)]);
+    assert_eq!(trackpad.deadline(), None);
+
+    trackpad
+      .handle_at(touch(Move, 0, 0.4, 0.3), ms(start, 300), &mut backend)
+      .unwrap();
+    assert!(matches!(backend.take()[..], [Record::Mouse(MouseInput::Move { .. })]));
+    trackpad
+      .handle_at(touch(Up, 0, 0.4, 0.3), ms(start, 350), &mut backend)
+      .unwrap();
+    assert_eq!(backend.take(), [button(MouseButton::Left, false)]);
+  }
+
+  #[test]
+  fn moves_the_cursor_by_the_finger() {
+    let start = Instant::now();
+    let mut trackpad = Trackpad::new(Acceleration {
+      sensitivity: 2.0,
+      acceleration: 0.0,
+    });
+    let mut backend = RecordingBackend::new();
+
+    trackpad
+      .handle_at(touch(Down, 0, 0.5, 0.5), start, &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Move, 0, 0.6, 0.45), ms(start, 10), &mut backend)
+      .unwrap();
+    let Record::Mouse(MouseInput::Move { x, y }) = backend.take()[1] else {
+      panic!("the cursor did not move");
+    };
+    assert!((x - 0.7).abs() < 1e-9 && (y - 0.4).abs() < 1e-9);
+  }
+
+  #[test]
+  fn scrolls_with_two_fingers() {
+    let start = Instant::now();
+    let (mut trackpad, mut backend) = placed(start);
+
+    trackpad
+      .handle_at(touch(Down, 0, 0.4, 0.5), ms(start, 100), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Down, 1, 0.6, 0.5), ms(start, 110), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Move, 0, 0.4, 0.25), ms(start, 120), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Move, 1, 0.6, 0.25), ms(start, 130), &mut backend)
+      .unwrap();
+    // moving the fingers up scrolls down, and the center moves by an eighth of the desktop per step
+    assert_eq!(
+      backend.take(),
+      [
+        Record::Mouse(MouseInput::Wheel { dx: 0.0, dy: 3.75 }),
+        Record::Mouse(MouseInput::Wheel { dx: 0.0, dy: 3.75 }),
+      ]
+    );
+
+    // a scroll is no two-finger tap
+    trackpad
+      .handle_at(touch(Up, 0, 0.4, 0.25), ms(start, 140), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 1, 0.6, 0.25), ms(start, 150), &mut backend)
+      .unwrap();
+    assert_eq!(backend.take(), []);
+  }
+
+  #[test]
+  fn right_clicks_on_a_two_finger_tap() {
+    let start = Instant::now();
+    let (mut trackpad, mut backend) = placed(start);
+
+    trackpad
+      .handle_at(touch(Down, 0, 0.4, 0.5), ms(start, 100), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Down, 1, 0.6, 0.5), ms(start, 110), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 0, 0.4, 0.5), ms(start, 150), &mut backend)
+      .unwrap();
+    trackpad
+      .handle_at(touch(Up, 1, 0.6, 0.5), ms(start, 160), &mut backend)
+      .unwrap();
+    assert_eq!(
+      backend.take(),
+      [button(MouseButton::Right, true), button(MouseButton::Right, false)]
+    );
+    assert_eq!(trackpad.deadline(), None);
+  }
+}
@@ -0,0 +1,513 @@
+// Linux backend built on uinput virtual devices
+//
+// https://www.kernel.org/doc/html/latest/input/event-codes.html
+// https://www.kernel.org/doc/html/latest/input/multi-touch-protocol.html
+
+use std::io;
+
+use evdev::{
+  uinput::{VirtualDevice, VirtualDeviceBuilder},
+  AbsInfo, AbsoluteAxisType, AttributeSet, BusType, EventType, InputEvent, InputId, Key, PropType, RelativeAxisType,
+  UinputAbsSetup,
+};
+
+use crate::{
+  InputBackend, KeyInput, MouseButton, MouseInput, PenInput, PenTool, PointerEvent, PointerEventType, MAX_CONTACTS,
+};
+
+/// Logical maximum of the position axes, for both the pen and the touchscreen
+const POSITION_MAX: i32 = 32767;
+/// Position resolution in units per millimeter, which makes the surface about 330mm wide
+const POSITION_RESOLUTION: i32 = 100;
+const PRESSURE_MAX: i32 = 4095;
+/// Tilt is reported in tenths of a degree, and its resolution is in units per radian
+const TILT_MAX: i32 = 900;
+const TILT_RESOLUTION: i32 = 573;
+/// Logical maximum of the wheel of an airbrush, which goes down to the negative
+const TANGENTIAL_PRESSURE_MAX: i32 = 1023;
+
+const VENDOR_ID: u16 = 0x1209; // pid.codes
+const PEN_PRODUCT_ID: u16 = 0x0001;
+const TOUCH_PRODUCT_ID: u16 = 0x0002;
+const KEYBOARD_PRODUCT_ID: u16 = 0x0003;
+const MOUSE_PRODUCT_ID: u16 = 0x0004;
+
+/// High-resolution wheel units per notch
+const WHEEL_HI_RES: f64 = 120.0;
+
+fn abs(axis: AbsoluteAxisType, min: i32, max: i32, resolution: i32) -> UinputAbsSetup {
+  UinputAbsSetup::new(axis, AbsInfo::new(0, min, max, 0, 0, resolution))
+}
+
+fn key(key: Key, value: i32) -> InputEvent {
+  InputEvent::new(EventType::KEY, key.code(), value)
+}
+
+fn axis(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+  InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+}
+
+fn relative(axis: RelativeAxisType, value: i32) -> InputEvent {
+  InputEvent::new(EventType::RELATIVE, axis.0, value)
+}
+
+fn position(value: f64) -> i32 {
+  (value.clamp(0.0, 1.0) * POSITION_MAX as f64).round() as i32
+}
+
+fn pressure(value: f64) -> i32 {
+  (value.clamp(0.0, 1.0) * PRESSURE_MAX as f64).round() as i32
+}
+
+fn tangential_pressure(value: f64) -> i32 {
+  (value.clamp(-1.0, 1.0) * TANGENTIAL_PRESSURE_MAX as f64).round() as i32
+}
+
+fn tilt(degrees: f64) -> i32 {
+  (degrees.clamp(-90.0, 90.0) * TILT_MAX as f64 / 90.0).round() as i32
+}
+
+/// Translate a HID usage to a Linux key code, following `hid_keyboard` in drivers/hid/hid-input.c
+fn key_from_usage(usage: u16) -> Option<Key> {
+  let key = match usage {
+    0x04 => Key::KEY_A,
+    0x05 => Key::KEY_B,
+    0x06 => Key::KEY_C,
+    0x07 => Key::KEY_D,
+    0x08 => Key::KEY_E,
+    0x09 => Key::KEY_F,
+    0x0a => Key::KEY_G,
+    0x0b => Key::KEY_H,
+    0x0c => Key::KEY_I,
+    0x0d => Key::KEY_J,
+    0x0e => Key::KEY_K,
+    0x0f => Key::KEY_L,
+    0x10 => Key::KEY_M,
+    0x11 => Key::KEY_N,
+    0x12 => Key::KEY_O,
+    0x13 => Key::KEY_P,
+    0x14 => Key::KEY_Q,
+    0x15 => Key::KEY_R,
+    0x16 => Key::KEY_S,
+    0x17 => Key::KEY_T,
+    0x18 => Key::KEY_U,
+    0x19 => Key::KEY_V,
+    0x1a => Key::KEY_W,
+    0x1b => Key::KEY_X,
+    0x1c => Key::KEY_Y,
+    0x1d => Key::KEY_Z,
+    0x1e => Key::KEY_1,
+    0x1f => Key::KEY_2,
+    0x20 => Key::KEY_3,
+    0x21 => Key::KEY_4,
+    0x22 => Key::KEY_5,
+    0x23 => Key::KEY_6,
+    0x24 => Key::KEY_7,
+    0x25 => Key::KEY_8,
+    0x26 => Key::KEY_9,
+    0x27 => Key::KEY_0,
+    0x28 => Key::KEY_ENTER,
+    0x29 => Key::KEY_ESC,
+    0x2a => Key::KEY_BACKSPACE,
+    0x2b => Key::KEY_TAB,
+    0x2c => Key::KEY_SPACE,
+    0x2d => Key::KEY_MINUS,
+    0x2e => Key::KEY_EQUAL,
+    0x2f => Key::KEY_LEFTBRACE,
+    0x30 => Key::KEY_RIGHTBRACE,
+    0x31 => Key::KEY_BACKSLASH,
+    0x32 => Key::KEY_BACKSLASH,
+    0x33 => Key::KEY_SEMICOLON,
+    0x34 => Key::KEY_APOSTROPHE,
+    0x35 => Key::KEY_GRAVE,
+    0x36 => Key::KEY_COMMA,
+    0x37 => Key::KEY_DOT,
+    0x38 => Key::KEY_SLASH,
+    0x39 => Key::KEY_CAPSLOCK,
+    0x3a => Key::KEY_F1,
+    0x3b => Key::KEY_F2,
+    0x3c => Key::KEY_F3,
+    0x3d => Key::KEY_F4,
+    0x3e => Key::KEY_F5,
+    0x3f => Key::KEY_F6,
+    0x40 => Key::KEY_F7,
+    0x41 => Key::KEY_F8,
+    0x42 => Key::KEY_F9,
+    0x43 => Key::KEY_F10,
+    0x44 => Key::KEY_F11,
+    0x45 => Key::KEY_F12,
+    0x46 => Key::KEY_SYSRQ,
+    0x47 => Key::KEY_SCROLLLOCK,
+    0x48 => Key::KEY_PAUSE,
+    0x49 => Key::KEY_INSERT,
+    0x4a => Key::KEY_HOME,
+    0x4b => Key::KEY_PAGEUP,
+    0x4c => Key::KEY_DELETE,
+    0x4d => Key::KEY_END,
+    0x4e => Key::KEY_PAGEDOWN,
+    0x4f => Key::KEY_RIGHT,
+    0x50 => Key::KEY_LEFT,
+    0x51 => Key::KEY_DOWN,
+    0x52 => Key::KEY_UP,
+    0x53 => Key::KEY_NUMLOCK,
+    0x54 => Key::KEY_KPSLASH,
+    0x55 => Key::KEY_KPASTERISK,
+    0x56 => Key::KEY_KPMINUS,
+    0x57 => Key::KEY_KPPLUS,
+    0x58 => Key::KEY_KPENTER,
+    0x59 => Key::KEY_KP1,
+    0x5a => Key::KEY_KP2,
+    0x5b => Key::KEY_KP3,
+    0x5c => Key::KEY_KP4,
+    0x5d => Key::KEY_KP5,
+    0x5e => Key::KEY_KP6,
+    0x5f => Key::KEY_KP7,
+    0x60 => Key::KEY_KP8,
+    0x61 => Key::KEY_KP9,
+    0x62 => Key::KEY_KP0,
+    0x63 => Key::KEY_KPDOT,
+    0x64 => Key::KEY_102ND,
+    0x65 => Key::KEY_COMPOSE,
+    0x66 => Key::KEY_POWER,
+    0x67 => Key::KEY_KPEQUAL,
+    0x68 => Key::KEY_F13,
+    0x69 => Key::KEY_F14,
+    0x6a => Key::KEY_F15,
+    0x6b => Key::KEY_F16,
+    0x6c => Key::KEY_F17,
+    0x6d => Key::KEY_F18,
+    0x6e => Key::KEY_F19,
+    0x6f => Key::KEY_F20,
+    0x70 => Key::KEY_F21,
+    0x71 => Key::KEY_F22,
+    0x72 => Key::KEY_F23,
+    0x73 => Key::KEY_F24,
+    0x7f => Key::KEY_MUTE,
+    0x80 => Key::KEY_VOLUMEUP,
+    0x81 => Key::KEY_VOLUMEDOWN,
+    0x85 => Key::KEY_KPCOMMA,
+    0x87 => Key::KEY_RO,
+    0x88 => Key::KEY_KATAKANAHIRAGANA,
+    0x89 => Key::KEY_YEN,
+    0x8a => Key::KEY_HENKAN,
+    0x8b => Key::KEY_MUHENKAN,
+    0x90 => Key::KEY_HANGEUL,
+    0x91 => Key::KEY_HANJA,
+    0xe0 => Key::KEY_LEFTCTRL,
+    0xe1 => Key::KEY_LEFTSHIFT,
+    0xe2 => Key::KEY_LEFTALT,
+    0xe3 => Key::KEY_LEFTMETA,
+    0xe4 => Key::KEY_RIGHTCTRL,
+    0xe5 => Key::KEY_RIGHTSHIFT,
+    0xe6 => Key::KEY_RIGHTALT,
+    0xe7 => Key::KEY_RIGHTMETA,
+    _ => return None,
+  };
+  Some(key)
+}
+
+fn create_pen() -> io::Result<VirtualDevice> {
+  let mut keys = AttributeSet::<Key>::new();
+  keys.insert(Key::BTN_TOOL_PEN);
+  keys.insert(Key::BTN_TOOL_RUBBER);
+  keys.insert(Key::BTN_TOUCH);
+  keys.insert(Key::BTN_STYLUS);
+
+  let mut props = AttributeSet::<PropType>::new();
+  props.insert(PropType::DIRECT);
+
+  VirtualDeviceBuilder::new()?
+    .name("Remote Stylus Pen")
+    .input_id(InputId::new(BusType::BUS_VIRTUAL, VENDOR_ID, PEN_PRODUCT_ID, 1))
+    .with_properties(&props)?
+    .with_keys(&keys)?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_X, 0, POSITION_MAX, POSITION_RESOLUTION))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_Y, 0, POSITION_MAX, POSITION_RESOLUTION))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_PRESSURE, 0, PRESSURE_MAX, 0))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_TILT_X, -TILT_MAX, TILT_MAX, TILT_RESOLUTION))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_TILT_Y, -TILT_MAX, TILT_MAX, TILT_RESOLUTION))?
+    .with_absolute_axis(&abs(
+      AbsoluteAxisType::ABS_WHEEL,
+      -TANGENTIAL_PRESSURE_MAX,
+      TANGENTIAL_PRESSURE_MAX,
+      0,
+    ))?
+    .build()
+}
+
+fn create_touch() -> io::Result<VirtualDevice> {
+  let mut keys = AttributeSet::<Key>::new();
+  keys.insert(Key::BTN_TOUCH);
+
+  let mut props = AttributeSet::<PropType>::new();
+  props.insert(PropType::DIRECT);
+
+  VirtualDeviceBuilder::new()?
+    .name("Remote Stylus Touch")
+    .input_id(InputId::new(BusType::BUS_VIRTUAL, VENDOR_ID, TOUCH_PRODUCT_ID, 1))
+    .with_properties(&props)?
+    .with_keys(&keys)?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_X, 0, POSITION_MAX, POSITION_RESOLUTION))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_Y, 0, POSITION_MAX, POSITION_RESOLUTION))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_MT_SLOT, 0, MAX_CONTACTS as i32 - 1, 0))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, 0, u16::MAX as i32, 0))?
+    .with_absolute_axis(&abs(
+      AbsoluteAxisType::ABS_MT_POSITION_X,
+      0,
+      POSITION_MAX,
+      POSITION_RESOLUTION,
+    ))?
+    .with_absolute_axis(&abs(
+      AbsoluteAxisType::ABS_MT_POSITION_Y,
+      0,
+      POSITION_MAX,
+      POSITION_RESOLUTION,
+    ))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_MT_PRESSURE, 0, PRESSURE_MAX, 0))?
+    // contact size is in the units of the position, and orientation 1 turns the major axis from y to x
+    .with_absolute_axis(&abs(
+      AbsoluteAxisType::ABS_MT_TOUCH_MAJOR,
+      0,
+      POSITION_MAX,
+      POSITION_RESOLUTION,
+    ))?
+    .with_absolute_axis(&abs(
+      AbsoluteAxisType::ABS_MT_TOUCH_MINOR,
+      0,
+      POSITION_MAX,
+      POSITION_RESOLUTION,
+    ))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_MT_ORIENTATION, 0, 1, 0))?
+    .build()
+}
+
+/// An absolute pointer, like the tablet of a virtual machine
+fn create_mouse() -> io::Result<VirtualDevice> {
+  let mut keys = AttributeSet::<Key>::new();
+  keys.insert(Key::BTN_LEFT);
+  keys.insert(Key::BTN_RIGHT);
+  keys.insert(Key::BTN_MIDDLE);
+
+  let mut axes = AttributeSet::<RelativeAxisType>::new();
+  axes.insert(RelativeAxisType::REL_WHEEL);
+  axes.insert(RelativeAxisType::REL_HWHEEL);
+  axes.insert(RelativeAxisType::REL_WHEEL_HI_RES);
+  axes.insert(RelativeAxisType::REL_HWHEEL_HI_RES);
+
+  VirtualDeviceBuilder::new()?
+    .name("Remote Stylus Mouse")
+    .input_id(InputId::new(BusType::BUS_VIRTUAL, VENDOR_ID, MOUSE_PRODUCT_ID, 1))
+    .with_keys(&keys)?
+    .with_relative_axes(&axes)?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_X, 0, POSITION_MAX, 0))?
+    .with_absolute_axis(&abs(AbsoluteAxisType::ABS_Y, 0, POSITION_MAX, 0))?
+    .build()
+}
+
+fn create_keyboard() -> io::Result<VirtualDevice> {
+  let mut keys = AttributeSet::<Key>::new();
+  for usage in 0..=u8::MAX as u16 {
+    if let Some(key) = key_from_usage(usage) {
+      keys.insert(key);
+    }
+  }
+
+  VirtualDeviceBuilder::new()?
+    .name("Remote Stylus Keyboard")
+    .input_id(InputId::new(BusType::BUS_VIRTUAL, VENDOR_ID, KEYBOARD_PRODUCT_ID, 1))
+    .with_keys(&keys)?
+    .build()
+}
+
+pub struct UinputBackend {
+  pen: VirtualDevice,
+  touch: VirtualDevice,
+  keyboard: VirtualDevice,
+  mouse: VirtualDevice,
+  /// Scrolling not sent yet as whole notches, horizontally and vertically
+  wheel: (f64, f64),
+  /// The tool currently in proximity, if any
+  pen_tool: Option<Key>,
+  /// Pointer id of the contact occupying each multitouch slot
+  slots: [Option<u32>; MAX_CONTACTS],
+  next_tracking_id: i32,
+}
+
+impl UinputBackend {
+  /// Check that uinput can be opened, without creating any devices
+  pub fn probe() -> io::Result<()> {
+    VirtualDeviceBuilder::new().map(|_| ())
+  }
+
+  pub fn new() -> io::Result<Self> {
+    Ok(UinputBackend {
+      pen: create_pen()?,
+      touch: create_touch()?,
+      keyboard: create_keyboard()?,
+      mouse: create_mouse()?,
+      wheel: (0.0, 0.0),
+      pen_tool: None,
+      slots: [None; MAX_CONTACTS],
+      next_tracking_id: 0,
+    })
+  }
+}
+
+impl InputBackend for UinputBackend {
+  fn pen(&mut self, input: &PenInput) -> io::Result<()> {
+    let event = &input.event;
+    if !input.in_range {
+      let Some(tool) = self.pen_tool.take() else {
+        return Ok(());
+      };
+      return self.pen.emit(&[
+        axis(AbsoluteAxisType::ABS_PRESSURE, 0),
+        axis(AbsoluteAxisType::ABS_WHEEL, 0),
+        key(Key::BTN_STYLUS, 0),
+        key(Key::BTN_TOUCH, 0),
+        key(tool, 0),
+      ]);
+    }
+
+    let mut events = vec![];
+    let tool = match input.tool {
+      PenTool::Pen => Key::BTN_TOOL_PEN,
+      PenTool::Eraser => Key::BTN_TOOL_RUBBER,
+    };
+    if self.pen_tool != Some(tool) {
+      // the previous tool has to leave proximity before the new one enters
+      if let Some(previous) = self.pen_tool.take() {
+        self.pen.emit(&[key(Key::BTN_TOUCH, 0), key(previous, 0)])?;
+      }
+      events.push(key(tool, 1));
+      self.pen_tool = Some(tool);
+    }
+
+    let pressure = match input.contact {
+      true => pressure(event.pressure),
+      false => 0,
+    };
+    events.extend([
+      axis(AbsoluteAxisType::ABS_X, position(event.x)),
+      axis(AbsoluteAxisType::ABS_Y, position(event.y)),
+      axis(AbsoluteAxisType::ABS_PRESSURE, pressure),
+      axis(AbsoluteAxisType::ABS_TILT_X, tilt(event.tilt_x)),
+      axis(AbsoluteAxisType::ABS_TILT_Y, tilt(event.tilt_y)),
+      axis(
+        AbsoluteAxisType::ABS_WHEEL,
+        tangential_pressure(event.tangential_pressure),
+      ),
+      key(Key::BTN_STYLUS, input.barrel as i32),
+      key(Key::BTN_TOUCH, input.contact as i32),
+    ]);
+    self.pen.emit(&events)
+  }
+
+  fn touch(&mut self, contacts: &[PointerEvent]) -> io::Result<()> {
+    let mut events = vec![];
+
+    for contact in contacts {
+      let lifted = matches!(
+        contact.event_type,
+        PointerEventType::Up | PointerEventType::Cancel | PointerEventType::Leave
+      );
+
+      let slot = match self.slots.iter().position(|id| *id == Some(contact.id)) {
+        Some(slot) => {
+          events.push(axis(AbsoluteAxisType::ABS_MT_SLOT, slot as i32));
+          slot
+        }
+        None if lifted => continue,
+        None => {
+          // contacts beyond the number of slots are ignored
+          let Some(slot) = self.slots.iter().position(Option::is_none) else {
+            continue;
+          };
+          self.slots[slot] = Some(contact.id);
+          let tracking_id = self.next_tracking_id;
+          self.next_tracking_id = (self.next_tracking_id + 1) % (u16::MAX as i32 + 1);
+          events.push(axis(AbsoluteAxisType::ABS_MT_SLOT, slot as i32));
+          events.push(axis(AbsoluteAxisType::ABS_MT_TRACKING_ID, tracking_id));
+          slot
+        }
+      };
+
+      if lifted {
+        events.push(axis(AbsoluteAxisType::ABS_MT_TRACKING_ID, -1));
+        self.slots[slot] = None;
+      } else {
+        events.extend([
+          axis(AbsoluteAxisType::ABS_MT_POSITION_X, position(contact.x)),
+          axis(AbsoluteAxisType::ABS_MT_POSITION_Y, position(contact.y)),
+          axis(AbsoluteAxisType::ABS_MT_PRESSURE, pressure(contact.pressure)),
+          axis(
+            AbsoluteAxisType::ABS_MT_TOUCH_MAJOR,
+            position(contact.width.max(contact.height)),
+          ),
+          axis(
+            AbsoluteAxisType::ABS_MT_TOUCH_MINOR,
+            position(contact.width.min(contact.height)),
+          ),
+          axis(
+            AbsoluteAxisType::ABS_MT_ORIENTATION,
+            (contact.width > contact.height) as i32,
+          ),
+        ]);
+      }
+    }
+
+    // single touch emulation follows the first contact still on the surface
+    let first = self.slots.iter().flatten().next();
+    let first = first.and_then(|id| contacts.iter().find(|contact| contact.id == *id));
+    events.push(key(Key::BTN_TOUCH, first.is_some() as i32));
+    if let Some(first) = first {
+      events.push(axis(AbsoluteAxisType::ABS_X, position(first.x)));
+      events.push(axis(AbsoluteAxisType::ABS_Y, position(first.y)));
+    }
+
+    self.touch.emit(&events)
+  }
+
+  fn mouse(&mut self, input: &MouseInput) -> io::Result<()> {
+    match *input {
+      MouseInput::Move { x, y } => self.mouse.emit(&[
+        axis(AbsoluteAxisType::ABS_X, position(x)),
+        axis(AbsoluteAxisType::ABS_Y, position(y)),
+      ]),
+      MouseInput::Button { button, down } => {
+        let code = match button {
+          MouseButton::Left => Key::BTN_LEFT,
+          MouseButton::Right => Key::BTN_RIGHT,
+          MouseButton::Middle => Key::BTN_MIDDLE,
+        };
+        self.mouse.emit(&[key(code, down as i32)])
+      }
+      MouseInput::Wheel { dx, dy } => {
+        // positive wheel values scroll up, so the vertical axis is flipped
+        let (x, y) = (self.wheel.0 + dx, self.wheel.1 - dy);
+        let (notches_x, notches_y) = (x.trunc(), y.trunc());
+        self.wheel = (x - notches_x, y - notches_y);
+        let events = [
+          relative(RelativeAxisType::REL_HWHEEL_HI_RES, (dx * WHEEL_HI_RES).round() as i32),
+          relative(RelativeAxisType::REL_WHEEL_HI_RES, (-dy * WHEEL_HI_RES).round() as i32),
+          relative(RelativeAxisType::REL_HWHEEL, notches_x as i32),
+          relative(RelativeAxisType::REL_WHEEL, notches_y as i32),
+        ];
+        let events: Vec<_> = events.into_iter().filter(|event| event.value() != 0).collect();
+        self.mouse.emit(&events)
+      }
+    }
+  }
+
+  fn key(&mut self, input: &KeyInput) -> io::Result<()> {
+    let Some(code) = key_from_usage(input.usage) else {
+      return Ok(());
+    };
+    let value = match (input.down, input.repeat) {
+      (false, _) => 0,
+      (true, false) => 1,
+      (true, true) => 2,
+    };
+    self.keyboard.emit(&[key(code, value)])
+  }
+}
@@ -0,0 +1,527 @@
+// Windows backend built on the synthetic pointer API and `SendInput`
+
+use std::{io, mem, sync::Once};
+
+use windows::{
+  core::{s, w},
+  Win32::{
+    Foundation::{BOOL, HANDLE, HWND, LPARAM, POINT, RECT, TRUE},
+    Graphics::Gdi::{EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW},
+    System::LibraryLoader::{GetModuleHandleW, GetProcAddress},
+    UI::{
+      Controls::{
+        CreateSyntheticPointerDevice, DestroySyntheticPointerDevice, HSYNTHETICPOINTERDEVICE, POINTER_FEEDBACK_NONE,
+        POINTER_TYPE_INFO, POINTER_TYPE_INFO_0,
+      },
+      HiDpi::{SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2},
+      Input::{
+        KeyboardAndMouse::{
+          SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT, KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP,
+          KEYEVENTF_SCANCODE, MOUSEEVENTF_ABSOLUTE, MOUSEEVENTF_HWHEEL, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP,
+          MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP,
+          MOUSEEVENTF_VIRTUALDESK, MOUSEEVENTF_WHEEL, MOUSEINPUT, MOUSE_EVENT_FLAGS, VIRTUAL_KEY,
+        },
+        Pointer::{
+          InjectSyntheticPointerInput, POINTER_BUTTON_CHANGE_TYPE, POINTER_CHANGE_FIRSTBUTTON_DOWN,
+          POINTER_CHANGE_FIRSTBUTTON_UP, POINTER_CHANGE_NONE, POINTER_CHANGE_SECONDBUTTON_DOWN,
+          POINTER_CHANGE_SECONDBUTTON_UP, POINTER_FLAGS, POINTER_FLAG_CANCELED, POINTER_FLAG_DOWN,
+          POINTER_FLAG_FIRSTBUTTON, POINTER_FLAG_INCONTACT, POINTER_FLAG_INRANGE, POINTER_FLAG_PRIMARY,
+          POINTER_FLAG_SECONDBUTTON, POINTER_FLAG_UP, POINTER_FLAG_UPDATE, POINTER_INFO, POINTER_PEN_INFO,
+          POINTER_TOUCH_INFO,
+        },
+      },
+      WindowsAndMessaging::{
+        GetSystemMetrics, MONITORINFOF_PRIMARY, PEN_FLAG_BARREL, PEN_FLAG_ERASER, PEN_FLAG_INVERTED, PEN_FLAG_NONE,
+        PEN_MASK_PRESSURE, PEN_MASK_ROTATION, PEN_MASK_TILT_X, PEN_MASK_TILT_Y, POINTER_INPUT_TYPE, PT_PEN, PT_TOUCH,
+        SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN, TOUCH_FLAG_NONE,
+        TOUCH_MASK_CONTACTAREA, TOUCH_MASK_PRESSURE,
+      },
+    },
+  },
+};
+
+use crate::{
+  InputBackend, KeyInput, Monitor, MouseButton, MouseInput, PenButton, PenInput, PenTool, PointerEvent,
+  PointerEventType, Rect, MAX_CONTACTS,
+};
+
+const WHEEL_DELTA: f64 = 120.0;
+/// HIMETRIC units (0.01 mm) per pixel at the standard 96 DPI
+const HIMETRIC_PER_PIXEL: f64 = 2540.0 / 96.0;
+
+/// Use physical pixels everywhere, instead of coordinates scaled for a DPI-unaware process
+fn set_dpi_aware() {
+  static DPI_AWARE: Once = Once::new();
+  DPI_AWARE.call_once(|| {
+    // fails if the awareness was already set, e.g. by the manifest of the host
+    let _ = unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) };
+  });
+}
+
+/// Bounding box of all monitors, which positions from the client are normalized to
+fn virtual_screen() -> Rect {
+  unsafe {
+    Rect {
+      x: GetSystemMetrics(SM_XVIRTUALSCREEN),
+      y: GetSystemMetrics(SM_YVIRTUALSCREEN),
+      width: GetSystemMetrics(SM_CXVIRTUALSCREEN).max(1) as u32,
+      height: GetSystemMetrics(SM_CYVIRTUALSCREEN).max(1) as u32,
+    }
+  }
+}
+
+pub(crate) fn monitors() -> io::Result<Vec<Monitor>> {
+  set_dpi_aware();
+
+  unsafe extern "system" fn callback(monitor: HMONITOR, _: HDC, _: *mut RECT, data: LPARAM) -> BOOL {
+    let monitors = &mut *(data.0 as *mut Vec<Monitor>);
+    let mut info = MONITORINFOEXW::default();
+    info.monitorInfo.cbSize = mem::size_of::<MONITORINFOEXW>() as u32;
+    if GetMonitorInfoW(monitor, &mut info as *mut MONITORINFOEXW as *mut MONITORINFO).as_bool() {
+      let rect = info.monitorInfo.rcMonitor;
+      let name = info.szDevice.split(|&c| c == 0).next().unwrap_or_default();
+      monitors.push(Monitor {
+        name: String::from_utf16_lossy(name),
+        rect: Rect {
+          x: rect.left,
+          y: rect.top,
+          width: (rect.right - rect.left) as u32,
+          height: (rect.bottom - rect.top) as u32,
+        },
+        primary: info.monitorInfo.dwFlags & MONITORINFOF_PRIMARY != 0,
+      });
+    }
+    TRUE
+  }
+
+  let mut monitors: Vec<Monitor> = Vec::new();
+  let data = LPARAM(&mut monitors as *mut Vec<Monitor> as isize);
+  if !unsafe { EnumDisplayMonitors(HDC::default(), None, Some(callback), data) }.as_bool() {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(monitors)
+}
+
+fn pointer_info(
+  event: &PointerEvent,
+  pointer_type: POINTER_INPUT_TYPE,
+  mut pointer_flags: POINTER_FLAGS,
+  button_change: POINTER_BUTTON_CHANGE_TYPE,
+) -> (POINTER_INFO, f64, f64) {
+  if event.is_primary {
+    pointer_flags |= POINTER_FLAG_PRIMARY;
+  }
+
+  let desktop = virtual_screen();
+  let x = desktop.x as f64 + event.x * desktop.width as f64;
+  let y = desktop.y as f64 + event.y * desktop.height as f64;
+  let himetric = POINT {
+    x: (event.x * desktop.width as f64 * HIMETRIC_PER_PIXEL) as i32,
+    y: (event.y * desktop.height as f64 * HIMETRIC_PER_PIXEL) as i32,
+  };
+
+  let info = POINTER_INFO {
+    pointerType: pointer_type,
+    pointerId: event.id,
+    frameId: 0,
+    pointerFlags: pointer_flags,
+    sourceDevice: HANDLE::default(),
+    hwndTarget: HWND::default(),
+    ptPixelLocation: POINT {
+      x: x as i32,
+      y: y as i32,
+    },
+    ptHimetricLocation: himetric,
+    ptPixelLocationRaw: POINT {
+      x: x as i32,
+      y: y as i32,
+    },
+    ptHimetricLocationRaw: himetric,
+    dwTime: 0,
+    historyCount: 1,
+    InputData: 0,
+    dwKeyStates: 0,
+    PerformanceCount: 0,
+    ButtonChangeType: button_change,
+  };
+  (info, x, y)
+}
+
+fn pen_info(input: &PenInput) -> POINTER_TYPE_INFO {
+  let event = &input.event;
+  let mut pointer_flags = match input.change {
+    Some((PenButton::Tip, true)) => POINTER_FLAG_DOWN,
+    Some((PenButton::Tip, false)) => POINTER_FLAG_UP,
+    _ => POINTER_FLAG_UPDATE,
+  };
+  if input.in_range {
+    pointer_flags |= POINTER_FLAG_INRANGE;
+  }
+  if input.contact {
+    pointer_flags |= POINTER_FLAG_INCONTACT | POINTER_FLAG_FIRSTBUTTON;
+  }
+  if input.barrel {
+    pointer_flags |= POINTER_FLAG_SECONDBUTTON;
+  }
+  if event.event_type == PointerEventType::Cancel {
+    pointer_flags |= POINTER_FLAG_CANCELED;
+  }
+  let button_change = match input.change {
+    Some((PenButton::Tip, true)) => POINTER_CHANGE_FIRSTBUTTON_DOWN,
+    Some((PenButton::Tip, false)) => POINTER_CHANGE_FIRSTBUTTON_UP,
+    Some((PenButton::Barrel, true)) => POINTER_CHANGE_SECONDBUTTON_DOWN,
+    Some((PenButton::Barrel, false)) => POINTER_CHANGE_SECONDBUTTON_UP,
+    None => POINTER_CHANGE_NONE,
+  };
+
+  // the eraser end is inverted while in range, and erasing while touching
+  let mut pen_flags = PEN_FLAG_NONE;
+  if input.barrel {
+    pen_flags |= PEN_FLAG_BARREL;
+  }
+  if input.tool == PenTool::Eraser {
+    pen_flags |= PEN_FLAG_INVERTED;
+    if input.contact {
+      pen_flags |= PEN_FLAG_ERASER;
+    }
+  }
+
+  let (pointer_info, _, _) = pointer_info(event, PT_PEN, pointer_flags, button_change);
+  POINTER_TYPE_INFO {
+    r#type: PT_PEN,
+    Anonymous: POINTER_TYPE_INFO_0 {
+      penInfo: POINTER_PEN_INFO {
+        pointerInfo: pointer_info,
+        penFlags: pen_flags,
+        penMask: PEN_MASK_PRESSURE | PEN_MASK_ROTATION | PEN_MASK_TILT_X | PEN_MASK_TILT_Y,
+        // a hovering pen may still report the pressure of its last contact
+        pressure: match input.contact {
+          true => (event.pressure * 1024.0) as u32,
+          false => 0,
+        },
+        // there is no tangential pressure for pens on Windows
+        rotation: event.twist,
+        tiltX: event.tilt_x.round() as i32,
+        tiltY: event.tilt_y.round() as i32,
+      },
+    },
+  }
+}
+
+fn touch_info(event: &PointerEvent) -> POINTER_TYPE_INFO {
+  let pointer_flags = match event.event_type {
+    PointerEventType::Down => POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT | POINTER_FLAG_DOWN,
+    PointerEventType::Move => POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT | POINTER_FLAG_UPDATE,
+    PointerEventType::Up | PointerEventType::Leave => POINTER_FLAG_UP,
+    PointerEventType::Cancel => POINTER_FLAG_INRANGE | POINTER_FLAG_UPDATE | POINTER_FLAG_CANCELED,
+  };
+  let (pointer_info, x, y) = pointer_info(event, PT_TOUCH, pointer_flags, POINTER_CHANGE_NONE);
+
+  let desktop = virtual_screen();
+  let width_half = event.width * desktop.width as f64 / 2.0;
+  let height_half = event.height * desktop.height as f64 / 2.0;
+  let contact_area = RECT {
+    left: (x - width_half) as i32,
+    top: (y - height_half) as i32,
+    right: (x + width_half) as i32,
+    bottom: (y + height_half) as i32,
+  };
+
+  POINTER_TYPE_INFO {
+    r#type: PT_TOUCH,
+    Anonymous: POINTER_TYPE_INFO_0 {
+      touchInfo: POINTER_TOUCH_INFO {
+        pointerInfo: pointer_info,
+        touchFlags: TOUCH_FLAG_NONE,
+        touchMask: TOUCH_MASK_CONTACTAREA | TOUCH_MASK_PRESSURE,
+        rcContact: contact_area,
+        rcContactRaw: RECT::default(),
+        orientation: 0,
+        pressure: (event.pressure * 1024.0) as u32,
+      },
+    },
+  }
+}
+
+/// Translate a HID usage to a set 1 scan code, and whether it needs the E0 (extended) prefix
+fn scancode_from_usage(usage: u16) -> Option<(u16, bool)> {
+  let scancode = match usage {
+    0x04 => (0x1e, false),
+    0x05 => (0x30, false),
+    0x06 => (0x2e, false),
+    0x07 => (0x20, false),
+    0x08 => (0x12, false),
+    0x09 => (0x21, false),
+    0x0a => (0x22, false),
+    0x0b => (0x23, false),
+    0x0c => (0x17, false),
+    0x0d => (0x24, false),
+    0x0e => (0x25, false),
+    0x0f => (0x26, false),
+    0x10 => (0x32, false),
+    0x11 => (0x31, false),
+    0x12 => (0x18, false),
+    0x13 => (0x19, false),
+    0x14 => (0x10, false),
+    0x15 => (0x13, false),
+    0x16 => (0x1f, false),
+    0x17 => (0x14, false),
+    0x18 => (0x16, false),
+    0x19 => (0x2f, false),
+    0x1a => (0x11, false),
+    0x1b => (0x2d, false),
+    0x1c => (0x15, false),
+    0x1d => (0x2c, false),
+    0x1e => (0x02, false),
+    0x1f => (0x03, false),
+    0x20 => (0x04, false),
+    0x21 => (0x05, false),
+    0x22 => (0x06, false),
+    0x23 => (0x07, false),
+    0x24 => (0x08, false),
+    0x25 => (0x09, false),
+    0x26 => (0x0a, false),
+    0x27 => (0x0b, false),
+    0x28 => (0x1c, false),
+    0x29 => (0x01, false),
+    0x2a => (0x0e, false),
+    0x2b => (0x0f, false),
+    0x2c => (0x39, false),
+    0x2d => (0x0c, false),
+    0x2e => (0x0d, false),
+    0x2f => (0x1a, false),
+    0x30 => (0x1b, false),
+    0x31 => (0x2b, false),
+    0x32 => (0x2b, false),
+    0x33 => (0x27, false),
+    0x34 => (0x28, false),
+    0x35 => (0x29, false),
+    0x36 => (0x33, false),
+    0x37 => (0x34, false),
+    0x38 => (0x35, false),
+    0x39 => (0x3a, false),
+    0x3a => (0x3b, false),
+    0x3b => (0x3c, false),
+    0x3c => (0x3d, false),
+    0x3d => (0x3e, false),
+    0x3e => (0x3f, false),
+    0x3f => (0x40, false),
+    0x40 => (0x41, false),
+    0x41 => (0x42, false),
+    0x42 => (0x43, false),
+    0x43 => (0x44, false),
+    0x44 => (0x57, false),
+    0x45 => (0x58, false),
+    0x46 => (0x37, true),
+    0x47 => (0x46, false),
+    0x48 => (0x45, false),
+    0x49 => (0x52, true),
+    0x4a => (0x47, true),
+    0x4b => (0x49, true),
+    0x4c => (0x53, true),
+    0x4d => (0x4f, true),
+    0x4e => (0x51, true),
+    0x4f => (0x4d, true),
+    0x50 => (0x4b, true),
+    0x51 => (0x50, true),
+    0x52 => (0x48, true),
+    0x53 => (0x45, true),
+    0x54 => (0x35, true),
+    0x55 => (0x37, false),
+    0x56 => (0x4a, false),
+    0x57 => (0x4e, false),
+    0x58 => (0x1c, true),
+    0x59 => (0x4f, false),
+    0x5a => (0x50, false),
+    0x5b => (0x51, false),
+    0x5c => (0x4b, false),
+    0x5d => (0x4c, false),
+    0x5e => (0x4d, false),
+    0x5f => (0x47, false),
+    0x60 => (0x48, false),
+    0x61 => (0x49, false),
+    0x62 => (0x52, false),
+    0x63 => (0x53, false),
+    0x64 => (0x56, false),
+    0x65 => (0x5d, true),
+    0x66 => (0x5e, true),
+    0x67 => (0x59, false),
+    0x68 => (0x64, false),
+    0x69 => (0x65, false),
+    0x6a => (0x66, false),
+    0x6b => (0x67, false),
+    0x6c => (0x68, false),
+    0x6d => (0x69, false),
+    0x6e => (0x6a, false),
+    0x6f => (0x6b, false),
+    0x70 => (0x6c, false),
+    0x71 => (0x6d, false),
+    0x72 => (0x6e, false),
+    0x73 => (0x76, false),
+    0x7f => (0x20, true),
+    0x80 => (0x30, true),
+    0x81 => (0x2e, true),
+    0x85 => (0x7e, false),
+    0x87 => (0x73, false),
+    0x88 => (0x70, false),
+    0x89 => (0x7d, false),
+    0x8a => (0x79, false),
+    0x8b => (0x7b, false),
+    0x90 => (0xf2, false),
+    0x91 => (0xf1, false),
+    0xe0 => (0x1d, false),
+    0xe1 => (0x2a, false),
+    0xe2 => (0x38, false),
+    0xe3 => (0x5b, true),
+    0xe4 => (0x1d, true),
+    0xe5 => (0x36, false),
+    0xe6 => (0x38, true),
+    0xe7 => (0x5c, true),
+    _ => return None,
+  };
+  Some(scancode)
+}
+
+pub struct Win32Backend {
+  touch: HSYNTHETICPOINTERDEVICE,
+  pen: HSYNTHETICPOINTERDEVICE,
+}
+
+impl Drop for Win32Backend {
+  fn drop(&mut self) {
+    unsafe {
+      DestroySyntheticPointerDevice(self.touch);
+      DestroySyntheticPointerDevice(self.pen);
+    }
+  }
+}
+
+impl Win32Backend {
+  /// Check that the synthetic pointer API is available, which it is from Windows 10 1809 on, without creating
+  /// any devices
+  pub fn probe() -> io::Result<()> {
+    let user32 = unsafe { GetModuleHandleW(w!("user32.dll"))? };
+    match unsafe { GetProcAddress(user32, s!("CreateSyntheticPointerDevice")) } {
+      Some(_) => Ok(()),
+      None => Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "synthetic pointer devices are not available",
+      )),
+    }
+  }
+
+  pub fn new() -> io::Result<Self> {
+    set_dpi_aware();
+    let touch = unsafe { CreateSyntheticPointerDevice(PT_TOUCH, MAX_CONTACTS as u32, POINTER_FEEDBACK_NONE)? };
+    let pen = match unsafe { CreateSyntheticPointerDevice(PT_PEN, 1, POINTER_FEEDBACK_NONE) } {
+      Ok(pen) => pen,
+      Err(e) => {
+        unsafe { DestroySyntheticPointerDevice(touch) };
+        return Err(e.into());
+      }
+    };
+    Ok(Win32Backend { touch, pen })
+  }
+
+  fn send_mouse(&self, dx: i32, dy: i32, data: i32, flags: MOUSE_EVENT_FLAGS) -> io::Result<()> {
+    let input = INPUT {
+      r#type: INPUT_MOUSE,
+      Anonymous: INPUT_0 {
+        mi: MOUSEINPUT {
+          dx,
+          dy,
+          mouseData: data as u32,
+          dwFlags: flags,
+          time: 0,
+          dwExtraInfo: 0,
+        },
+      },
+    };
+    send_input(&input)
+  }
+}
+
+fn send_input(input: &INPUT) -> io::Result<()> {
+  let sent = unsafe { SendInput(std::slice::from_ref(input), std::mem::size_of::<INPUT>() as i32) };
+  if sent == 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+impl InputBackend for Win32Backend {
+  fn pen(&mut self, input: &PenInput) -> io::Result<()> {
+    unsafe { InjectSyntheticPointerInput(self.pen, &[pen_info(input)])? };
+    Ok(())
+  }
+
+  fn touch(&mut self, contacts: &[PointerEvent]) -> io::Result<()> {
+    let infos = contacts.iter().map(touch_info).collect::<Vec<_>>();
+    unsafe { InjectSyntheticPointerInput(self.touch, &infos)? };
+    Ok(())
+  }
+
+  fn mouse(&mut self, input: &MouseInput) -> io::Result<()> {
+    match *input {
+      MouseInput::Move { x, y } => {
+        // absolute coordinates are normalized to 0..=65535 over the virtual desktop
+        let dx = (x.clamp(0.0, 1.0) * 65535.0) as i32;
+        let dy = (y.clamp(0.0, 1.0) * 65535.0) as i32;
+        self.send_mouse(
+          dx,
+          dy,
+          0,
+          MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK,
+        )
+      }
+      MouseInput::Button { button, down } => {
+        let flags = match (button, down) {
+          (MouseButton::Left, true) => MOUSEEVENTF_LEFTDOWN,
+          (MouseButton::Left, false) => MOUSEEVENTF_LEFTUP,
+          (MouseButton::Right, true) => MOUSEEVENTF_RIGHTDOWN,
+          (MouseButton::Right, false) => MOUSEEVENTF_RIGHTUP,
+          (MouseButton::Middle, true) => MOUSEEVENTF_MIDDLEDOWN,
+          (MouseButton::Middle, false) => MOUSEEVENTF_MIDDLEUP,
+        };
+        self.send_mouse(0, 0, 0, flags)
+      }
+      MouseInput::Wheel { dx, dy } => {
+        // positive WHEEL deltas scroll up, positive HWHEEL deltas scroll right
+        if dy != 0.0 {
+          self.send_mouse(0, 0, (-dy * WHEEL_DELTA) as i32, MOUSEEVENTF_WHEEL)?;
+        }
+        if dx != 0.0 {
+          self.send_mouse(0, 0, (dx * WHEEL_DELTA) as i32, MOUSEEVENTF_HWHEEL)?;
+        }
+        Ok(())
+      }
+    }
+  }
+
+  fn key(&mut self, input: &KeyInput) -> io::Result<()> {
+    let Some((scancode, extended)) = scancode_from_usage(input.usage) else {
+      return Ok(());
+    };
+    // a repeat is just another key down, like the typematic repeat of a real keyboard
+    let mut flags = KEYEVENTF_SCANCODE;
+    if extended {
+      flags |= KEYEVENTF_EXTENDEDKEY;
+    }
+    if !input.down {
+      flags |= KEYEVENTF_KEYUP;
+    }
+    let input = INPUT {
+      r#type: INPUT_KEYBOARD,
+      Anonymous: INPUT_0 {
+        ki: KEYBDINPUT {
+          wVk: VIRTUAL_KEY(0),
+          wScan: scancode,
+          dwFlags: flags,
+          time: 0,
+          dwExtraInfo: 0,
+        },
+      },
+    };
+    send_input(&input)
+  }
+}
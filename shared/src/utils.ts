@@ -63,7 +63,7 @@ export class Reorderer<T extends { i: number }> {
 }
 
 export interface MsgpackPointerEventInfo {
-  eventType: 'up' | 'move' | 'down' | 'cancel'
+  eventType: 'up' | 'move' | 'down' | 'cancel' | 'leave'
   pointerId: number
   pointerType: string
   isPrimary: boolean
@@ -78,12 +78,21 @@ export interface MsgpackPointerEventInfo {
   tiltX: number
   tiltY: number
   twist: number
+  /** More precise than the tilt, where the browser has them */
+  altitudeAngle: number | null
+  azimuthAngle: number | null
+}
+
+/** Not in the DOM types of every TypeScript version yet */
+interface PointerAngles {
+  altitudeAngle?: number
+  azimuthAngle?: number
 }
 
 export class MsgpackPointerEvent {
   constructor(public info: MsgpackPointerEventInfo) {}
   static fromEvent(
-    eventType: 'up' | 'move' | 'down' | 'cancel',
+    eventType: 'up' | 'move' | 'down' | 'cancel' | 'leave',
     e: PointerEvent,
     rect: DOMRect,
   ): MsgpackPointerEvent {
@@ -103,6 +112,8 @@ export class MsgpackPointerEvent {
       tiltX: e.tiltX,
       tiltY: e.tiltY,
       twist: e.twist,
+      altitudeAngle: (e as PointerAngles).altitudeAngle ?? null,
+      azimuthAngle: (e as PointerAngles).azimuthAngle ?? null,
     })
   }
   static deserialize(data: unknown): MsgpackPointerEvent {
@@ -123,6 +134,8 @@ export class MsgpackPointerEvent {
       tiltX: data[12] as number,
       tiltY: data[13] as number,
       twist: data[14] as number,
+      altitudeAngle: (data[15] as number | undefined) ?? null,
+      azimuthAngle: (data[16] as number | undefined) ?? null,
     })
   }
   serialize(): unknown {
@@ -142,6 +155,8 @@ export class MsgpackPointerEvent {
       this.info.tiltX,
       this.info.tiltY,
       this.info.twist,
+      this.info.altitudeAngle,
+      this.info.azimuthAngle,
     ]
   }
 }